@@ -3,13 +3,12 @@ use std::sync::{Arc, Mutex};
 use mimalloc::MiMalloc;
 use sqlx::postgres::{PgPool, PgPoolOptions};
 use sqlx::types::Uuid;
-use tokio::sync::mpsc;
-use tokio::sync::mpsc::UnboundedSender;
 use tracing::{info, instrument};
 
 use archive_lib::core::ArchiveSubCommand;
 use gate_lib::message::gate_header::GateHeader;
-use shared_net::{op, Bufferable, NodeType, RoutedMessage, SizedBuffer, SizedBufferError, VClientMode};
+use shared_net::channel::{bounded, QueueConfig, VSender};
+use shared_net::{op, Request, RoutedMessage, Secret, SizedBuffer, SizedBufferError, Spawn, VClientConfig};
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
@@ -23,6 +22,8 @@ struct Archive {
 enum ArchiveError {
     Environment(std::env::VarError),
     Database(sqlx::Error),
+    SizedBuffer(SizedBufferError),
+    Send,
     Client(()),
 }
 
@@ -42,13 +43,15 @@ async fn main() -> Result<(), ArchiveError> {
 async fn archive_main(courtyard: String, database: &str) -> Result<(), ArchiveError> {
     info!("START");
 
+    let secret = Secret::from_env("COURTYARD_SECRET").map_err(ArchiveError::Environment)?;
+
     let context = Arc::new(Mutex::new(Archive {
         pool: PgPoolOptions::new().max_connections(16).connect(database).await.map_err(ArchiveError::Database)?,
     }));
 
-    let (dummy_tx, dummy_rx) = mpsc::unbounded_channel();
+    let (dummy_tx, dummy_rx) = bounded(QueueConfig::default());
 
-    let courtyard_client = shared_net::async_client(context, op::Flavor::Archive, dummy_tx, dummy_rx, courtyard, process_courtyard);
+    let courtyard_client = shared_net::async_client(context, op::Flavor::Archive, dummy_tx, dummy_rx, VClientConfig::from(courtyard).with_secret(secret).with_shutdown(shared_net::shutdown_on_ctrl_c()), Spawn(process_courtyard), |_, _, _| {});
 
     courtyard_client.await.map_err(ArchiveError::Client)?;
 
@@ -57,47 +60,33 @@ async fn archive_main(courtyard: String, database: &str) -> Result<(), ArchiveEr
     Ok(())
 }
 
-fn process_courtyard(context: Arc<Mutex<Archive>>, tx: UnboundedSender<RoutedMessage>, mut buf: SizedBuffer) -> VClientMode {
-    if let Ok(op::Command::Message(subcommand)) = buf.pull::<op::Command>() {
-        let _result = match subcommand.into() {
-            ArchiveSubCommand::InvGen => c_invgen(context, tx, buf),
-            ArchiveSubCommand::InvList => c_invlist(context, tx, buf),
-        };
+async fn process_courtyard(context: Arc<Mutex<Archive>>, tx: VSender<RoutedMessage>, mut buf: SizedBuffer) -> Result<(), ArchiveError> {
+    let op::Command::Request(correlation) = buf.pull::<op::Command>().map_err(ArchiveError::SizedBuffer)? else {
+        return Ok(());
+    };
+    let request = Request::read(correlation, &mut buf).map_err(ArchiveError::SizedBuffer)?;
+    match request.command {
+        op::Command::Inventory(subcommand) => match subcommand.into() {
+            ArchiveSubCommand::InvGen => c_invgen(context, tx, request, buf).await,
+            ArchiveSubCommand::InvList => c_invlist(context, tx, request, buf).await,
+        },
+        _ => Ok(()),
     }
-
-    VClientMode::Continue
 }
 
-fn c_invgen(context: Arc<Mutex<Archive>>, tx: UnboundedSender<RoutedMessage>, mut buf: SizedBuffer) -> Result<(), SizedBufferError> {
-    let gate = buf.pull::<NodeType>()?;
-    let header = buf.pull::<GateHeader>()?;
-    let _ob_type = buf.pull::<u8>()?;
+async fn c_invgen(context: Arc<Mutex<Archive>>, tx: VSender<RoutedMessage>, request: Request, mut buf: SizedBuffer) -> Result<(), ArchiveError> {
+    let header = buf.pull::<GateHeader>().map_err(ArchiveError::SizedBuffer)?;
+    let _ob_type = buf.pull::<u8>().map_err(ArchiveError::SizedBuffer)?;
 
     let pool = context.lock().unwrap().pool.clone();
 
-    let future = async move {
-        let user_uuid = Uuid::from_u128(header.user);
-        let object_uuid = Uuid::new_v4();
-        let result = sqlx::query("INSERT INTO objects(user_uuid,ob_uuid) VALUES ( $1, $2 )").bind(user_uuid).bind(object_uuid).execute(&pool).await.is_ok();
-        if result
-            && let Ok(out) = move || -> Result<SizedBuffer, SizedBufferError> {
-                let route = op::Route::One(gate);
-                let command = op::Command::Inventory(ArchiveSubCommand::InvList as op::SubCommandType);
-                let results = vec![object_uuid.as_u128()];
-
-                let mut out = SizedBuffer::new(route.size_in_buffer() + command.size_in_buffer() + header.vagabond.size_in_buffer() + results.size_in_buffer());
-                out.push(&route)?;
-                out.push(&command)?;
-                out.push(&header.vagabond)?;
-                out.push(&results)?;
-                Ok(out)
-            }()
-        {
-            let _ = tx.send(out.into());
-        }
-    };
-    tokio::spawn(future);
-    Ok(())
+    let user_uuid = Uuid::from_u128(header.user);
+    let object_uuid = Uuid::new_v4();
+    sqlx::query("INSERT INTO objects(user_uuid,ob_uuid) VALUES ( $1, $2 )").bind(user_uuid).bind(object_uuid).execute(&pool).await.map_err(ArchiveError::Database)?;
+
+    let results = SizedBuffer::from(&vec![object_uuid.as_u128()]).map_err(ArchiveError::SizedBuffer)?;
+    let out = request.reply(op::Command::Inventory(ArchiveSubCommand::InvList as op::SubCommandType), results).map_err(ArchiveError::SizedBuffer)?;
+    tx.send_async(out).await.map_err(|_| ArchiveError::Send)
 }
 
 #[derive(sqlx::FromRow)]
@@ -105,34 +94,16 @@ struct Object {
     ob_uuid: Uuid,
 }
 
-fn c_invlist(context: Arc<Mutex<Archive>>, tx: UnboundedSender<RoutedMessage>, mut buf: SizedBuffer) -> Result<(), SizedBufferError> {
-    let gate = buf.pull::<NodeType>()?;
-    let header = buf.pull::<GateHeader>()?;
-    let _ob_type = buf.pull::<u8>()?;
+async fn c_invlist(context: Arc<Mutex<Archive>>, tx: VSender<RoutedMessage>, request: Request, mut buf: SizedBuffer) -> Result<(), ArchiveError> {
+    let header = buf.pull::<GateHeader>().map_err(ArchiveError::SizedBuffer)?;
+    let _ob_type = buf.pull::<u8>().map_err(ArchiveError::SizedBuffer)?;
 
     let pool = context.lock().unwrap().pool.clone();
 
-    let future = async move {
-        let user_uuid = Uuid::from_u128(header.user);
-
-        let query_result = sqlx::query_as::<_, Object>("SELECT (ob_uuid) FROM objects WHERE user_uuid = $1").bind(user_uuid).fetch_all(&pool).await;
-        if let Ok(results) = query_result
-            && let Ok(out) = move || -> Result<SizedBuffer, SizedBufferError> {
-                let route = op::Route::One(gate);
-                let command = op::Command::Inventory(ArchiveSubCommand::InvList as op::SubCommandType);
-                let mapped_results = results.iter().map(|r| r.ob_uuid.as_u128()).collect::<Vec<_>>();
-
-                let mut out = SizedBuffer::new(route.size_in_buffer() + command.size_in_buffer() + header.vagabond.size_in_buffer() + mapped_results.size_in_buffer());
-                out.push(&op::Route::One(gate))?;
-                out.push(&op::Command::Inventory(ArchiveSubCommand::InvList as op::SubCommandType))?;
-                out.push(&header.vagabond)?;
-                out.push(&mapped_results)?;
-                Ok(out)
-            }()
-        {
-            let _ = tx.send(out.into());
-        }
-    };
-    tokio::spawn(future);
-    Ok(())
+    let user_uuid = Uuid::from_u128(header.user);
+    let objects = sqlx::query_as::<_, Object>("SELECT (ob_uuid) FROM objects WHERE user_uuid = $1").bind(user_uuid).fetch_all(&pool).await.map_err(ArchiveError::Database)?;
+
+    let results = SizedBuffer::from(&objects.iter().map(|r| r.ob_uuid.as_u128()).collect::<Vec<_>>()).map_err(ArchiveError::SizedBuffer)?;
+    let out = request.reply(op::Command::Inventory(ArchiveSubCommand::InvList as op::SubCommandType), results).map_err(ArchiveError::SizedBuffer)?;
+    tx.send_async(out).await.map_err(|_| ArchiveError::Send)
 }
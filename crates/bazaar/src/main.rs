@@ -2,11 +2,10 @@ use std::sync::{Arc, Mutex};
 
 use mimalloc::MiMalloc;
 use sqlx::postgres::{PgPool, PgPoolOptions};
-use tokio::sync::mpsc;
-use tokio::sync::mpsc::UnboundedSender;
 use tracing::{info, instrument};
 
-use shared_net::{op, RoutedMessage, SizedBuffer, VClientMode};
+use shared_net::channel::{bounded, QueueConfig, VSender};
+use shared_net::{op, RoutedMessage, Secret, SizedBuffer, VClientConfig, VClientMode};
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
@@ -39,13 +38,15 @@ async fn main() -> Result<(), BazaarError> {
 async fn bazaar_main(courtyard: String, database: &str) -> Result<(), BazaarError> {
     info!("START");
 
+    let secret = Secret::from_env("COURTYARD_SECRET").map_err(BazaarError::Environment)?;
+
     let context = Arc::new(Mutex::new(Bazaar {
         _pool: PgPoolOptions::new().max_connections(16).connect(database).await.map_err(BazaarError::Database)?,
     }));
 
-    let (dummy_tx, dummy_rx) = mpsc::unbounded_channel();
+    let (dummy_tx, dummy_rx) = bounded(QueueConfig::default());
 
-    let courtyard_client = shared_net::async_client(context, op::Flavor::Bazaar, dummy_tx, dummy_rx, courtyard, process_courtyard);
+    let courtyard_client = shared_net::async_client(context, op::Flavor::Bazaar, dummy_tx, dummy_rx, VClientConfig::from(courtyard).with_secret(secret).with_shutdown(shared_net::shutdown_on_ctrl_c()), process_courtyard, |_, _, _| {});
 
     courtyard_client.await.map_err(BazaarError::Client)?;
 
@@ -54,6 +55,6 @@ async fn bazaar_main(courtyard: String, database: &str) -> Result<(), BazaarErro
     Ok(())
 }
 
-fn process_courtyard(_context: Arc<Mutex<Bazaar>>, _tx: UnboundedSender<RoutedMessage>, _buf: SizedBuffer) -> VClientMode {
+fn process_courtyard(_context: Arc<Mutex<Bazaar>>, _tx: VSender<RoutedMessage>, _buf: SizedBuffer) -> VClientMode {
     VClientMode::Continue
 }
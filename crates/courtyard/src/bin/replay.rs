@@ -0,0 +1,136 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::BufReader;
+
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::time::{sleep_until, timeout, Duration, Instant};
+
+use archive_lib::core::ArchiveSubCommand;
+use forum_lib::core::ForumSubCommand;
+use hall_lib::core::GameSubCommand;
+use shared_net::channel::{bounded, QueueConfig, VSender};
+use shared_net::{op, NodeType, Record, RecordReader, RecordingError, RoutedMessage, Secret, SizedBuffer, VClientConfig, VClientEvent, VClientMode};
+
+const USAGE: &str = "usage: courtyard-replay decode <recording> | replay <recording> <courtyard>";
+
+const CONNECT_LIMIT: Duration = Duration::from_secs(10);
+
+#[allow(dead_code)]
+#[derive(Debug)]
+enum ReplayError {
+    Usage(&'static str),
+    Io(std::io::Error),
+    Recording(RecordingError),
+    Environment(std::env::VarError),
+    Connect,
+    Send,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), ReplayError> {
+    tracing_subscriber::fmt::init();
+
+    let mut args = std::env::args();
+    let _ = args.next(); // program name
+    match (args.next().as_deref(), args.next(), args.next()) {
+        (Some("decode"), Some(path), None) => decode(&read(&path)?),
+        (Some("replay"), Some(path), Some(courtyard)) => replay(read(&path)?, courtyard).await,
+        _ => Err(ReplayError::Usage(USAGE)),
+    }
+}
+
+fn read(path: &str) -> Result<Vec<Record>, ReplayError> {
+    let file = File::open(path).map_err(ReplayError::Io)?;
+    RecordReader::new(BufReader::new(file)).collect::<Result<Vec<_>, _>>().map_err(ReplayError::Recording)
+}
+
+fn decode(records: &[Record]) -> Result<(), ReplayError> {
+    let Some(first) = records.first() else {
+        return Ok(());
+    };
+    for record in records {
+        let header = &record.header;
+        let elapsed = Duration::from_micros(header.timestamp.saturating_sub(first.header.timestamp));
+        println!("{:>12.6} {:>5} {:<10} {:<16} {:<40} {} bytes", elapsed.as_secs_f64(), header.source, format!("{:?}", header.flavor), format!("{:?}", header.route), describe(header.command, &record.payload), record.payload.read_remain());
+    }
+    Ok(())
+}
+
+// Request and Reply payloads start with the command they carry
+fn describe(command: op::Command, payload: &SizedBuffer) -> String {
+    match command {
+        op::Command::Game(sub) => format!("Game({})", sub_command::<GameSubCommand>(sub)),
+        op::Command::Inventory(sub) => format!("Inventory({})", sub_command::<ArchiveSubCommand>(sub)),
+        op::Command::Message(sub) => format!("Message({})", sub_command::<ForumSubCommand>(sub)),
+        op::Command::Request(correlation) | op::Command::Reply(correlation) => {
+            let inner = payload.clone().pull::<op::Command>().map(|inner| describe(inner, payload)).unwrap_or_else(|_| "?".to_string());
+            let envelope = if matches!(command, op::Command::Request(_)) { "Request" } else { "Reply" };
+            format!("{envelope}({correlation}) {inner}")
+        }
+        _ => format!("{command:?}"),
+    }
+}
+
+// The sub command enums fall back to their default, so an unknown value is shown raw
+fn sub_command<T>(sub: op::SubCommandType) -> String
+where
+    T: From<op::SubCommandType> + Into<op::SubCommandType> + Copy + Debug,
+{
+    let decoded = T::from(sub);
+    if decoded.into() == sub { format!("{decoded:?}") } else { format!("#{sub}") }
+}
+
+// Connects one client per recorded source with its recorded flavor, then resends each frame on the recorded schedule.
+// One(id) routes keep the ids from the recording, so they only reach the same node when connection order matches.
+async fn replay(records: Vec<Record>, courtyard: String) -> Result<(), ReplayError> {
+    let secret = Secret::from_env("COURTYARD_SECRET").map_err(ReplayError::Environment)?;
+    let sources = records.iter().map(|record| (record.header.source, record.header.flavor)).collect::<BTreeMap<NodeType, op::Flavor>>();
+
+    let shutdown = shared_net::shutdown_on_ctrl_c();
+    let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+    let mut senders = BTreeMap::new();
+    let mut clients = Vec::new();
+    for (source, flavor) in sources {
+        let (tx, rx) = bounded(QueueConfig::default());
+        let config = VClientConfig::new(courtyard.clone()).with_secret(secret.clone()).with_shutdown(shutdown.clone());
+        clients.push(tokio::spawn(shared_net::async_client(events_tx.clone(), flavor, tx.clone(), rx, config, |_, _, _| VClientMode::Continue, connected)));
+        senders.insert(source, tx);
+    }
+
+    for _ in 0..senders.len() {
+        match timeout(CONNECT_LIMIT, events_rx.recv()).await {
+            Ok(Some(VClientEvent::Connected)) => {}
+            _ => {
+                shutdown.cancel();
+                return Err(ReplayError::Connect);
+            }
+        }
+    }
+
+    let result = resend(&records, &senders).await;
+    shutdown.cancel();
+    for client in clients {
+        let _ = client.await;
+    }
+    result
+}
+
+async fn resend(records: &[Record], senders: &BTreeMap<NodeType, VSender<RoutedMessage>>) -> Result<(), ReplayError> {
+    let Some(first) = records.first() else {
+        return Ok(());
+    };
+    let start = Instant::now();
+    for record in records {
+        sleep_until(start + Duration::from_micros(record.header.timestamp.saturating_sub(first.header.timestamp))).await;
+        let (Some(tx), Ok(frame)) = (senders.get(&record.header.source), record.frame()) else {
+            return Err(ReplayError::Send);
+        };
+        tx.send_async(RoutedMessage::new(record.header.route.clone(), frame)).await.map_err(|_| ReplayError::Send)?;
+    }
+    Ok(())
+}
+
+fn connected(context: UnboundedSender<VClientEvent>, _tx: VSender<RoutedMessage>, event: VClientEvent) {
+    let _ = context.send(event);
+}
@@ -0,0 +1,73 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::time::{timeout, Duration};
+
+use shared_net::channel::{bounded, QueueConfig, VSender};
+use shared_net::{op, CancellationToken, RoutedMessage, Secret, SizedBuffer, SizedBufferError, TimestampType, VClientConfig, VClientEvent, VClientMode};
+
+const USAGE: &str = "usage: courtyard-status <courtyard>";
+
+const STATUS_LIMIT: Duration = Duration::from_secs(10);
+
+#[allow(dead_code)]
+#[derive(Debug)]
+enum StatusError {
+    Usage(&'static str),
+    Environment(std::env::VarError),
+    Timeout,
+    Buffer(SizedBufferError),
+}
+
+#[tokio::main]
+async fn main() -> Result<(), StatusError> {
+    tracing_subscriber::fmt::init();
+
+    let mut args = std::env::args();
+    let _ = args.next(); // program name
+    let (Some(courtyard), None) = (args.next(), args.next()) else {
+        return Err(StatusError::Usage(USAGE));
+    };
+
+    // the same secret Courtyard expects of a Steward
+    let secret = Secret::from_env("COURTYARD_SECRET_STEWARD").or_else(|_| Secret::from_env("COURTYARD_SECRET")).map_err(StatusError::Environment)?;
+
+    let shutdown = CancellationToken::new();
+    let (status_tx, mut status_rx) = mpsc::unbounded_channel();
+    let (tx, rx) = bounded(QueueConfig::default());
+    let config = VClientConfig::new(courtyard).with_secret(secret).with_shutdown(shutdown.clone());
+    let client = tokio::spawn(shared_net::async_client(status_tx, op::Flavor::Steward, tx, rx, config, received, ask));
+
+    let status = timeout(STATUS_LIMIT, status_rx.recv()).await;
+    shutdown.cancel();
+    let _ = client.await;
+
+    let mut status = status.ok().flatten().ok_or(StatusError::Timeout)?;
+    print(&status.pull::<Vec<op::ConnectionStatus>>().map_err(StatusError::Buffer)?);
+    Ok(())
+}
+
+// Asks once registered, and again after a reconnect
+fn ask(_context: UnboundedSender<SizedBuffer>, tx: VSender<RoutedMessage>, event: VClientEvent) {
+    if event == VClientEvent::Connected
+        && let Ok(buf) = SizedBuffer::from(&op::Command::Status)
+    {
+        let _ = tx.send(RoutedMessage::local(buf));
+    }
+}
+
+fn received(context: UnboundedSender<SizedBuffer>, _tx: VSender<RoutedMessage>, mut buf: SizedBuffer) -> VClientMode {
+    if let Ok(op::Command::Status) = buf.pull::<op::Command>() {
+        let _ = context.send(buf);
+    }
+    VClientMode::Continue
+}
+
+fn print(statuses: &[op::ConnectionStatus]) {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_micros() as TimestampType).unwrap_or_default();
+    println!("{:>5} {:<10} {:>12} {:>10} {:>12} {:>10} {:>12}", "id", "flavor", "connected", "in", "in bytes", "out", "out bytes");
+    for status in statuses {
+        let connected = Duration::from_micros(now.saturating_sub(status.connected));
+        println!("{:>5} {:<10} {:>11}s {:>10} {:>12} {:>10} {:>12}", status.id, format!("{:?}", status.flavor), connected.as_secs(), status.received.messages, status.received.bytes, status.sent.messages, status.sent.bytes);
+    }
+}
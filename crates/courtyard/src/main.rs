@@ -1,21 +1,39 @@
+mod mesh;
+
 use mimalloc::MiMalloc;
-use tokio::sync::mpsc;
-use tokio::sync::mpsc::UnboundedSender;
+use tokio::time::Duration;
 use tracing::{error, info, instrument};
 
-use shared_net::{op, Bufferable, IdMessage, RoutedMessage, SizedBuffer};
+use shared_net::channel::{bounded, QueueConfig, VSender};
+use shared_net::{op, AnyStrategy, Directory, IdMessage, InFlight, NodeType, Recorder, Registration, RoutedMessage, Secret, VClientConfig, VServerConfig};
+
+use crate::mesh::{Link, Mesh, MeshIndex, MeshSize};
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
 
+// Every routed frame is also written to COURTYARD_RECORD when it is set
 #[derive(Clone)]
-struct NoContext;
+struct Courtyard {
+    recorder: Option<Recorder>,
+    mesh: Mesh,
+}
 
+#[allow(dead_code)]
 #[derive(Debug)]
 enum CourtyardError {
+    Environment(std::env::VarError),
+    Index(String),
+    MeshSize(String),
+    Recording(std::io::Error),
     Server(()),
 }
 
+// A little past the longest any service waits on a reply, after that an unanswered request stops counting against its connection
+const REQUEST_EXPIRY: Duration = Duration::from_secs(10);
+
+const SERVICES: [op::Flavor; 10] = [op::Flavor::Archive, op::Flavor::Bazaar, op::Flavor::Courtyard, op::Flavor::Drawbridge, op::Flavor::Forum, op::Flavor::Gate, op::Flavor::Hall, op::Flavor::Jail, op::Flavor::Lookout, op::Flavor::Steward];
+
 #[tokio::main]
 async fn main() -> Result<(), CourtyardError> {
     tracing_subscriber::fmt::init();
@@ -31,35 +49,203 @@ async fn main() -> Result<(), CourtyardError> {
 async fn courtyard_main(interface: String) -> Result<(), CourtyardError> {
     info!("START");
 
-    let (dummy_tx, dummy_rx) = mpsc::unbounded_channel();
+    let registration = registration().map_err(CourtyardError::Environment)?;
+    let secret = secret(op::Flavor::Courtyard).map_err(CourtyardError::Environment)?;
+
+    // COURTYARD_MESH_SIZE Courtyards split the ids between them and must all agree on it, a lone Courtyard keeps all of them.
+    // COURTYARD_INDEX tells Courtyards in one mesh apart, each one dials the COURTYARD_PEERS it is given.
+    let size = match std::env::var("COURTYARD_MESH_SIZE") {
+        Ok(size) => size.parse::<MeshSize>().ok().filter(|size| (1..=mesh::MAX_SIZE).contains(size)).ok_or(CourtyardError::MeshSize(size))?,
+        Err(_) => 1,
+    };
+    let index = match std::env::var("COURTYARD_INDEX") {
+        Ok(index) => index.parse::<MeshIndex>().ok().filter(|index| MeshSize::from(*index) < size).ok_or(CourtyardError::Index(index))?,
+        Err(_) => 0,
+    };
+    let peers = std::env::var("COURTYARD_PEERS").map(|peers| peers.split_whitespace().map(str::to_string).collect()).unwrap_or_default();
+
+    let (recorder, writer) = match std::env::var("COURTYARD_RECORD") {
+        Ok(path) => {
+            info!(path, "Recording");
+            Recorder::create(path).map(|(recorder, writer)| (Some(recorder), Some(writer))).map_err(CourtyardError::Recording)?
+        }
+        Err(_) => (None, None),
+    };
+
+    let config = VServerConfig::new(interface).with_registration(registration).with_any_strategy(AnyStrategy::LeastOutstanding).with_shutdown(shared_net::shutdown_on_ctrl_c());
+    run(config, index, size, peers, secret, recorder).await.map_err(CourtyardError::Server)?;
 
-    shared_net::async_server(NoContext, dummy_tx, dummy_rx, interface, process).await.map_err(CourtyardError::Server)?;
+    // the server has dropped its recorder, so the writer finishes what is queued and stops
+    if let Some(writer) = writer {
+        match writer.join() {
+            Ok(result) => result.map_err(CourtyardError::Recording)?,
+            Err(_) => error!("Recorder panicked"),
+        }
+    }
 
     info!("END");
 
     Ok(())
 }
 
-fn process(_context: NoContext, tx: UnboundedSender<RoutedMessage>, msg: IdMessage) -> bool {
-    let mut in_buf = msg.buf;
-    if let Ok(route) = in_buf.pull::<op::Route>()
-        && let Ok(command) = in_buf.pull::<op::Command>()
+async fn run(config: VServerConfig, index: MeshIndex, size: MeshSize, peers: Vec<String>, secret: Secret, recorder: Option<Recorder>) -> Result<(), ()> {
+    let directory = Directory::default();
+    let in_flight = InFlight::new(REQUEST_EXPIRY);
+    let (server_tx, server_rx) = bounded(QueueConfig::default());
+    let (mesh, dialed) = Mesh::new(index, size, directory.clone(), in_flight.clone(), server_tx.clone(), peers.len());
+
+    let mut clients = Vec::new();
+    for (peer, (context, rx)) in peers.into_iter().zip(dialed) {
+        info!(peer, "Dialing");
+        let client_config = VClientConfig::new(peer).with_secret(secret.clone()).with_shutdown(config.shutdown.clone());
+        clients.push(tokio::spawn(shared_net::async_client(context, op::Flavor::Courtyard, server_tx.clone(), rx, client_config, mesh::process_peer, mesh::peer_event)));
+    }
+    tokio::spawn(mesh.clone().advertise(config.shutdown.clone()));
+
+    let config = config.with_directory(directory).with_in_flight(in_flight).with_node_ids(mesh.node_ids());
+    let result = shared_net::async_server(Courtyard { recorder, mesh }, server_tx, server_rx, config, process, disconnect).await;
+
+    for client in clients {
+        let _ = client.await;
+    }
+    result
+}
+
+// Only services, peer Courtyards and Stewards asking for status may register, each proving COURTYARD_SECRET or its own COURTYARD_SECRET_<FLAVOR>
+fn registration() -> Result<Registration, std::env::VarError> {
+    SERVICES.into_iter().try_fold(Registration::default(), |registration, flavor| Ok(registration.with_secret(flavor, secret(flavor)?)))
+}
+
+fn secret(flavor: op::Flavor) -> Result<Secret, std::env::VarError> {
+    Secret::from_env(&format!("COURTYARD_SECRET_{}", format!("{flavor:?}").to_uppercase())).or_else(|_| Secret::from_env("COURTYARD_SECRET"))
+}
+
+fn process(context: Courtyard, tx: VSender<RoutedMessage>, msg: IdMessage) -> bool {
+    // peers speak the mesh protocol rather than [Route][Command]
+    if msg.flavor == op::Flavor::Courtyard {
+        return context.mesh.receive(Link::Server(msg.id), msg.buf);
+    }
+
+    let mut buf = msg.buf;
+    if let Ok(route) = buf.pull::<op::Route>()
+        && let Ok(command) = buf.pull::<op::Command>()
     {
-        let mut out_buf = SizedBuffer::new(command.size_in_buffer() + msg.id.size_in_buffer() + in_buf.read_remain());
-        let success = out_buf.push(&command).and_then(|_| out_buf.push(&msg.id)).and_then(|_| out_buf.xfer_bytes(&mut in_buf)).is_ok();
+        if let Some(recorder) = &context.recorder
+            && let Err(err) = recorder.record(msg.id, msg.flavor, &route, command, &buf)
+        {
+            error!(msg.id, ?err, "Recording failed");
+        }
+
+        // [Route][Command] becomes [Command][sender] in place, the payload stays where it is
+        let success = buf.prepend(&msg.id).and_then(|_| buf.prepend(&command)).is_ok();
 
         if success {
-            info!(msg.id, ?command, ?route, bytes = out_buf.size());
+            info!(msg.id, ?command, ?route, bytes = buf.size());
         } else {
-            error!(msg.id, ?command, ?route, bytes = out_buf.size());
+            error!(msg.id, ?command, ?route, bytes = buf.size());
         }
 
-        let message = RoutedMessage {
-            route,
-            buf: out_buf,
-        };
-        return tx.send(message).is_ok();
+        // the sender hears back when nothing is connected to take it, and Game frames go out ahead of chat
+        let message = RoutedMessage::new(route, buf).with_origin(msg.id).with_priority(command.into());
+        return context.mesh.route(&tx, message);
     }
 
     false
 }
+
+fn disconnect(context: Courtyard, _tx: VSender<RoutedMessage>, id: NodeType) {
+    context.mesh.forget(&Link::Server(id));
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::sync::mpsc::{self, UnboundedSender};
+    use tokio::time::{sleep, timeout, Duration};
+
+    use shared_net::channel::{bounded, QueueConfig, VSender};
+    use shared_net::{async_client, op, CancellationToken, NodeType, Request, RequestError, Requester, RoutedMessage, Secret, SizedBuffer, VClientConfig, VClientMode, VServerConfig};
+
+    use super::run;
+
+    // Answers every request with twice the number it carried, and tells the test who asked
+    fn double(context: UnboundedSender<NodeType>, tx: VSender<RoutedMessage>, mut buf: SizedBuffer) -> VClientMode {
+        if let Ok(op::Command::Request(correlation)) = buf.pull::<op::Command>()
+            && let Ok(request) = Request::read(correlation, &mut buf)
+            && let Ok(value) = buf.pull::<u64>()
+            && let Ok(reply) = request.reply(request.command, SizedBuffer::from(&(value * 2)).unwrap())
+        {
+            let _ = context.send(request.sender);
+            let _ = tx.send(reply);
+        }
+        VClientMode::Continue
+    }
+
+    fn received(context: UnboundedSender<SizedBuffer>, _tx: VSender<RoutedMessage>, buf: SizedBuffer) -> VClientMode {
+        let _ = context.send(buf);
+        VClientMode::Continue
+    }
+
+    #[tokio::test]
+    async fn test_mesh() {
+        let shutdown = CancellationToken::new();
+        let secret = Secret::new(b"mesh-test");
+        let courtyard = |interface: &str| VServerConfig::new(interface.to_string()).with_shutdown(shutdown.clone());
+        let a = tokio::spawn(run(courtyard("mem:mesh-test-a"), 0, 2, Vec::new(), secret.clone(), None));
+        let b = tokio::spawn(run(courtyard("mem:mesh-test-b"), 1, 2, vec!["mem:mesh-test-a".to_string()], secret, None));
+
+        // Hall only registers with B
+        let (senders_tx, mut senders_rx) = mpsc::unbounded_channel();
+        let (hall_tx, hall_rx) = bounded(QueueConfig::default());
+        tokio::spawn(async_client(senders_tx, op::Flavor::Hall, hall_tx.clone(), hall_rx, VClientConfig::new("mem:mesh-test-b".to_string()).with_shutdown(shutdown.clone()), double, |_, _, _| {}));
+
+        let (received_tx, mut received_rx) = mpsc::unbounded_channel();
+        let (gate_tx, gate_rx) = bounded(QueueConfig::default());
+        let requester = Requester::new(gate_tx.clone());
+        let config = VClientConfig::new("mem:mesh-test-a".to_string()).with_requester(requester.clone()).with_shutdown(shutdown.clone());
+        tokio::spawn(async_client(received_tx, op::Flavor::Gate, gate_tx, gate_rx, config, received, |_, _, _| {}));
+
+        // A turns the request away until B has advertised Hall to it
+        let mut attempts = 0;
+        let mut reply = loop {
+            match requester.request(op::Route::Any(op::Flavor::Hall), op::Command::Game(1), SizedBuffer::from(&21_u64).unwrap(), Duration::from_secs(10)).await {
+                Err(RequestError::Undeliverable(op::UndeliverableReason::Unavailable)) if attempts < 100 => {
+                    attempts += 1;
+                    sleep(Duration::from_millis(50)).await;
+                }
+                result => break result.unwrap(),
+            }
+        };
+        assert_eq!(reply.pull::<op::Command>().unwrap(), op::Command::Game(1));
+        assert_eq!(reply.pull::<u64>().unwrap(), 42);
+
+        // B bounces a request for an id in its block that nobody holds, and the notice finds its way back
+        let result = requester.request(op::Route::One(32768 + 999), op::Command::Game(1), SizedBuffer::from(&21_u64).unwrap(), Duration::from_secs(60)).await;
+        assert!(matches!(result, Err(RequestError::Undeliverable(op::UndeliverableReason::Disconnected))));
+
+        // nobody in the mesh serves Lookout
+        let result = requester.request(op::Route::Any(op::Flavor::Lookout), op::Command::Authorize, SizedBuffer::new(0), Duration::from_secs(60)).await;
+        assert!(matches!(result, Err(RequestError::Undeliverable(op::UndeliverableReason::Unavailable))));
+
+        // a multicast from B reaches the Gate on A as a single copy
+        let gate = timeout(Duration::from_secs(10), senders_rx.recv()).await.unwrap().unwrap();
+        let targets = vec![op::Target { gate, vagabond: 5 }, op::Target { gate, vagabond: 6 }];
+        let mut out = SizedBuffer::new(64);
+        out.push(&op::Route::Multi(targets)).and_then(|_| out.push(&op::Command::Game(2))).and_then(|_| out.push(&9_u64)).unwrap();
+        hall_tx.send(RoutedMessage::local(out)).unwrap();
+        // the Gate also saw its Hello
+        let mut buf = loop {
+            let mut buf = timeout(Duration::from_secs(10), received_rx.recv()).await.unwrap().unwrap();
+            if buf.pull::<op::Command>().unwrap() == op::Command::Multicast {
+                break buf;
+            }
+        };
+        assert_eq!(buf.pull::<Vec<NodeType>>().unwrap(), vec![5, 6]);
+        assert_eq!(buf.pull::<op::Command>().unwrap(), op::Command::Game(2));
+        let _ = buf.pull::<NodeType>().unwrap(); // hall
+        assert_eq!(buf.pull::<u64>().unwrap(), 9);
+
+        shutdown.cancel();
+        assert!(a.await.unwrap().is_ok());
+        assert!(b.await.unwrap().is_ok());
+    }
+}
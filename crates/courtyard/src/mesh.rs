@@ -0,0 +1,367 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use tokio::time::{interval, Duration};
+use tracing::{error, info, warn};
+
+use shared_net::channel::{bounded, QueueConfig, VReceiver, VSender};
+use shared_net::{op, Bufferable, CancellationToken, Directory, InFlight, NodeType, RoutedMessage, SizedBuffer, SizedBufferError, VClientEvent, VClientMode};
+
+pub(crate) type MeshIndex = u8;
+pub(crate) type MeshSize = u16;
+
+// The id range is split into one block per Courtyard, so the block an id falls in names the Courtyard holding it.
+// A Courtyard on its own takes every id, one of `size` holds at most 65536 / size connections, less one for block 0.
+pub(crate) const MAX_SIZE: MeshSize = MeshIndex::MAX as MeshSize + 1;
+const IDS: u32 = NodeType::MAX as u32 + 1;
+
+// Advertisements also go out on this beat, so a peer that lost a link learns the other one again
+const ADVERTISE_INTERVAL: Duration = Duration::from_secs(5);
+
+// [Advertise][Advertisement]: the flavors registered with the sending Courtyard itself
+#[derive(Bufferable)]
+struct Advertisement {
+    index: MeshIndex,
+    flavors: Vec<op::Flavor>,
+}
+
+// Peers are reached over the link they last advertised on, either a connection they made to us or one we made to them
+#[derive(Clone)]
+pub(crate) enum Link {
+    Server(NodeType),
+    Client(usize),
+}
+
+impl Link {
+    fn same(&self, other: &Link) -> bool {
+        match (self, other) {
+            (Link::Server(a), Link::Server(b)) => a == b,
+            (Link::Client(a), Link::Client(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+struct Peer {
+    link: Link,
+    flavors: Vec<op::Flavor>,
+}
+
+struct Dialed {
+    tx: VSender<RoutedMessage>,
+    connected: AtomicBool,
+}
+
+// Frames only ever take one hop between Courtyards, so every Courtyard has to peer with every other one
+#[derive(Clone)]
+pub(crate) struct Mesh {
+    index: MeshIndex,
+    block: u32,
+    directory: Directory,
+    in_flight: InFlight,
+    server_tx: VSender<RoutedMessage>,
+    dialed: Arc<Vec<Dialed>>,
+    peers: Arc<Mutex<HashMap<MeshIndex, Peer>>>,
+}
+
+// The context of a connection this Courtyard made to a peer
+#[derive(Clone)]
+pub(crate) struct PeerClient {
+    mesh: Mesh,
+    link: usize,
+}
+
+impl Mesh {
+    pub(crate) fn new(index: MeshIndex, size: MeshSize, directory: Directory, in_flight: InFlight, server_tx: VSender<RoutedMessage>, dial: usize) -> (Self, Vec<(PeerClient, VReceiver<RoutedMessage>)>) {
+        let (dialed, receivers): (Vec<_>, Vec<_>) = (0..dial)
+            .map(|_| {
+                let (tx, rx) = bounded(QueueConfig::default());
+                (
+                    Dialed {
+                        tx,
+                        connected: AtomicBool::new(false),
+                    },
+                    rx,
+                )
+            })
+            .unzip();
+        let mesh = Self {
+            index,
+            block: IDS / u32::from(size.clamp(1, MAX_SIZE)),
+            directory,
+            in_flight,
+            server_tx,
+            dialed: Arc::new(dialed),
+            peers: Arc::new(Mutex::new(HashMap::new())),
+        };
+        let clients = receivers
+            .into_iter()
+            .enumerate()
+            .map(|(link, rx)| {
+                (
+                    PeerClient {
+                        mesh: mesh.clone(),
+                        link,
+                    },
+                    rx,
+                )
+            })
+            .collect();
+        (mesh, clients)
+    }
+
+    pub(crate) fn node_ids(&self) -> RangeInclusive<NodeType> {
+        let first = u32::from(self.index) * self.block;
+        let last = first + (self.block - 1);
+        (first.max(1) as NodeType)..=(last as NodeType)
+    }
+
+    // Expects a frame from one of our own connections, already turned into [Command][sender][payload]
+    pub(crate) fn route(&self, tx: &VSender<RoutedMessage>, message: RoutedMessage) -> bool {
+        if let op::Route::Multi(targets) = &message.route {
+            return self.multicast(tx, targets.clone(), message);
+        }
+        let link = match message.route {
+            op::Route::One(id) if self.owner(id) != self.index => self.find(|index, _| *index == self.owner(id)),
+            op::Route::Any(flavor) if !self.directory.offers(flavor) => self.find(|_, peer| peer.flavors.contains(&flavor)),
+            op::Route::All(flavor) => {
+                for link in self.offering(flavor) {
+                    self.forward(&link, &message);
+                }
+                None
+            }
+            _ => None,
+        };
+        // what no peer takes is left to the server, which tells the origin when it can't deliver either
+        match link {
+            Some(link) if self.forward(&link, &message) => {
+                // a reply that leaves this way never reaches the server, which would keep counting it against its sender
+                self.in_flight.answered(&message);
+                true
+            }
+            _ => tx.send(message).is_ok(),
+        }
+    }
+
+    // Targets behind other Courtyards go on to them as a Multi route of their own
+    fn multicast(&self, tx: &VSender<RoutedMessage>, targets: Vec<op::Target>, message: RoutedMessage) -> bool {
+        let mut local = Vec::new();
+        let mut remote = BTreeMap::<MeshIndex, Vec<op::Target>>::new();
+        for target in targets {
+            match self.owner(target.gate) {
+                index if index == self.index => local.push(target),
+                index => remote.entry(index).or_default().push(target),
+            }
+        }
+
+        for (index, targets) in remote {
+            let forwarded = RoutedMessage {
+                route: op::Route::Multi(targets.clone()),
+                ..message.clone()
+            };
+            // the server tells the origin about targets no peer takes
+            if !self.find(|peer, _| *peer == index).is_some_and(|link| self.forward(&link, &forwarded)) {
+                local.extend(targets);
+            }
+        }
+
+        if local.is_empty() {
+            return true;
+        }
+        let message = RoutedMessage {
+            route: op::Route::Multi(local),
+            ..message
+        };
+        tx.send(message).is_ok()
+    }
+
+    // Handles whatever a peer sends over either kind of link, false drops a server link
+    pub(crate) fn receive(&self, link: Link, mut buf: SizedBuffer) -> bool {
+        match buf.pull::<op::Command>() {
+            Ok(op::Command::Advertise) => match buf.pull::<Advertisement>() {
+                Ok(advertisement) if advertisement.index != self.index => {
+                    info!(index = advertisement.index, flavors = ?advertisement.flavors, "Peer");
+                    self.lock().insert(
+                        advertisement.index,
+                        Peer {
+                            link,
+                            flavors: advertisement.flavors,
+                        },
+                    );
+                    true
+                }
+                Ok(advertisement) => {
+                    error!(index = advertisement.index, "Peer shares our mesh index");
+                    false
+                }
+                Err(_) => false,
+            },
+            Ok(op::Command::Forward) => self.deliver(link, buf),
+            Ok(op::Command::Undeliverable) => self.relay(buf),
+            _ => true,
+        }
+    }
+
+    pub(crate) fn forget(&self, link: &Link) {
+        self.lock().retain(|index, peer| {
+            let keep = !peer.link.same(link);
+            if !keep {
+                info!(index, "Peer lost");
+            }
+            keep
+        });
+    }
+
+    pub(crate) async fn advertise(self, shutdown: CancellationToken) {
+        let mut changes = self.directory.watch();
+        let mut refresh = interval(ADVERTISE_INTERVAL);
+        loop {
+            tokio::select! {
+                changed = changes.changed() => if changed.is_err() { break },
+                _ = refresh.tick() => {}
+                _ = shutdown.cancelled() => break,
+            }
+            let Ok(advertisement) = self.advertisement() else {
+                continue;
+            };
+            for (link, dialed) in self.dialed.iter().enumerate() {
+                if dialed.connected.load(Ordering::Relaxed) {
+                    self.send(&Link::Client(link), op::Priority::Normal, advertisement.clone());
+                }
+            }
+            let _ = self.server_tx.send(RoutedMessage::new(op::Route::All(op::Flavor::Courtyard), advertisement));
+        }
+    }
+
+    fn advertisement(&self) -> Result<SizedBuffer, SizedBufferError> {
+        let advertisement = Advertisement {
+            index: self.index,
+            flavors: self.directory.flavors().into_iter().filter(|flavor| *flavor != op::Flavor::Courtyard).collect(),
+        };
+        let mut buf = SizedBuffer::new(op::Command::Advertise.size_in_buffer() + advertisement.size_in_buffer());
+        buf.push(&op::Command::Advertise)?;
+        buf.push(&advertisement)?;
+        Ok(buf)
+    }
+
+    // [Forward][Route][Command][sender][payload]
+    fn forward(&self, link: &Link, message: &RoutedMessage) -> bool {
+        let mut buf = message.buf.clone();
+        buf.prepend(&message.route).and_then(|_| buf.prepend(&op::Command::Forward)).is_ok() && self.send(link, message.priority, buf)
+    }
+
+    // A forwarded frame only goes to our own connections, one that can't be delivered goes back the way it came
+    fn deliver(&self, link: Link, mut buf: SizedBuffer) -> bool {
+        let Ok(route) = buf.pull::<op::Route>() else {
+            return false;
+        };
+        buf.discard_read();
+        let Ok(command) = buf.pull::<op::Command>() else {
+            return false;
+        };
+        let reason = match route {
+            op::Route::One(id) if self.directory.flavor(id).is_none() => Some(op::UndeliverableReason::Disconnected),
+            op::Route::Any(flavor) if !self.directory.offers(flavor) => Some(op::UndeliverableReason::Unavailable),
+            _ => None,
+        };
+        let Some(reason) = reason else {
+            buf.rewind();
+            return self.server_tx.send(RoutedMessage::new(route, buf).with_priority(command.into())).is_ok();
+        };
+
+        warn!(?route, ?command, %reason, "Undeliverable");
+        let notice = op::Undeliverable {
+            route,
+            command,
+            reason,
+        };
+        buf.prepend(&notice).and_then(|_| buf.prepend(&op::Command::Undeliverable)).is_ok() && self.send(&link, op::Priority::Normal, buf)
+    }
+
+    // [Undeliverable][Undeliverable][sender][payload] goes on to the sender, who is one of ours
+    fn relay(&self, mut buf: SizedBuffer) -> bool {
+        let mut notice = buf.clone();
+        let Ok(sender) = notice.pull::<op::Undeliverable>().and_then(|_| notice.pull::<NodeType>()) else {
+            return false;
+        };
+        buf.rewind();
+        self.server_tx.send(RoutedMessage::new(op::Route::One(sender), buf)).is_ok()
+    }
+
+    fn send(&self, link: &Link, priority: op::Priority, buf: SizedBuffer) -> bool {
+        match link {
+            Link::Server(id) => self.server_tx.send(RoutedMessage::new(op::Route::One(*id), buf).with_priority(priority)).is_ok(),
+            Link::Client(link) => self.dialed.get(*link).is_some_and(|dialed| dialed.tx.send(RoutedMessage::local(buf)).is_ok()),
+        }
+    }
+
+    fn find(&self, matches: impl Fn(&MeshIndex, &Peer) -> bool) -> Option<Link> {
+        self.lock().iter().filter(|(index, peer)| matches(index, peer)).min_by_key(|(index, _)| **index).map(|(_, peer)| peer.link.clone())
+    }
+
+    fn offering(&self, flavor: op::Flavor) -> Vec<Link> {
+        self.lock().values().filter(|peer| peer.flavors.contains(&flavor)).map(|peer| peer.link.clone()).collect()
+    }
+
+    fn owner(&self, id: NodeType) -> MeshIndex {
+        (u32::from(id) / self.block) as MeshIndex
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<MeshIndex, Peer>> {
+        self.peers.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+
+pub(crate) fn process_peer(context: PeerClient, _tx: VSender<RoutedMessage>, buf: SizedBuffer) -> VClientMode {
+    context.mesh.receive(Link::Client(context.link), buf);
+    VClientMode::Continue
+}
+
+pub(crate) fn peer_event(context: PeerClient, _tx: VSender<RoutedMessage>, event: VClientEvent) {
+    let mesh = &context.mesh;
+    let Some(dialed) = mesh.dialed.get(context.link) else {
+        return;
+    };
+    let link = Link::Client(context.link);
+    match event {
+        VClientEvent::Connected => {
+            dialed.connected.store(true, Ordering::Relaxed);
+            if let Ok(advertisement) = mesh.advertisement() {
+                mesh.send(&link, op::Priority::Normal, advertisement);
+            }
+        }
+        VClientEvent::Disconnected => {
+            dialed.connected.store(false, Ordering::Relaxed);
+            mesh.forget(&link);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use shared_net::channel::{bounded, QueueConfig};
+    use shared_net::{Directory, InFlight, NodeType};
+
+    use super::{Mesh, MeshIndex, MeshSize};
+
+    fn mesh(index: MeshIndex, size: MeshSize) -> Mesh {
+        let (server_tx, _server_rx) = bounded(QueueConfig::default());
+        Mesh::new(index, size, Directory::default(), InFlight::default(), server_tx, 0).0
+    }
+
+    #[test]
+    fn test_node_ids() {
+        // a lone Courtyard keeps the whole range
+        assert_eq!(mesh(0, 1).node_ids(), 1..=NodeType::MAX);
+
+        assert_eq!(mesh(0, 2).node_ids(), 1..=32767);
+        let last = mesh(1, 2);
+        assert_eq!(last.node_ids(), 32768..=NodeType::MAX);
+        assert_eq!((last.owner(32767), last.owner(32768), last.owner(NodeType::MAX)), (0, 1, 1));
+
+        assert_eq!(mesh(255, 256).node_ids(), 65280..=NodeType::MAX);
+    }
+}
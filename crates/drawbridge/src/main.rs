@@ -1,10 +1,12 @@
+use std::path::Path;
+use std::sync::Arc;
+
 use mimalloc::MiMalloc;
-use tokio::signal;
-use tokio::sync::mpsc;
-use tokio::sync::mpsc::UnboundedSender;
 use tracing::{info, instrument};
 
-use shared_net::{op, IdMessage, NodeType, RoutedMessage, SizedBuffer, VClientMode};
+use shared_net::channel::{bounded, QueueConfig, VSender};
+use shared_net::tls::{self, TlsError};
+use shared_net::{op, IdMessage, NodeType, Registration, RoutedMessage, Secret, SizedBuffer, VClientConfig, VClientMode, VServerConfig};
 
 use crate::DrawbridgeError::{Client, Server};
 
@@ -17,9 +19,10 @@ struct NoContext;
 #[allow(dead_code)]
 #[derive(Debug)]
 enum DrawbridgeError {
-    Interrupt,
+    Environment(std::env::VarError),
     Client(()),
     Server(()),
+    Tls(TlsError),
 }
 
 #[tokio::main]
@@ -30,30 +33,42 @@ async fn main() -> Result<(), DrawbridgeError> {
     let _ = args.next(); // program name
     let iface_to_courtyard = args.next().unwrap_or("[::1]:12345".to_string());
     let iface_to_vagabond = args.next().unwrap_or("[::]:23450".to_string());
+    let tls = match (args.next(), args.next()) {
+        (Some(cert), Some(key)) => Some(tls::load_server_config(Path::new(&cert), Path::new(&key)).map_err(DrawbridgeError::Tls)?),
+        _ => None,
+    };
 
-    drawbridge_main(iface_to_vagabond, iface_to_courtyard).await
+    drawbridge_main(iface_to_vagabond, iface_to_courtyard, tls).await
 }
 
-#[instrument]
-async fn drawbridge_main(interface: String, courtyard: String) -> Result<(), DrawbridgeError> {
+#[instrument(skip(tls))]
+async fn drawbridge_main(interface: String, courtyard: String, tls: Option<Arc<tls::ServerConfig>>) -> Result<(), DrawbridgeError> {
     info!("START");
 
-    let (d2c_tx, d2c_rx) = mpsc::unbounded_channel();
-    let (d2v_tx, d2v_rx) = mpsc::unbounded_channel();
-    let drawbridge = shared_net::async_server(NoContext, d2v_tx, d2c_rx, interface, process_drawbridge);
-    let courtyard_client = shared_net::async_client(NoContext, op::Flavor::Drawbridge, d2c_tx, d2v_rx, courtyard, process_courtyard);
+    let secret = Secret::from_env("COURTYARD_SECRET").map_err(DrawbridgeError::Environment)?;
+
+    let (d2c_tx, d2c_rx) = bounded(QueueConfig::default());
+    let (d2v_tx, d2v_rx) = bounded(QueueConfig::default());
+    let shutdown = shared_net::shutdown_on_ctrl_c();
+    let mut server_config = VServerConfig::new(interface).with_registration(Registration::default().allow(op::Flavor::Vagabond)).with_shutdown(shutdown.clone());
+    if let Some(tls) = tls {
+        server_config = server_config.with_tls(tls);
+    }
+    let drawbridge = shared_net::async_server(NoContext, d2v_tx, d2c_rx, server_config, process_drawbridge, |_, _, _| {});
+    let courtyard_client = shared_net::async_client(NoContext, op::Flavor::Drawbridge, d2c_tx, d2v_rx, VClientConfig::from(courtyard).with_secret(secret).with_shutdown(shutdown.clone()), process_courtyard, |_, _, _| {});
 
-    tokio::spawn(drawbridge);
-    tokio::spawn(courtyard_client);
+    let drawbridge = tokio::spawn(drawbridge);
+    let courtyard_client = tokio::spawn(courtyard_client);
 
-    signal::ctrl_c().await.map_err(|_| DrawbridgeError::Interrupt)?;
+    shutdown.cancelled().await;
+    let _ = tokio::join!(drawbridge, courtyard_client);
 
     info!("END");
 
     Ok(())
 }
 
-fn process_drawbridge(_context: NoContext, tx: UnboundedSender<RoutedMessage>, msg: IdMessage) -> bool {
+fn process_drawbridge(_context: NoContext, tx: VSender<RoutedMessage>, msg: IdMessage) -> bool {
     let id = msg.id;
     let mut buf = msg.buf;
     match buf.pull::<op::Command>() {
@@ -63,29 +78,27 @@ fn process_drawbridge(_context: NoContext, tx: UnboundedSender<RoutedMessage>, m
     .is_ok()
 }
 
-fn v_authorize(tx: &UnboundedSender<RoutedMessage>, id: u8, buf: &mut SizedBuffer) -> Result<(), DrawbridgeError> {
+fn v_authorize(tx: &VSender<RoutedMessage>, id: NodeType, buf: &mut SizedBuffer) -> Result<(), DrawbridgeError> {
     let mut out = SizedBuffer::new(256);
     out.push(&op::Route::Any(op::Flavor::Lookout)).map_err(|_| Client(()))?;
     out.push(&op::Command::Authorize).map_err(|_| Client(()))?;
     out.push(&id).map_err(|_| Client(()))?;
     out.xfer_bytes(buf).map_err(|_| Client(()))?;
 
-    let message = RoutedMessage {
-        route: op::Route::Local,
-        buf: out,
-    };
+    let message = RoutedMessage::new(op::Route::Local, out);
     tx.send(message).map_err(|_| Client(()))
 }
 
-fn process_courtyard(_context: NoContext, tx: UnboundedSender<RoutedMessage>, mut buf: SizedBuffer) -> VClientMode {
+fn process_courtyard(_context: NoContext, tx: VSender<RoutedMessage>, mut buf: SizedBuffer) -> VClientMode {
     let result = match buf.pull::<op::Command>() {
         Ok(op::Command::Authorize) => c_authorize(&tx, &mut buf),
+        Ok(op::Command::Undeliverable) => c_undeliverable(&tx, &mut buf),
         _ => Ok(VClientMode::Continue),
     };
     result.unwrap_or(VClientMode::Disconnect)
 }
 
-fn c_authorize(tx: &UnboundedSender<RoutedMessage>, buf: &mut SizedBuffer) -> Result<VClientMode, DrawbridgeError> {
+fn c_authorize(tx: &VSender<RoutedMessage>, buf: &mut SizedBuffer) -> Result<VClientMode, DrawbridgeError> {
     let mut out = SizedBuffer::new(256);
     out.push(&op::Command::Authorize).map_err(|_| Server(()))?;
 
@@ -94,9 +107,24 @@ fn c_authorize(tx: &UnboundedSender<RoutedMessage>, buf: &mut SizedBuffer) -> Re
 
     out.xfer_bytes(buf).map_err(|_| Server(()))?;
 
-    let message = RoutedMessage {
-        route: op::Route::One(route_id),
-        buf: out,
-    };
+    let message = RoutedMessage::new(op::Route::One(route_id), out);
+    tx.send(message).map_err(|_| Server(())).map(|_| VClientMode::Continue)
+}
+
+// With no Lookout to answer, the Vagabond is told why instead of waiting on its login
+fn c_undeliverable(tx: &VSender<RoutedMessage>, buf: &mut SizedBuffer) -> Result<VClientMode, DrawbridgeError> {
+    let notice = buf.pull::<op::Undeliverable>().map_err(|_| Server(()))?;
+    if notice.command != op::Command::Authorize {
+        return Ok(VClientMode::Continue);
+    }
+
+    let _ = buf.pull::<NodeType>(); //discard
+    let route_id = buf.pull::<NodeType>().map_err(|_| Server(()))?;
+
+    let mut out = SizedBuffer::new(64);
+    out.push(&op::Command::Undeliverable).map_err(|_| Server(()))?;
+    out.push(&notice).map_err(|_| Server(()))?;
+
+    let message = RoutedMessage::new(op::Route::One(route_id), out);
     tx.send(message).map_err(|_| Server(())).map(|_| VClientMode::Continue)
 }
@@ -1,11 +1,10 @@
 use mimalloc::MiMalloc;
-use tokio::sync::mpsc;
-use tokio::sync::mpsc::UnboundedSender;
 use tracing::{info, instrument};
 
 use forum_lib::core::ForumSubCommand;
+use shared_net::channel::{bounded, QueueConfig, VSender};
 use shared_net::op::SubCommandType;
-use shared_net::{NodeType, RoutedMessage, SizedBuffer, SizedBufferError, VClientMode, op};
+use shared_net::{NodeType, RoutedMessage, Secret, SizedBuffer, SizedBufferError, VClientConfig, VClientMode, op};
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
@@ -13,8 +12,10 @@ static GLOBAL: MiMalloc = MiMalloc;
 #[derive(Clone)]
 struct NoContext;
 
+#[allow(dead_code)]
 #[derive(Debug)]
 enum ForumError {
+    Environment(std::env::VarError),
     Client(()),
 }
 
@@ -33,9 +34,11 @@ async fn main() -> Result<(), ForumError> {
 async fn forum_main(courtyard: String) -> Result<(), ForumError> {
     info!("START");
 
-    let (dummy_tx, dummy_rx) = mpsc::unbounded_channel();
+    let secret = Secret::from_env("COURTYARD_SECRET").map_err(ForumError::Environment)?;
 
-    let courtyard_client = shared_net::async_client(NoContext, op::Flavor::Forum, dummy_tx, dummy_rx, courtyard, process_courtyard);
+    let (dummy_tx, dummy_rx) = bounded(QueueConfig::default());
+
+    let courtyard_client = shared_net::async_client(NoContext, op::Flavor::Forum, dummy_tx, dummy_rx, VClientConfig::from(courtyard).with_secret(secret).with_shutdown(shared_net::shutdown_on_ctrl_c()), process_courtyard, |_, _, _| {});
 
     courtyard_client.await.map_err(ForumError::Client)?;
 
@@ -44,7 +47,7 @@ async fn forum_main(courtyard: String) -> Result<(), ForumError> {
     Ok(())
 }
 
-fn process_courtyard(_context: NoContext, tx: UnboundedSender<RoutedMessage>, mut buf: SizedBuffer) -> VClientMode {
+fn process_courtyard(_context: NoContext, tx: VSender<RoutedMessage>, mut buf: SizedBuffer) -> VClientMode {
     if let Ok(op::Command::Message(subcommand)) = buf.pull::<op::Command>() {
         match subcommand.into() {
             ForumSubCommand::Chat => c_chat(tx, buf),
@@ -54,7 +57,7 @@ fn process_courtyard(_context: NoContext, tx: UnboundedSender<RoutedMessage>, mu
     VClientMode::Continue
 }
 
-fn c_chat(tx: UnboundedSender<RoutedMessage>, mut buf: SizedBuffer) {
+fn c_chat(tx: VSender<RoutedMessage>, mut buf: SizedBuffer) {
     if let Ok(out) = move || -> Result<SizedBuffer, SizedBufferError> {
         let _gate = buf.pull::<NodeType>()?; // discard gate id
 
@@ -68,7 +71,7 @@ fn c_chat(tx: UnboundedSender<RoutedMessage>, mut buf: SizedBuffer) {
     }
 }
 
-fn c_dm(tx: UnboundedSender<RoutedMessage>, mut buf: SizedBuffer) {
+fn c_dm(tx: VSender<RoutedMessage>, mut buf: SizedBuffer) {
     if let Ok(out) = move || -> Result<SizedBuffer, SizedBufferError> {
         let _gate = buf.pull::<NodeType>()?;
 
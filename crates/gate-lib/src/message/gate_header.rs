@@ -26,7 +26,7 @@ mod test {
     #[test]
     fn test_gate_header() -> Result<(), SizedBufferError> {
         let orig = GateHeader {
-            vagabond: 3,
+            vagabond: 3000,
             user: 1234567890,
             auth: 9876543210,
         };
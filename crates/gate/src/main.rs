@@ -1,23 +1,33 @@
 use std::collections::HashMap;
 use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 
 use chrono::Utc;
 use mimalloc::MiMalloc;
-use tokio::signal;
-use tokio::sync::mpsc;
-use tokio::sync::mpsc::UnboundedSender;
+use tokio::time::Duration;
 use tracing::{error, info, instrument};
 
-use archive_lib::core::ArchiveSubCommand;
 use forum_lib::core::ForumSubCommand;
 use gate_lib::message::gate_header::GateHeader;
 use hall_lib::core::GameSubCommand;
-use shared_net::{op, AuthType, Bufferable, IdMessage, NodeType, RoutedMessage, SizedBuffer, SizedBufferError, TimestampType, UserIdType, VClientMode};
+use shared_net::channel::{bounded, OverflowPolicy, QueueConfig, VReceiver, VSender};
+use shared_net::tls::{self, TlsError};
+use shared_net::{op, AuthType, IdMessage, NodeType, RateLimit, RateLimits, RateViolation, Registration, Requester, RoutedMessage, Secret, SizedBuffer, SizedBufferError, TimestampType, UserIdType, VClientConfig, VClientMode, VServerConfig};
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
 
+// a Vagabond that cannot keep up is dropped rather than stalling everyone else
+const VAGABOND_QUEUE: QueueConfig = QueueConfig::new(256, OverflowPolicy::Disconnect);
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+// what a Vagabond may send before Gate stops forwarding it, anything past these is dropped and reported to Jail
+const GAME_LIMIT: RateLimit = RateLimit::new(20, 40);
+const MESSAGE_LIMIT: RateLimit = RateLimit::new(5, 10);
+const INVENTORY_LIMIT: RateLimit = RateLimit::new(10, 20);
+
 struct GateUser {
     name: String,
     user: UserIdType,
@@ -26,18 +36,21 @@ struct GateUser {
 
 struct Gate {
     interface: SocketAddr,
-    reply: UnboundedSender<RoutedMessage>,
+    reply: VSender<RoutedMessage>,
+    vagabonds: VSender<RoutedMessage>,
+    requester: Requester,
     map: HashMap<u128, GateUser>,
 }
 
 #[allow(dead_code)]
 #[derive(Debug)]
 enum GateError {
-    Interrupt,
+    Environment(std::env::VarError),
     Parse(std::net::AddrParseError),
     SizedBuffer(SizedBufferError),
     Client(()),
     Server(()),
+    Tls(TlsError),
 }
 
 #[tokio::main]
@@ -48,30 +61,49 @@ async fn main() -> Result<(), GateError> {
     let _ = args.next(); // program name
     let iface_to_courtyard = args.next().unwrap_or("[::1]:12345".to_string());
     let iface_to_vagabond = args.next().unwrap_or("[::]:23451".to_string());
+    let tls = match (args.next(), args.next()) {
+        (Some(cert), Some(key)) => Some(tls::load_server_config(Path::new(&cert), Path::new(&key)).map_err(GateError::Tls)?),
+        _ => None,
+    };
 
-    gate_main(iface_to_vagabond, iface_to_courtyard).await
+    gate_main(iface_to_vagabond, iface_to_courtyard, tls).await
 }
 
-#[instrument]
-async fn gate_main(interface: String, courtyard: String) -> Result<(), GateError> {
+#[instrument(skip(tls))]
+async fn gate_main(interface: String, courtyard: String, tls: Option<Arc<tls::ServerConfig>>) -> Result<(), GateError> {
     info!("START");
 
-    let (g2c_tx, g2c_rx) = mpsc::unbounded_channel();
-    let (g2v_tx, g2v_rx) = mpsc::unbounded_channel();
+    let secret = Secret::from_env("COURTYARD_SECRET").map_err(GateError::Environment)?;
+
+    let (g2c_tx, g2c_rx) = bounded(QueueConfig::default());
+    let (g2v_tx, g2v_rx) = bounded(QueueConfig::default());
+    let requester = Requester::new(g2v_tx.clone());
 
     let gate_context = Arc::new(Mutex::new(Gate {
         interface: interface.parse().map_err(GateError::Parse)?,
         reply: g2v_tx.clone(),
+        vagabonds: g2c_tx.clone(),
+        requester: requester.clone(),
         map: HashMap::new(),
     }));
 
-    let gate = shared_net::async_server(gate_context.clone(), g2v_tx, g2c_rx, interface, process_vagabond);
-    let courtyard_client = shared_net::async_client(gate_context.clone(), op::Flavor::Gate, g2c_tx, g2v_rx, courtyard, process_courtyard);
+    let (violations_tx, violations_rx) = bounded(QueueConfig::default());
+    let rate_limits = RateLimits::default().with_limit(op::Command::Game(0), GAME_LIMIT).with_limit(op::Command::Message(0), MESSAGE_LIMIT).with_limit(op::Command::Inventory(0), INVENTORY_LIMIT).with_violations(violations_tx);
+
+    let shutdown = shared_net::shutdown_on_ctrl_c();
+    let mut server_config = VServerConfig::new(interface).with_registration(Registration::default().allow(op::Flavor::Vagabond)).with_queue(VAGABOND_QUEUE).with_rate_limits(rate_limits).with_shutdown(shutdown.clone());
+    if let Some(tls) = tls {
+        server_config = server_config.with_tls(tls);
+    }
+    let gate = shared_net::async_server(gate_context.clone(), g2v_tx, g2c_rx, server_config, process_vagabond, disconnect_vagabond);
+    let courtyard_client = shared_net::async_client(gate_context.clone(), op::Flavor::Gate, g2c_tx, g2v_rx, VClientConfig::from(courtyard).with_secret(secret).with_requester(requester).with_shutdown(shutdown.clone()), process_courtyard, |_, _, _| {});
 
-    tokio::spawn(gate);
-    tokio::spawn(courtyard_client);
+    let gate = tokio::spawn(gate);
+    let courtyard_client = tokio::spawn(courtyard_client);
+    let floods = tokio::spawn(report_floods(gate_context, violations_rx));
 
-    signal::ctrl_c().await.map_err(|_| GateError::Interrupt)?;
+    shutdown.cancelled().await;
+    let _ = tokio::join!(gate, courtyard_client, floods);
 
     info!("END");
 
@@ -100,20 +132,31 @@ fn should_marshal_game_to_vagabond(subcommand: op::SubCommandType) -> bool {
 }
 
 #[rustfmt::skip]
-fn process_vagabond(context: Arc<Mutex<Gate>>, tx: UnboundedSender<RoutedMessage>, msg: IdMessage) -> bool {
+fn process_vagabond(context: Arc<Mutex<Gate>>, tx: VSender<RoutedMessage>, msg: IdMessage) -> bool {
     let id = msg.id;
     let mut buf = msg.buf;
     if let Ok(command) = buf.pull::<op::Command>() {
         match command {
             op::Command::Hello => v_hello(context, id, &mut buf).is_ok(),
-            op::Command::Message(_) => v_marshal_username(context, op::Flavor::Forum, command, &tx, &mut buf).is_ok(),
-            op::Command::Inventory(_) => v_marshal(context, op::Flavor::Archive, command, &tx, id, &mut buf).is_ok(),
-            op::Command::Game(subcommand) if should_marshal_game_to_vagabond(subcommand) => v_marshal(context, op::Flavor::Hall, command, &tx, id, &mut buf).is_ok(),
+            op::Command::Message(_) => v_marshal_username(context, op::Flavor::Forum, command, &tx, buf).is_ok(),
+            op::Command::Inventory(_) => v_request(context, op::Flavor::Archive, command, id, buf).is_ok(),
+            op::Command::Game(subcommand) if should_marshal_game_to_vagabond(subcommand) => v_marshal(context, op::Flavor::Hall, command, &tx, id, buf).is_ok(),
             op::Command::NoOp
             | op::Command::Register
             | op::Command::Authorize
             | op::Command::UserAttr
             | op::Command::Game(_)
+            | op::Command::Reject
+            | op::Command::Ping
+            | op::Command::Pong
+            | op::Command::Request(_)
+            | op::Command::Reply(_)
+            | op::Command::Challenge
+            | op::Command::Undeliverable
+            | op::Command::Advertise
+            | op::Command::Forward
+            | op::Command::Status
+            | op::Command::Multicast
             => false,
         }
     } else {
@@ -121,7 +164,7 @@ fn process_vagabond(context: Arc<Mutex<Gate>>, tx: UnboundedSender<RoutedMessage
     }
 }
 
-fn v_hello(context: Arc<Mutex<Gate>>, id: u8, buf: &mut SizedBuffer) -> Result<(), GateError> {
+fn v_hello(context: Arc<Mutex<Gate>>, id: NodeType, buf: &mut SizedBuffer) -> Result<(), GateError> {
     let auth = buf.pull::<AuthType>().map_err(GateError::SizedBuffer)?;
     if let Some(user) = context.lock().unwrap().map.get_mut(&auth) {
         user.vagabond = id;
@@ -131,47 +174,78 @@ fn v_hello(context: Arc<Mutex<Gate>>, id: u8, buf: &mut SizedBuffer) -> Result<(
     }
 }
 
-fn v_marshal_username(context: Arc<Mutex<Gate>>, flavor: op::Flavor, command: op::Command, tx: &UnboundedSender<RoutedMessage>, buf: &mut SizedBuffer) -> Result<(), GateError> {
+fn v_marshal_username(context: Arc<Mutex<Gate>>, flavor: op::Flavor, command: op::Command, tx: &VSender<RoutedMessage>, mut buf: SizedBuffer) -> Result<(), GateError> {
     if let Some(user) = context.lock().unwrap().map.get(&buf.pull::<u128>().map_err(GateError::SizedBuffer)?) {
-        let mut out = SizedBuffer::new(256);
-        out.push(&op::Route::Any(flavor)).map_err(GateError::SizedBuffer)?;
-        out.push(&command).map_err(GateError::SizedBuffer)?;
-        out.push(&user.name).map_err(GateError::SizedBuffer)?;
-        out.xfer_bytes(buf).map_err(GateError::SizedBuffer)?;
+        buf.prepend(&user.name).map_err(GateError::SizedBuffer)?;
+        buf.prepend(&command).map_err(GateError::SizedBuffer)?;
+        buf.prepend(&op::Route::Any(flavor)).map_err(GateError::SizedBuffer)?;
 
-        tx.send(RoutedMessage::local(out)).map_err(|_| GateError::Client(()))
+        tx.send(RoutedMessage::local(buf)).map_err(|_| GateError::Client(()))
     } else {
         Err(GateError::Client(()))
     }
 }
 
-fn v_marshal(context: Arc<Mutex<Gate>>, flavor: op::Flavor, command: op::Command, tx: &UnboundedSender<RoutedMessage>, id: u8, buf: &mut SizedBuffer) -> Result<(), GateError> {
+fn v_marshal(context: Arc<Mutex<Gate>>, flavor: op::Flavor, command: op::Command, tx: &VSender<RoutedMessage>, id: NodeType, mut buf: SizedBuffer) -> Result<(), GateError> {
     let auth = buf.pull::<AuthType>().map_err(GateError::SizedBuffer)?;
     if let Some(user) = context.lock().unwrap().map.get(&auth) {
-        let mut out = SizedBuffer::new(256);
-        out.push(&op::Route::Any(flavor)).map_err(GateError::SizedBuffer)?;
-        out.push(&command).map_err(GateError::SizedBuffer)?;
-        out.push(&GateHeader::new(id, user.user, auth)).map_err(GateError::SizedBuffer)?;
-        out.xfer_bytes(buf).map_err(GateError::SizedBuffer)?;
+        buf.prepend(&GateHeader::new(id, user.user, auth)).map_err(GateError::SizedBuffer)?;
+        buf.prepend(&command).map_err(GateError::SizedBuffer)?;
+        buf.prepend(&op::Route::Any(flavor)).map_err(GateError::SizedBuffer)?;
 
-        tx.send(RoutedMessage::local(out)).map_err(|_| GateError::Client(()))
+        tx.send(RoutedMessage::local(buf)).map_err(|_| GateError::Client(()))
     } else {
         Err(GateError::Client(()))
     }
 }
 
+fn v_request(context: Arc<Mutex<Gate>>, flavor: op::Flavor, command: op::Command, id: NodeType, mut buf: SizedBuffer) -> Result<(), GateError> {
+    let auth = buf.pull::<AuthType>().map_err(GateError::SizedBuffer)?;
+    let (requester, vagabonds, user) = {
+        let gate = context.lock().unwrap();
+        let user = gate.map.get(&auth).ok_or(GateError::Client(()))?;
+        (gate.requester.clone(), gate.vagabonds.clone(), user.user)
+    };
+
+    buf.prepend(&GateHeader::new(id, user, auth)).map_err(GateError::SizedBuffer)?;
+
+    tokio::spawn(async move {
+        match requester.request(op::Route::Any(flavor), command, buf, REQUEST_TIMEOUT).await {
+            Ok(mut reply) => {
+                reply.discard_read();
+                if vagabonds.send(RoutedMessage::new(op::Route::One(id), reply)).is_err() {
+                    error!(?command, id);
+                }
+            }
+            Err(err) => error!(?err, ?command, id),
+        }
+    });
+    Ok(())
+}
+
 #[rustfmt::skip]
-fn process_courtyard(context: Arc<Mutex<Gate>>, tx: UnboundedSender<RoutedMessage>, mut buf: SizedBuffer) -> VClientMode {
+fn process_courtyard(context: Arc<Mutex<Gate>>, tx: VSender<RoutedMessage>, mut buf: SizedBuffer) -> VClientMode {
     if let Ok(command) = buf.pull::<op::Command>() {
         let result = match command {
             op::Command::Authorize => c_authorize(context, &mut buf),
-            op::Command::Message(_) => c_marshal_message(command, context, &tx, &mut buf),
-            op::Command::Inventory(_) => c_marshal_inventory(command, &tx, &mut buf),
-            op::Command::Game(_) => c_marshal_one(command, &tx, &mut buf),
+            op::Command::Message(_) => c_marshal_message(command, context, &tx, buf),
+            op::Command::Game(_) => c_marshal_one(command, &tx, buf),
+            op::Command::Multicast => c_multicast(&tx, buf),
+            op::Command::Undeliverable => c_undeliverable(&tx, buf),
             op::Command::NoOp
             | op::Command::Register
             | op::Command::Hello
             | op::Command::UserAttr
+            | op::Command::Inventory(_)
+            | op::Command::Reject
+            | op::Command::Ping
+            | op::Command::Pong
+            | op::Command::Request(_)
+            | op::Command::Reply(_)
+            | op::Command::Challenge
+            | op::Command::Advertise
+            | op::Command::Forward
+            | op::Command::Status
             => Ok(VClientMode::Continue),
         };
         result.unwrap_or_else(|err| { error!(?err); VClientMode::Continue })
@@ -222,34 +296,65 @@ fn c_authorize(context: Arc<Mutex<Gate>>, buf: &mut SizedBuffer) -> Result<VClie
         return Ok(VClientMode::Disconnect);
     }
 
+    match send_user_attr(op::Route::Any(op::Flavor::Jail), user, "login", &context.reply) {
+        Err(GateError::Client(_)) => Ok(VClientMode::Disconnect),
+        result => result.map(|_| VClientMode::Continue),
+    }
+}
+
+fn send_user_attr(route: op::Route, user: UserIdType, attr: &str, tx: &VSender<RoutedMessage>) -> Result<(), GateError> {
     let mut update = SizedBuffer::new(128);
-    update.push(&op::Route::Any(op::Flavor::Jail)).map_err(GateError::SizedBuffer)?;
+    update.push(&route).map_err(GateError::SizedBuffer)?;
     update.push(&op::Command::UserAttr).map_err(GateError::SizedBuffer)?;
     update.push(&user).map_err(GateError::SizedBuffer)?;
-    update.push(&"login".to_string()).map_err(GateError::SizedBuffer)?;
+    update.push(&attr.to_string()).map_err(GateError::SizedBuffer)?;
 
     let now = Utc::now().timestamp() as TimestampType;
     update.push(&now).map_err(GateError::SizedBuffer)?;
 
-    if context.reply.send(RoutedMessage::local(update)).is_err() {
-        Ok(VClientMode::Disconnect)
-    } else {
-        Ok(VClientMode::Continue)
+    tx.send(RoutedMessage::local(update)).map_err(|_| GateError::Client(()))
+}
+
+// Ends once the server is gone, it holds the only sender
+async fn report_floods(context: Arc<Mutex<Gate>>, mut violations: VReceiver<RateViolation>) {
+    while let Some(violation) = violations.recv().await {
+        let (user, reply) = {
+            let context = context.lock().unwrap();
+            (context.map.values().find(|user| user.vagabond == violation.id).map(|user| user.user), context.reply.clone())
+        };
+        match user {
+            Some(user) => {
+                info!(user, id = violation.id, command = ?violation.command, "flood");
+                if let Err(err) = send_user_attr(op::Route::Any(op::Flavor::Jail), user, "flood", &reply) {
+                    error!(?err);
+                }
+            }
+            None => info!(id = violation.id, command = ?violation.command, "flood before hello"),
+        }
     }
 }
 
-fn c_marshal_inventory(command: op::Command, tx: &UnboundedSender<RoutedMessage>, buf: &mut SizedBuffer) -> Result<VClientMode, GateError> {
-    if let op::Command::Inventory(sub) = command {
-        match sub.into() {
-            ArchiveSubCommand::InvGen => Ok(VClientMode::Continue),
-            ArchiveSubCommand::InvList => c_marshal_one(command, tx, buf),
+fn disconnect_vagabond(context: Arc<Mutex<Gate>>, tx: VSender<RoutedMessage>, id: NodeType) {
+    let mut context = context.lock().unwrap();
+    let mut departed = Vec::new();
+    context.map.retain(|_, user| {
+        if user.vagabond == id {
+            departed.push(user.user);
+        }
+        user.vagabond != id
+    });
+
+    for user in departed {
+        info!(user, id, "logout");
+        for route in [op::Route::Any(op::Flavor::Jail), op::Route::All(op::Flavor::Hall)] {
+            if let Err(err) = send_user_attr(route, user, "logout", &tx) {
+                error!(?err);
+            }
         }
-    } else {
-        Ok(VClientMode::Continue)
     }
 }
 
-fn c_marshal_message(command: op::Command, context: Arc<Mutex<Gate>>, tx: &UnboundedSender<RoutedMessage>, buf: &mut SizedBuffer) -> Result<VClientMode, GateError> {
+fn c_marshal_message(command: op::Command, context: Arc<Mutex<Gate>>, tx: &VSender<RoutedMessage>, buf: SizedBuffer) -> Result<VClientMode, GateError> {
     if let op::Command::Message(sub) = command {
         match sub.into() {
             ForumSubCommand::Chat => c_marshal_name(command, context, tx, buf),
@@ -260,7 +365,7 @@ fn c_marshal_message(command: op::Command, context: Arc<Mutex<Gate>>, tx: &Unbou
     }
 }
 
-fn c_marshal_name(command: op::Command, context: Arc<Mutex<Gate>>, tx: &UnboundedSender<RoutedMessage>, buf: &mut SizedBuffer) -> Result<VClientMode, GateError> {
+fn c_marshal_name(command: op::Command, context: Arc<Mutex<Gate>>, tx: &VSender<RoutedMessage>, mut buf: SizedBuffer) -> Result<VClientMode, GateError> {
     let _ = buf.pull::<NodeType>().map_err(GateError::SizedBuffer)?; // forum (discard)
 
     let sendee = buf.pull::<String>().map_err(GateError::SizedBuffer)?;
@@ -274,26 +379,52 @@ fn c_marshal_name(command: op::Command, context: Arc<Mutex<Gate>>, tx: &Unbounde
     Ok(VClientMode::Continue)
 }
 
-fn c_marshal_one(command: op::Command, tx: &UnboundedSender<RoutedMessage>, buf: &mut SizedBuffer) -> Result<VClientMode, GateError> {
+fn c_marshal_one(command: op::Command, tx: &VSender<RoutedMessage>, mut buf: SizedBuffer) -> Result<VClientMode, GateError> {
     let _ = buf.pull::<NodeType>().map_err(GateError::SizedBuffer)?; // sender (discard)
     let vagabond = buf.pull::<NodeType>().map_err(GateError::SizedBuffer)?;
 
     send_to_client(op::Route::One(vagabond), command, tx, buf)
 }
 
-fn c_marshal_all(command: op::Command, tx: &UnboundedSender<RoutedMessage>, buf: &mut SizedBuffer) -> Result<VClientMode, GateError> {
+// Courtyard sends one copy for all of our Vagabonds a frame is meant for: [Multicast][Vec<vagabond>][Command][sender][payload]
+fn c_multicast(tx: &VSender<RoutedMessage>, mut buf: SizedBuffer) -> Result<VClientMode, GateError> {
+    let vagabonds = buf.pull::<Vec<NodeType>>().map_err(GateError::SizedBuffer)?;
+    let command = buf.pull::<op::Command>().map_err(GateError::SizedBuffer)?;
+    let _ = buf.pull::<NodeType>().map_err(GateError::SizedBuffer)?; // sender (discard)
+
+    for vagabond in vagabonds {
+        if let VClientMode::Disconnect = send_to_client(op::Route::One(vagabond), command, tx, buf.clone())? {
+            return Ok(VClientMode::Disconnect);
+        }
+    }
+    Ok(VClientMode::Continue)
+}
+
+// A game command carries the Vagabond in its GateHeader, so Hall being away can be passed on to it
+fn c_undeliverable(tx: &VSender<RoutedMessage>, mut buf: SizedBuffer) -> Result<VClientMode, GateError> {
+    let notice = buf.pull::<op::Undeliverable>().map_err(GateError::SizedBuffer)?;
+    error!(route = ?notice.route, command = ?notice.command, reason = %notice.reason, "Undeliverable");
+    if !matches!(notice.command, op::Command::Game(_)) {
+        return Ok(VClientMode::Continue);
+    }
+
+    let _ = buf.pull::<NodeType>().map_err(GateError::SizedBuffer)?; // gate (discard)
+    let vagabond = buf.pull::<NodeType>().map_err(GateError::SizedBuffer)?;
+
+    send_to_client(op::Route::One(vagabond), op::Command::Undeliverable, tx, SizedBuffer::from(&notice).map_err(GateError::SizedBuffer)?)
+}
+
+fn c_marshal_all(command: op::Command, tx: &VSender<RoutedMessage>, mut buf: SizedBuffer) -> Result<VClientMode, GateError> {
     let _ = buf.pull::<NodeType>().map_err(GateError::SizedBuffer)?; // sender (discard)
 
     send_to_client(op::Route::All(op::Flavor::Vagabond), command, tx, buf)
 }
 
-fn send_to_client(route: op::Route, command: op::Command, tx: &UnboundedSender<RoutedMessage>, buf: &mut SizedBuffer) -> Result<VClientMode, GateError> {
-    let mut out = SizedBuffer::new(command.size_in_buffer() + buf.read_remain());
-    out.push(&command).map_err(GateError::SizedBuffer)?;
-    out.xfer_bytes(buf).map_err(GateError::SizedBuffer)?;
+fn send_to_client(route: op::Route, command: op::Command, tx: &VSender<RoutedMessage>, mut buf: SizedBuffer) -> Result<VClientMode, GateError> {
+    buf.prepend(&command).map_err(GateError::SizedBuffer)?;
 
     info!(?route, ?command, "bytes: {}", buf.size());
-    if tx.send(RoutedMessage::new(route, out)).is_err() {
+    if tx.send(RoutedMessage::new(route, buf).with_priority(command.into())).is_err() {
         error!(?command);
         Ok(VClientMode::Disconnect)
     } else {
@@ -5,12 +5,11 @@ use shared_net::{Bufferable, SizedBuffer, SizedBufferError};
 
 use crate::core::{AttributeArray, AttributeArrays, AttributeValueType};
 
-type AttributeKindType = u8;
-
 #[repr(u8)]
-#[derive(Clone, Copy, Debug, Serialize, Deserialize, Hash, PartialEq, Eq, FromPrimitive, IntoPrimitive)]
+#[derive(Bufferable, Clone, Copy, Debug, Serialize, Deserialize, Hash, PartialEq, Eq, FromPrimitive, IntoPrimitive)]
 pub enum AttributeKind {
     #[num_enum(default)]
+    #[bufferable(default)]
     Analyze,
     Breach,
     Compute,
@@ -103,22 +102,6 @@ impl Attributes {
     }
 }
 
-impl Bufferable for AttributeKind {
-    fn push_into(&self, buf: &mut SizedBuffer) -> Result<usize, SizedBufferError> {
-        let attribute_kind: AttributeKindType = (*self).into();
-        attribute_kind.push_into(buf)
-    }
-
-    fn pull_from(buf: &mut SizedBuffer) -> Result<Self, SizedBufferError> {
-        let attribute_kind = AttributeKindType::pull_from(buf)?;
-        Ok(attribute_kind.into())
-    }
-
-    fn size_in_buffer(&self) -> usize {
-        size_of::<AttributeKindType>()
-    }
-}
-
 #[cfg(test)]
 mod test {
     use super::AttributeKind;
@@ -34,38 +34,12 @@ pub enum Host {
 
 pub type ActorIndexType = u8;
 
-#[derive(Clone, Copy)]
+#[derive(Bufferable, Clone, Copy)]
 #[cfg_attr(test, derive(Debug, PartialEq))]
 pub enum PickedCardTarget {
+    #[bufferable(default)]
     None,
     MachineLocal,
     MachineRemote,
     Actor(ActorIndexType),
 }
-
-impl Bufferable for PickedCardTarget {
-    fn push_into(&self, buf: &mut SizedBuffer) -> Result<usize, SizedBufferError> {
-        match self {
-            PickedCardTarget::None => 0u8,
-            PickedCardTarget::MachineLocal => 1,
-            PickedCardTarget::MachineRemote => 2,
-            PickedCardTarget::Actor(index) => 3 + index,
-        }
-        .push_into(buf)
-    }
-
-    fn pull_from(buf: &mut SizedBuffer) -> Result<Self, SizedBufferError> {
-        let result = match u8::pull_from(buf)? {
-            0 => PickedCardTarget::None,
-            1 => PickedCardTarget::MachineLocal,
-            2 => PickedCardTarget::MachineRemote,
-            index @ 3..12 => PickedCardTarget::Actor(index - 3),
-            _ => PickedCardTarget::None,
-        };
-        Ok(result)
-    }
-
-    fn size_in_buffer(&self) -> usize {
-        size_of::<u8>()
-    }
-}
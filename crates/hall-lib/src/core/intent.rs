@@ -4,10 +4,11 @@ use serde::{Deserialize, Serialize};
 use shared_net::{Bufferable, SizedBuffer, SizedBufferError};
 
 #[repr(u8)]
-#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, FromPrimitive, IntoPrimitive)]
+#[derive(Bufferable, Clone, Copy, PartialEq, Serialize, Deserialize, FromPrimitive, IntoPrimitive)]
 #[cfg_attr(test, derive(Debug))]
 pub enum AccessPointIntent {
     #[num_enum(default)]
+    #[bufferable(default)]
     None,
     Authenticate,
     TransferNext,
@@ -15,65 +16,73 @@ pub enum AccessPointIntent {
 }
 
 #[repr(u8)]
-#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, FromPrimitive, IntoPrimitive)]
+#[derive(Bufferable, Clone, Copy, PartialEq, Serialize, Deserialize, FromPrimitive, IntoPrimitive)]
 #[cfg_attr(test, derive(Debug))]
 pub enum BackendIntent {
     #[num_enum(default)]
+    #[bufferable(default)]
     None,
 }
 
 #[repr(u8)]
-#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, FromPrimitive, IntoPrimitive)]
+#[derive(Bufferable, Clone, Copy, PartialEq, Serialize, Deserialize, FromPrimitive, IntoPrimitive)]
 #[cfg_attr(test, derive(Debug))]
 pub enum ControlIntent {
     #[num_enum(default)]
+    #[bufferable(default)]
     None,
 }
 
 #[repr(u8)]
-#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, FromPrimitive, IntoPrimitive)]
+#[derive(Bufferable, Clone, Copy, PartialEq, Serialize, Deserialize, FromPrimitive, IntoPrimitive)]
 #[cfg_attr(test, derive(Debug))]
 pub enum DatabaseIntent {
     #[num_enum(default)]
+    #[bufferable(default)]
     None,
 }
 
 #[repr(u8)]
-#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, FromPrimitive, IntoPrimitive)]
+#[derive(Bufferable, Clone, Copy, PartialEq, Serialize, Deserialize, FromPrimitive, IntoPrimitive)]
 #[cfg_attr(test, derive(Debug))]
 pub enum EngineIntent {
     #[num_enum(default)]
+    #[bufferable(default)]
     None,
 }
 
 #[repr(u8)]
-#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, FromPrimitive, IntoPrimitive)]
+#[derive(Bufferable, Clone, Copy, PartialEq, Serialize, Deserialize, FromPrimitive, IntoPrimitive)]
 #[cfg_attr(test, derive(Debug))]
 pub enum FrontendIntent {
     #[num_enum(default)]
+    #[bufferable(default)]
     None,
 }
 
 #[repr(u8)]
-#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, FromPrimitive, IntoPrimitive)]
+#[derive(Bufferable, Clone, Copy, PartialEq, Serialize, Deserialize, FromPrimitive, IntoPrimitive)]
 #[cfg_attr(test, derive(Debug))]
 pub enum GatewayIntent {
     #[num_enum(default)]
+    #[bufferable(default)]
     None,
 }
 
 #[repr(u8)]
-#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, FromPrimitive, IntoPrimitive)]
+#[derive(Bufferable, Clone, Copy, PartialEq, Serialize, Deserialize, FromPrimitive, IntoPrimitive)]
 #[cfg_attr(test, derive(Debug))]
 pub enum HardwareIntent {
     #[num_enum(default)]
+    #[bufferable(default)]
     None,
 }
 
-#[derive(Default, Clone, Copy)]
+#[derive(Bufferable, Default, Clone, Copy)]
 #[cfg_attr(test, derive(Debug, PartialEq))]
 pub enum MissionNodeIntent {
     #[default]
+    #[bufferable(default)]
     None,
     Link(MissionNodeLinkDir),
     AccessPoint(AccessPointIntent),
@@ -118,50 +127,3 @@ impl MissionNodeIntent {
         }
     }
 }
-
-fn pack(intent: u8, action: u8) -> u16 {
-    ((intent as u16) << 8) | action as u16
-}
-
-fn unpack(packed: u16) -> (u8, u8) {
-    (((packed >> 8) & 0xFF) as u8, (packed & 0xFF) as u8)
-}
-
-impl Bufferable for MissionNodeIntent {
-    fn push_into(&self, buf: &mut SizedBuffer) -> Result<usize, SizedBufferError> {
-        let packed = match self {
-            MissionNodeIntent::None => 0u16,
-            MissionNodeIntent::Link(dir) => pack(1, (*dir).into()),
-            MissionNodeIntent::AccessPoint(action) => pack(2, (*action).into()),
-            MissionNodeIntent::Backend(action) => pack(3, (*action).into()),
-            MissionNodeIntent::Control(action) => pack(4, (*action).into()),
-            MissionNodeIntent::Database(action) => pack(5, (*action).into()),
-            MissionNodeIntent::Engine(action) => pack(6, (*action).into()),
-            MissionNodeIntent::Frontend(action) => pack(7, (*action).into()),
-            MissionNodeIntent::Gateway(action) => pack(8, (*action).into()),
-            MissionNodeIntent::Hardware(action) => pack(9, (*action).into()),
-        };
-        packed.push_into(buf)
-    }
-
-    fn pull_from(buf: &mut SizedBuffer) -> Result<Self, SizedBufferError> {
-        let (intent, action) = unpack(u16::pull_from(buf)?);
-        let result = match intent {
-            1 => MissionNodeIntent::Link(action.into()),
-            2 => MissionNodeIntent::AccessPoint(action.into()),
-            3 => MissionNodeIntent::Backend(action.into()),
-            4 => MissionNodeIntent::Control(action.into()),
-            5 => MissionNodeIntent::Database(action.into()),
-            6 => MissionNodeIntent::Engine(action.into()),
-            7 => MissionNodeIntent::Frontend(action.into()),
-            8 => MissionNodeIntent::Gateway(action.into()),
-            9 => MissionNodeIntent::Hardware(action.into()),
-            _ => MissionNodeIntent::None,
-        };
-        Ok(result)
-    }
-
-    fn size_in_buffer(&self) -> usize {
-        size_of::<u16>()
-    }
-}
@@ -31,10 +31,11 @@ pub enum MissionNodeState {
 }
 
 #[repr(u8)]
-#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, FromPrimitive, IntoPrimitive)]
+#[derive(Bufferable, Clone, Copy, PartialEq, Serialize, Deserialize, FromPrimitive, IntoPrimitive)]
 #[cfg_attr(test, derive(Debug))]
 pub enum MissionNodeLinkDir {
     #[num_enum(default)]
+    #[bufferable(default)]
     North,
     East,
     South,
@@ -7,14 +7,12 @@ use crate::core::GameSubCommand;
 #[cfg(test)]
 use strum_macros::EnumIter;
 
-type GameStageType = u8;
-type GamePhaseType = u8;
-
 #[repr(u8)]
-#[derive(Default, Clone, Copy, PartialEq)]
+#[derive(Bufferable, Default, Clone, Copy, PartialEq)]
 #[cfg_attr(test, derive(Debug, EnumIter))]
 pub enum Stage {
     #[default]
+    #[bufferable(default)]
     Idle,
     Building,
     Running(Phase),
@@ -22,10 +20,11 @@ pub enum Stage {
 }
 
 #[repr(u8)]
-#[derive(Default, Clone, Copy, PartialEq, FromPrimitive, IntoPrimitive)]
+#[derive(Bufferable, Default, Clone, Copy, PartialEq, FromPrimitive, IntoPrimitive)]
 #[cfg_attr(test, derive(Debug, EnumIter))]
 pub enum Phase {
     #[default]
+    #[bufferable(default)]
     ChooseIntent,
     ChooseAttr,
     CardPlay,
@@ -43,65 +42,6 @@ impl Phase {
     }
 }
 
-impl Stage {
-    const REPR_IDLE: GameStageType = 0;
-    const REPR_BUILDING: GameStageType = 1;
-    const REPR_RUNNING: GameStageType = 2;
-    const REPR_END: GameStageType = 3;
-}
-
-impl Bufferable for Stage {
-    fn push_into(&self, buf: &mut SizedBuffer) -> Result<usize, SizedBufferError> {
-        match self {
-            Stage::Idle => Self::REPR_IDLE.push_into(buf),
-            Stage::Building => Self::REPR_BUILDING.push_into(buf),
-            Stage::Running(phase) => {
-                Self::REPR_RUNNING.push_into(buf)?;
-                phase.push_into(buf)
-            }
-            Stage::End => Self::REPR_END.push_into(buf),
-        }
-    }
-
-    fn pull_from(buf: &mut SizedBuffer) -> Result<Self, SizedBufferError> {
-        let result = match u8::pull_from(buf)? {
-            Self::REPR_IDLE => Stage::Idle,
-            Self::REPR_BUILDING => Stage::Building,
-            Self::REPR_RUNNING => {
-                let phase = Phase::pull_from(buf)?;
-                Stage::Running(phase)
-            }
-            Self::REPR_END => Stage::End,
-            _ => Stage::Idle,
-        };
-        Ok(result)
-    }
-
-    fn size_in_buffer(&self) -> usize {
-        size_of::<GameStageType>()
-            + match self {
-                Stage::Running(phase) => phase.size_in_buffer(),
-                _ => 0,
-            }
-    }
-}
-
-impl Bufferable for Phase {
-    fn push_into(&self, buf: &mut SizedBuffer) -> Result<usize, SizedBufferError> {
-        let game_phase: GamePhaseType = (*self).into();
-        game_phase.push_into(buf)
-    }
-
-    fn pull_from(buf: &mut SizedBuffer) -> Result<Self, SizedBufferError> {
-        let game_phase = GamePhaseType::pull_from(buf)?;
-        Ok(game_phase.into())
-    }
-
-    fn size_in_buffer(&self) -> usize {
-        size_of::<GamePhaseType>()
-    }
-}
-
 #[cfg(test)]
 mod test {
     use super::{Phase, Stage};
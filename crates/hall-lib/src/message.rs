@@ -42,3 +42,73 @@ pub trait GameRequestMessage: CommandMessage {
 }
 
 pub trait GameResponseMessage: CommandMessage {}
+
+#[cfg(test)]
+mod test {
+    use rand::prelude::StdRng;
+    use rand::{RngExt, SeedableRng};
+    use shared_net::{Bufferable, SizedBuffer};
+
+    use super::*;
+
+    // Whatever a client sends has to decode to a value or an error, never a panic or an allocation the frame can't back
+    fn decode<T: Bufferable>(bytes: &[u8]) {
+        let mut buf = SizedBuffer::new(bytes.len());
+        buf.push_bytes(bytes).unwrap();
+        if let Ok(message) = buf.pull::<T>() {
+            assert!(message.size_in_buffer() <= bytes.len(), "{} from {bytes:?}", std::any::type_name::<T>());
+        }
+    }
+
+    fn decode_all(bytes: &[u8]) {
+        decode::<GameActivateRequest>(bytes);
+        decode::<GameActivateResponse>(bytes);
+        decode::<GameBuildRequest>(bytes);
+        decode::<GameBuildResponse>(bytes);
+        decode::<GameChooseAttrRequest>(bytes);
+        decode::<GameChooseAttrResponse>(bytes);
+        decode::<GameChooseIntentRequest>(bytes);
+        decode::<GameChooseIntentResponse>(bytes);
+        decode::<GameEndGameRequest>(bytes);
+        decode::<GameEndGameResponse>(bytes);
+        decode::<GameEndTurnRequest>(bytes);
+        decode::<GameEndTurnResponse>(bytes);
+        decode::<GamePlayCardRequest>(bytes);
+        decode::<GamePlayCardResponse>(bytes);
+        decode::<GameUpdateStateRequest>(bytes);
+        decode::<GameUpdateStateResponse>(bytes);
+        decode::<GameResolveCardsMessage>(bytes);
+        decode::<GameResourcesMessage>(bytes);
+        decode::<GameRollMessage>(bytes);
+        decode::<GameStartGameMessage>(bytes);
+        decode::<GameTickMessage>(bytes);
+        decode::<GameUpdateMissionMessage>(bytes);
+        decode::<GameUpdateTokensMessage>(bytes);
+        decode::<UpdateTokenMessage>(bytes);
+    }
+
+    #[test]
+    fn test_decode_arbitrary() {
+        let mut corpus = vec![vec![], vec![0x00], vec![0xFF], vec![0xFF; 2], vec![0xFF; 17], vec![0xFF; 512], vec![0x00; 512]];
+        // a plausible game id followed by every length prefix running past the end
+        for fill in [0x00, 0x7F, 0xFF] {
+            let mut bytes = 1234567890_u64.to_le_bytes().to_vec();
+            bytes.extend([0xFF, 0xFF]);
+            bytes.extend([fill; 40]);
+            corpus.push(bytes);
+        }
+
+        let mut rng = StdRng::seed_from_u64(0x5EED_F00D_CAFE_D00D);
+        for _ in 0..2000 {
+            let len = rng.random_range(0..300);
+            corpus.push((0..len).map(|_| rng.random::<u8>()).collect());
+        }
+
+        for bytes in &corpus {
+            for end in 0..=bytes.len().min(32) {
+                decode_all(&bytes[..end]);
+            }
+            decode_all(bytes);
+        }
+    }
+}
@@ -3,15 +3,14 @@ use std::rc::Rc;
 use std::sync::RwLock;
 
 use mimalloc::MiMalloc;
-use tokio::sync::mpsc;
-use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::mpsc::error::SendError;
 use tracing::{error, info, instrument};
 
 use gate_lib::message::gate_header::GateHeader;
 use hall_lib::core::GameSubCommand;
 use hall_lib::message::{GameRequestMessage, GameResponseMessage};
-use shared_net::{GameIdType, NodeType, RoutedMessage, SizedBuffer, SizedBufferError, VClientMode, op};
+use shared_net::channel::{bounded, QueueConfig, VSender};
+use shared_net::{GameIdType, NodeType, RoutedMessage, Secret, SizedBuffer, SizedBufferError, UserIdType, VClientConfig, VClientMode, op};
 
 use game::GameState;
 use logic::handle_phase_complete;
@@ -39,6 +38,7 @@ struct Hall {
 #[allow(dead_code)]
 #[derive(Debug)]
 enum HallError {
+    Environment(std::env::VarError),
     Io(std::io::Error),
     SizedBuffer(&'static str, SizedBufferError),
     Send(SendError<RoutedMessage>),
@@ -60,7 +60,9 @@ async fn main() -> Result<(), HallError> {
 async fn hall_main(courtyard: String) -> Result<(), HallError> {
     info!("START");
 
-    let (local_tx, local_rx) = mpsc::unbounded_channel();
+    let secret = Secret::from_env("COURTYARD_SECRET").map_err(HallError::Environment)?;
+
+    let (local_tx, local_rx) = bounded(QueueConfig::default());
 
     let context = Hall {
         games: RwLock::new(HashMap::new()),
@@ -69,17 +71,21 @@ async fn hall_main(courtyard: String) -> Result<(), HallError> {
     };
     let context = Rc::new(context);
 
-    shared_net::async_client(context, op::Flavor::Hall, local_tx, local_rx, courtyard, process_courtyard).await.map_err(HallError::Client)?;
+    shared_net::async_client(context, op::Flavor::Hall, local_tx, local_rx, VClientConfig::from(courtyard).with_secret(secret).with_shutdown(shared_net::shutdown_on_ctrl_c()), process_courtyard, |_, _, _| {}).await.map_err(HallError::Client)?;
 
     info!("END");
 
     Ok(())
 }
 
-fn process_courtyard(context: HallContext, tx: UnboundedSender<RoutedMessage>, mut buf: SizedBuffer) -> VClientMode {
+fn process_courtyard(context: HallContext, tx: VSender<RoutedMessage>, mut buf: SizedBuffer) -> VClientMode {
     let command = buf.pull::<op::Command>();
 
-    if let Ok(op::Command::Game(subcommand)) = command {
+    if let Ok(op::Command::UserAttr) = command {
+        if let Err(e) = handle_user_attr(&context, buf) {
+            error!(?command, ?e);
+        }
+    } else if let Ok(op::Command::Game(subcommand)) = command {
         let result = match subcommand.into() {
             GameSubCommand::Build => handle_recv(&context, tx, buf, logic::recv_game_build),
             GameSubCommand::Activate => handle_recv(&context, tx, buf, logic::recv_game_activate),
@@ -101,7 +107,18 @@ fn process_courtyard(context: HallContext, tx: UnboundedSender<RoutedMessage>, m
     VClientMode::Continue
 }
 
-fn handle_recv<Request, Response>(context: &HallContext, tx: UnboundedSender<RoutedMessage>, mut buf: SizedBuffer, handle_request: impl Fn(&HallContext, Request, NodeType, GateHeader) -> Option<Response>) -> Result<GameIdType, HallError>
+fn handle_user_attr(context: &HallContext, mut buf: SizedBuffer) -> Result<(), HallError> {
+    let gate = buf.pull::<NodeType>().map_err(|e| HallError::SizedBuffer("gate", e))?;
+    let user = buf.pull::<UserIdType>().map_err(|e| HallError::SizedBuffer("user", e))?;
+    let attr = buf.pull::<String>().map_err(|e| HallError::SizedBuffer("attr", e))?;
+
+    if attr == "logout" {
+        context.bx.write().map_err(|_| HallError::Client(()))?.untrack(&user, gate);
+    }
+    Ok(())
+}
+
+fn handle_recv<Request, Response>(context: &HallContext, tx: VSender<RoutedMessage>, mut buf: SizedBuffer, handle_request: impl Fn(&HallContext, Request, NodeType, GateHeader) -> Option<Response>) -> Result<GameIdType, HallError>
 where
     Request: GameRequestMessage,
     Response: GameResponseMessage,
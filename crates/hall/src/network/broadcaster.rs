@@ -1,20 +1,20 @@
 use std::collections::HashMap;
 
-use tokio::sync::mpsc::UnboundedSender;
 use tracing::error;
 
 use hall_lib::message::CommandMessage;
-use shared_net::{NodeType, RoutedMessage, UserIdType};
+use shared_net::{NodeType, RoutedMessage, UserIdType, op};
+use shared_net::channel::VSender;
 
-use crate::network::util::send_routed_message;
+use crate::network::util::{send_multicast_message, send_routed_message};
 
 pub(crate) struct Broadcaster {
-    pub(crate) local_tx: UnboundedSender<RoutedMessage>,
+    pub(crate) local_tx: VSender<RoutedMessage>,
     pub(crate) gate_map: HashMap<UserIdType, (NodeType, NodeType)>,
 }
 
 impl Broadcaster {
-    pub(crate) fn new(local_tx: UnboundedSender<RoutedMessage>) -> Self {
+    pub(crate) fn new(local_tx: VSender<RoutedMessage>) -> Self {
         Self {
             local_tx,
             gate_map: HashMap::new(),
@@ -22,20 +22,25 @@ impl Broadcaster {
     }
 
     pub(crate) fn broadcast<T: CommandMessage>(&mut self, message: T) {
-        let mut errors = vec![];
-        for (id, (gate, vagabond)) in &self.gate_map {
-            let result = send_routed_message(&message, *gate, *vagabond, &self.local_tx);
-            if result.is_err() {
-                error!(?result, id, gate, vagabond);
-                errors.push(*id);
-            }
+        if self.gate_map.is_empty() {
+            return;
         }
-
-        for id in &errors {
-            self.gate_map.remove(id);
+        let result = send_multicast_message(&message, self.targets(), &self.local_tx);
+        if result.is_err() {
+            error!(?result, users = self.gate_map.len());
         }
     }
 
+    fn targets(&self) -> Vec<op::Target> {
+        self.gate_map
+            .values()
+            .map(|(gate, vagabond)| op::Target {
+                gate: *gate,
+                vagabond: *vagabond,
+            })
+            .collect()
+    }
+
     pub(crate) fn send_to_user<T: CommandMessage>(&mut self, id: &UserIdType, message: &T) {
         if let Some((gate, vagabond)) = self.gate_map.get(id) {
             let result = send_routed_message(message, *gate, *vagabond, &self.local_tx);
@@ -49,4 +54,60 @@ impl Broadcaster {
     pub(crate) fn track(&mut self, id: UserIdType, target: (NodeType, NodeType)) {
         self.gate_map.insert(id, target);
     }
+
+    pub(crate) fn untrack(&mut self, id: &UserIdType, gate: NodeType) {
+        // a user that already moved to another gate keeps that route
+        if self.gate_map.get(id).is_some_and(|(tracked, _)| *tracked == gate) {
+            self.gate_map.remove(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hall_lib::message::{CommandMessage, GameRollMessage};
+    use shared_net::channel::{QueueConfig, bounded};
+    use shared_net::{NodeType, op};
+
+    use super::Broadcaster;
+
+    #[test]
+    fn test_untrack() {
+        let (local_tx, _local_rx) = bounded(QueueConfig::default());
+        let mut bx = Broadcaster::new(local_tx);
+        bx.track(1, (7, 3));
+        bx.track(2, (8, 4));
+
+        bx.untrack(&1, 8);
+        assert!(bx.gate_map.contains_key(&1));
+
+        bx.untrack(&1, 7);
+        assert!(!bx.gate_map.contains_key(&1));
+        assert!(bx.gate_map.contains_key(&2));
+    }
+
+    #[test]
+    fn test_broadcast() {
+        let (local_tx, mut local_rx) = bounded(QueueConfig::default());
+        let mut bx = Broadcaster::new(local_tx);
+        bx.track(1, (7, 3));
+        bx.track(2, (8, 4));
+        bx.track(3, (7, 5));
+
+        bx.broadcast(GameRollMessage {
+            roll: [1, 2, 3, 4],
+        });
+
+        // one frame for every player
+        let mut buf = local_rx.try_recv().unwrap().buf;
+        assert!(local_rx.try_recv().is_none());
+        let op::Route::Multi(targets) = buf.pull::<op::Route>().unwrap() else {
+            panic!("expected a Multi route");
+        };
+        let mut targets = targets.iter().map(|target| (target.gate, target.vagabond)).collect::<Vec<(NodeType, NodeType)>>();
+        targets.sort();
+        assert_eq!(targets, vec![(7, 3), (7, 5), (8, 4)]);
+        assert_eq!(buf.pull::<op::Command>().unwrap(), GameRollMessage::COMMAND);
+        assert_eq!(buf.pull::<GameRollMessage>().unwrap().roll, [1, 2, 3, 4]);
+    }
 }
@@ -1,11 +1,11 @@
-use tokio::sync::mpsc::UnboundedSender;
 
 use hall_lib::message::CommandMessage;
 use shared_net::{Bufferable, NodeType, RoutedMessage, SizedBuffer, op};
+use shared_net::channel::VSender;
 
 use crate::HallError;
 
-pub(crate) fn send_routed_message<T: CommandMessage>(message: &T, gate: NodeType, vagabond: NodeType, tx: &UnboundedSender<RoutedMessage>) -> Result<(), HallError> {
+pub(crate) fn send_routed_message<T: CommandMessage>(message: &T, gate: NodeType, vagabond: NodeType, tx: &VSender<RoutedMessage>) -> Result<(), HallError> {
     let route = op::Route::One(gate);
     let command = T::COMMAND;
 
@@ -18,3 +18,17 @@ pub(crate) fn send_routed_message<T: CommandMessage>(message: &T, gate: NodeType
 
     tx.send(out.into()).map_err(HallError::Send)
 }
+
+// One frame for every target, Courtyard and the Gates make the copies
+pub(crate) fn send_multicast_message<T: CommandMessage>(message: &T, targets: Vec<op::Target>, tx: &VSender<RoutedMessage>) -> Result<(), HallError> {
+    let route = op::Route::Multi(targets);
+    let command = T::COMMAND;
+
+    let mut out = SizedBuffer::new(route.size_in_buffer() + command.size_in_buffer() + message.size_in_buffer());
+
+    out.push(&route).map_err(|e| HallError::SizedBuffer("route", e))?;
+    out.push(&command).map_err(|e| HallError::SizedBuffer("command", e))?;
+    out.push(message).map_err(|e| HallError::SizedBuffer("message", e))?;
+
+    tx.send(out.into()).map_err(HallError::Send)
+}
@@ -1,9 +1,8 @@
 use mimalloc::MiMalloc;
-use tokio::sync::mpsc;
-use tokio::sync::mpsc::UnboundedSender;
 use tracing::{info, instrument};
 
-use shared_net::{NodeType, RoutedMessage, SizedBuffer, SizedBufferError, TimestampType, UserIdType, VClientMode, op};
+use shared_net::channel::{bounded, QueueConfig, VSender};
+use shared_net::{NodeType, RoutedMessage, Secret, SizedBuffer, SizedBufferError, TimestampType, UserIdType, VClientConfig, VClientMode, op};
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
@@ -14,6 +13,7 @@ struct NoContext;
 #[allow(dead_code)]
 #[derive(Debug)]
 enum JailError {
+    Environment(std::env::VarError),
     Client(()),
 }
 
@@ -32,9 +32,11 @@ async fn main() -> Result<(), JailError> {
 async fn jail_main(courtyard: String) -> Result<(), JailError> {
     info!("START");
 
-    let (dummy_tx, dummy_rx) = mpsc::unbounded_channel();
+    let secret = Secret::from_env("COURTYARD_SECRET").map_err(JailError::Environment)?;
 
-    let courtyard_client = shared_net::async_client(NoContext, op::Flavor::Jail, dummy_tx, dummy_rx, courtyard, process_courtyard);
+    let (dummy_tx, dummy_rx) = bounded(QueueConfig::default());
+
+    let courtyard_client = shared_net::async_client(NoContext, op::Flavor::Jail, dummy_tx, dummy_rx, VClientConfig::from(courtyard).with_secret(secret).with_shutdown(shared_net::shutdown_on_ctrl_c()), process_courtyard, |_, _, _| {});
 
     courtyard_client.await.map_err(JailError::Client)?;
 
@@ -43,7 +45,7 @@ async fn jail_main(courtyard: String) -> Result<(), JailError> {
     Ok(())
 }
 
-fn process_courtyard(_context: NoContext, _tx: UnboundedSender<RoutedMessage>, mut buf: SizedBuffer) -> VClientMode {
+fn process_courtyard(_context: NoContext, _tx: VSender<RoutedMessage>, mut buf: SizedBuffer) -> VClientMode {
     let _result = match buf.pull::<op::Command>() {
         Ok(op::Command::UserAttr) => c_userattr(buf),
         _ => Ok(()),
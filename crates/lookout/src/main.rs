@@ -3,11 +3,10 @@ use std::sync::{Arc, Mutex};
 use mimalloc::MiMalloc;
 use sqlx::postgres::{PgPool, PgPoolOptions};
 use sqlx::types::Uuid;
-use tokio::sync::mpsc;
-use tokio::sync::mpsc::UnboundedSender;
 use tracing::{info, instrument};
 
-use shared_net::{NodeType, PasswordType, RoutedMessage, SizedBuffer, SizedBufferError, UserIdType, VClientMode, op};
+use shared_net::channel::{bounded, QueueConfig, VSender};
+use shared_net::{NodeType, PasswordType, RoutedMessage, Secret, SizedBuffer, SizedBufferError, Spawn, UserIdType, VClientConfig, op};
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
@@ -21,6 +20,8 @@ struct Lookout {
 enum LookoutError {
     Environment(std::env::VarError),
     Database(sqlx::Error),
+    SizedBuffer(SizedBufferError),
+    Send,
     Client(()),
 }
 
@@ -41,13 +42,15 @@ async fn main() -> Result<(), LookoutError> {
 async fn lookout_main(courtyard: String, database: &str) -> Result<(), LookoutError> {
     info!("START");
 
+    let secret = Secret::from_env("COURTYARD_SECRET").map_err(LookoutError::Environment)?;
+
     let context = Arc::new(Mutex::new(Lookout {
         pool: PgPoolOptions::new().max_connections(16).connect(database).await.map_err(LookoutError::Database)?,
     }));
 
-    let (dummy_tx, dummy_rx) = mpsc::unbounded_channel();
+    let (dummy_tx, dummy_rx) = bounded(QueueConfig::default());
 
-    let courtyard_client = shared_net::async_client(context, op::Flavor::Lookout, dummy_tx, dummy_rx, courtyard, process_courtyard);
+    let courtyard_client = shared_net::async_client(context, op::Flavor::Lookout, dummy_tx, dummy_rx, VClientConfig::from(courtyard).with_secret(secret).with_shutdown(shared_net::shutdown_on_ctrl_c()), Spawn(process_courtyard), |_, _, _| {});
 
     courtyard_client.await.map_err(LookoutError::Client)?;
 
@@ -56,12 +59,11 @@ async fn lookout_main(courtyard: String, database: &str) -> Result<(), LookoutEr
     Ok(())
 }
 
-fn process_courtyard(context: Arc<Mutex<Lookout>>, tx: UnboundedSender<RoutedMessage>, mut buf: SizedBuffer) -> VClientMode {
-    let _result = match buf.pull::<op::Command>() {
-        Ok(op::Command::Authorize) => c_authorize(context, tx, &mut buf),
+async fn process_courtyard(context: Arc<Mutex<Lookout>>, tx: VSender<RoutedMessage>, mut buf: SizedBuffer) -> Result<(), LookoutError> {
+    match buf.pull::<op::Command>().map_err(LookoutError::SizedBuffer)? {
+        op::Command::Authorize => c_authorize(context, tx, buf).await,
         _ => Ok(()),
-    };
-    VClientMode::Continue
+    }
 }
 
 #[derive(sqlx::FromRow)]
@@ -70,54 +72,42 @@ struct User {
     pass_uuid: Uuid,
 }
 
-fn c_authorize(context: Arc<Mutex<Lookout>>, tx: UnboundedSender<RoutedMessage>, buf: &mut SizedBuffer) -> Result<(), SizedBufferError> {
-    let drawbridge = buf.pull::<NodeType>()?;
-    let vagabond = buf.pull::<NodeType>()?;
-    let user_hash = buf.pull::<UserIdType>()?;
-    let pass_hash = buf.pull::<PasswordType>()?;
+async fn c_authorize(context: Arc<Mutex<Lookout>>, tx: VSender<RoutedMessage>, mut buf: SizedBuffer) -> Result<(), LookoutError> {
+    let drawbridge = buf.pull::<NodeType>().map_err(LookoutError::SizedBuffer)?;
+    let vagabond = buf.pull::<NodeType>().map_err(LookoutError::SizedBuffer)?;
+    let user_hash = buf.pull::<UserIdType>().map_err(LookoutError::SizedBuffer)?;
+    let pass_hash = buf.pull::<PasswordType>().map_err(LookoutError::SizedBuffer)?;
 
     let pool = context.lock().unwrap().pool.clone();
 
-    let future = async move {
-        let user_uuid = Uuid::from_u128(user_hash);
-        let query_result = sqlx::query_as::<_, User>("SELECT * FROM users WHERE user_uuid = $1 LIMIT 1").bind(user_uuid).fetch_optional(&pool).await;
-        match query_result {
-            Ok(Some(user)) => {
-                let pass = Uuid::as_u128(&user.pass_uuid);
-
-                if pass_hash == pass {
-                    info!(user_hash, "ALLOW: {}", user.name);
-                    let auth = Uuid::new_v4().as_u128();
-
-                    if let Ok(out) = move || -> Result<SizedBuffer, SizedBufferError> {
-                        let mut out = SizedBuffer::new(256);
-                        out.push(&op::Route::Any(op::Flavor::Gate))?;
-                        out.push(&op::Command::Authorize)?;
-                        out.push(&drawbridge)?;
-                        out.push(&vagabond)?;
-
-                        out.push(&user_hash)?;
-                        out.push(&auth)?;
-                        out.push(&user.name)?;
-
-                        Ok(out)
-                    }() {
-                        let _ = tx.send(out.into());
-                    }
-                } else {
-                    info!(user_hash, "DENY: {}", user.name);
-                }
-            }
-            Ok(None) => {
-                info!(user_hash, "UNKNOWN");
-            }
-            Err(err) => {
-                info!(user_hash, "ERROR: {:?}", err);
-            }
+    let user_uuid = Uuid::from_u128(user_hash);
+    let query_result = sqlx::query_as::<_, User>("SELECT * FROM users WHERE user_uuid = $1 LIMIT 1").bind(user_uuid).fetch_optional(&pool).await.map_err(LookoutError::Database)?;
+    match query_result {
+        Some(user) if Uuid::as_u128(&user.pass_uuid) == pass_hash => {
+            info!(user_hash, "ALLOW: {}", user.name);
+            let auth = Uuid::new_v4().as_u128();
+
+            let mut out = SizedBuffer::new(256);
+            out.push(&op::Route::Any(op::Flavor::Gate)).map_err(LookoutError::SizedBuffer)?;
+            out.push(&op::Command::Authorize).map_err(LookoutError::SizedBuffer)?;
+            out.push(&drawbridge).map_err(LookoutError::SizedBuffer)?;
+            out.push(&vagabond).map_err(LookoutError::SizedBuffer)?;
+
+            out.push(&user_hash).map_err(LookoutError::SizedBuffer)?;
+            out.push(&auth).map_err(LookoutError::SizedBuffer)?;
+            out.push(&user.name).map_err(LookoutError::SizedBuffer)?;
+
+            tx.send_async(out.into()).await.map_err(|_| LookoutError::Send)
         }
-    };
-    tokio::spawn(future);
-    Ok(())
+        Some(user) => {
+            info!(user_hash, "DENY: {}", user.name);
+            Ok(())
+        }
+        None => {
+            info!(user_hash, "UNKNOWN");
+            Ok(())
+        }
+    }
 }
 
 #[cfg(test)]
@@ -1,57 +1,239 @@
 use proc_macro::TokenStream;
-use quote::quote;
-use syn::{parse_macro_input, DeriveInput, Fields};
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DataEnum, DeriveInput, Expr, ExprLit, Fields, Ident, Lit, LitInt};
 
-#[proc_macro_derive(Bufferable)]
+type TagType = u8;
+
+#[proc_macro_derive(Bufferable, attributes(bufferable))]
 pub fn bufferable_derive(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
-    let name = input.ident;
 
-    if let syn::Data::Struct(ref data) = input.data
-        && let Fields::Named(ref fields) = data.fields
-    {
-        let push_into = fields.named.iter().map(|field| {
-            let name = &field.ident;
-            quote!(pushed += self.#name.push_into(buf)?;)
-        });
-        let pull_from_vars = fields.named.iter().map(|field| {
-            let name = &field.ident;
-            let ty = &field.ty;
-            quote!(let #name = <#ty>::pull_from(buf)?;)
-        });
-        let pull_from_self = fields.named.iter().map(|field| {
-            let name = &field.ident;
-            quote!(#name)
-        });
-        let size_in_buffer = fields.named.iter().map(|field| {
-            let name = &field.ident;
-            quote!(self.#name.size_in_buffer())
-        });
-
-        let output = quote!(
-            impl Bufferable for #name {
-                fn push_into(&self, buf: &mut SizedBuffer) -> Result<usize, SizedBufferError>{
-                    let mut pushed = 0;
-                    #(#push_into)*
-                    Ok(pushed)
-                }
+    let output = match input.data {
+        Data::Struct(ref data) => Ok(derive_struct(&data.fields)),
+        Data::Enum(ref data) => derive_enum(data),
+        Data::Union(_) => Err(syn::Error::new(input.ident.span(), "Only structs and enums can derive `Bufferable`")),
+    };
+
+    let (min_size, push_into, pull_from, size_in_buffer) = match output {
+        Ok(output) => output,
+        Err(err) => return err.to_compile_error().into(),
+    };
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let output = quote!(
+        impl #impl_generics Bufferable for #name #ty_generics #where_clause {
+            const MIN_SIZE: usize = #min_size;
+
+            fn push_into(&self, buf: &mut SizedBuffer) -> Result<usize, SizedBufferError>{
+                let mut pushed = 0;
+                #push_into
+                Ok(pushed)
+            }
+
+            fn pull_from(buf: &mut SizedBuffer) -> Result<Self, SizedBufferError> {
+                #pull_from
+            }
+
+            fn size_in_buffer(&self) -> usize {
+                #size_in_buffer
+            }
+        }
+    );
+
+    output.into()
+}
+
+type Derived = (TokenStream2, TokenStream2, TokenStream2, TokenStream2);
+
+fn derive_struct(fields: &Fields) -> Derived {
+    let members = field_members(fields);
+    let construct = fields_construct(quote!(Self), fields);
+    let types = fields.iter().map(|field| &field.ty);
+
+    let min_size = quote!(
+        0 #(+ <#types as Bufferable>::MIN_SIZE)*
+    );
+
+    let push_into = quote!(
+        #(pushed += self.#members.push_into(buf)?;)*
+    );
+    let pull_from = quote!(
+        let result = #construct;
+        Ok(result)
+    );
+    let size_in_buffer = quote!(
+        0 #(+ self.#members.size_in_buffer())*
+    );
+
+    (min_size, push_into, pull_from, size_in_buffer)
+}
+
+fn derive_enum(data: &DataEnum) -> Result<Derived, syn::Error> {
+    let mut push_arms = Vec::new();
+    let mut pull_arms = Vec::new();
+    let mut size_arms = Vec::new();
+    let mut used_tags = Vec::new();
+    let mut fallback = None;
+    let mut next_tag: u16 = 0;
+
+    for variant in &data.variants {
+        let attrs = VariantAttrs::parse(variant)?;
+
+        let tag = match (attrs.tag, &variant.discriminant) {
+            (Some(tag), _) => tag,
+            (None, Some((_, expr))) => discriminant_value(expr)?,
+            (None, None) => next_tag,
+        };
+        if tag > TagType::MAX as u16 {
+            return Err(syn::Error::new_spanned(variant, format!("Tag {tag} does not fit in `u8`")));
+        }
+        if used_tags.contains(&tag) {
+            return Err(syn::Error::new_spanned(variant, format!("Tag {tag} is used more than once")));
+        }
+        used_tags.push(tag);
+        next_tag = tag + 1;
+
+        let tag = LitInt::new(&format!("{tag}u8"), Span::call_site());
+        let ident = &variant.ident;
+        let bindings = field_bindings(&variant.fields);
+        let pattern = fields_pattern(quote!(Self::#ident), &variant.fields, &bindings);
+        let construct = fields_construct(quote!(Self::#ident), &variant.fields);
+
+        if attrs.default {
+            if !matches!(variant.fields, Fields::Unit) {
+                return Err(syn::Error::new_spanned(variant, "Only unit variants can be the `#[bufferable(default)]`"));
+            }
+            if fallback.is_some() {
+                return Err(syn::Error::new_spanned(variant, "Only one variant can be the `#[bufferable(default)]`"));
+            }
+            fallback = Some(quote!(_ => Self::#ident,));
+        }
 
-                fn pull_from(buf: &mut SizedBuffer) -> Result<Self, SizedBufferError> {
-                    #(#pull_from_vars)*
-                    let result = Self {
-                        #(#pull_from_self),*
-                    };
-                    Ok(result)
+        push_arms.push(quote!(
+            #pattern => {
+                pushed += #tag.push_into(buf)?;
+                #(pushed += #bindings.push_into(buf)?;)*
+            }
+        ));
+        pull_arms.push(quote!(
+            #tag => #construct,
+        ));
+        size_arms.push(quote!(
+            #pattern => 0 #(+ #bindings.size_in_buffer())*,
+        ));
+    }
+
+    let fallback = fallback.unwrap_or_else(|| quote!(_ => return Err(SizedBufferError::UnexpectedEnum(tag)),));
+
+    let push_into = quote!(
+        match self {
+            #(#push_arms)*
+        }
+    );
+    let pull_from = quote!(
+        let tag = u8::pull_from(buf)?;
+        let result = match tag {
+            #(#pull_arms)*
+            #fallback
+        };
+        Ok(result)
+    );
+    // Only the tag is certain, the smallest variant is left for the length checks to discover
+    let min_size = quote!(::core::mem::size_of::<u8>());
+    let size_in_buffer = if data.variants.is_empty() {
+        quote!(match *self {})
+    } else {
+        quote!(
+            ::core::mem::size_of::<u8>()
+                + match self {
+                    #(#size_arms)*
                 }
+        )
+    };
 
-                fn size_in_buffer(&self) -> usize {
-                    #(#size_in_buffer)+*
+    Ok((min_size, push_into, pull_from, size_in_buffer))
+}
+
+#[derive(Default)]
+struct VariantAttrs {
+    tag: Option<u16>,
+    default: bool,
+}
+
+impl VariantAttrs {
+    fn parse(variant: &syn::Variant) -> Result<Self, syn::Error> {
+        let mut result = Self::default();
+        for attr in variant.attrs.iter().filter(|attr| attr.path().is_ident("bufferable")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("tag") {
+                    let tag: LitInt = meta.value()?.parse()?;
+                    result.tag = Some(tag.base10_parse()?);
+                    Ok(())
+                } else if meta.path.is_ident("default") {
+                    result.default = true;
+                    Ok(())
+                } else {
+                    Err(meta.error("Expected `tag = <u8>` or `default`"))
                 }
-            }
-        );
+            })?;
+        }
+        Ok(result)
+    }
+}
 
-        return output.into();
+fn discriminant_value(expr: &Expr) -> Result<u16, syn::Error> {
+    if let Expr::Lit(ExprLit {
+        lit: Lit::Int(lit),
+        ..
+    }) = expr
+    {
+        lit.base10_parse()
+    } else {
+        Err(syn::Error::new_spanned(expr, "Use `#[bufferable(tag = <u8>)]` for non-literal discriminants"))
+    }
+}
+
+fn field_bindings(fields: &Fields) -> Vec<Ident> {
+    match fields {
+        Fields::Named(named) => named.named.iter().filter_map(|field| field.ident.as_ref()).map(|ident| format_ident!("__self_{}", ident)).collect(),
+        Fields::Unnamed(unnamed) => (0..unnamed.unnamed.len()).map(|idx| format_ident!("__self_{}", idx)).collect(),
+        Fields::Unit => Vec::new(),
+    }
+}
+
+fn field_members(fields: &Fields) -> Vec<TokenStream2> {
+    match fields {
+        Fields::Named(named) => named.named.iter().map(|field| field.ident.as_ref()).map(|ident| quote!(#ident)).collect(),
+        Fields::Unnamed(unnamed) => (0..unnamed.unnamed.len()).map(syn::Index::from).map(|idx| quote!(#idx)).collect(),
+        Fields::Unit => Vec::new(),
+    }
+}
+
+fn fields_pattern(path: TokenStream2, fields: &Fields, bindings: &[Ident]) -> TokenStream2 {
+    match fields {
+        Fields::Named(named) => {
+            let names = named.named.iter().map(|field| &field.ident);
+            quote!(#path { #(#names: #bindings),* })
+        }
+        Fields::Unnamed(_) => quote!(#path(#(#bindings),*)),
+        Fields::Unit => quote!(#path),
     }
+}
 
-    TokenStream::from(syn::Error::new(name.span(), "Only structs with named fields can derive `Bufferable`").to_compile_error())
+fn fields_construct(path: TokenStream2, fields: &Fields) -> TokenStream2 {
+    match fields {
+        Fields::Named(named) => {
+            let names = named.named.iter().map(|field| &field.ident);
+            let types = named.named.iter().map(|field| &field.ty);
+            quote!(#path { #(#names: <#types>::pull_from(buf)?),* })
+        }
+        Fields::Unnamed(unnamed) => {
+            let types = unnamed.unnamed.iter().map(|field| &field.ty);
+            quote!(#path(#(<#types>::pull_from(buf)?),*))
+        }
+        Fields::Unit => quote!(#path),
+    }
 }
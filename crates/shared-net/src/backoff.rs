@@ -0,0 +1,57 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use tokio::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Backoff {
+    pub const fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+        }
+    }
+
+    // Doubles per attempt up to `max`, then keeps a random half so clients that lost the same server spread out
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        let ceiling = self.initial.saturating_mul(2_u32.saturating_pow(attempt)).min(self.max);
+        let half = ceiling / 2;
+        half + half.mul_f64(unit_random(attempt))
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(Duration::from_millis(500), Duration::from_secs(30))
+    }
+}
+
+fn unit_random(attempt: u32) -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(attempt);
+    (hasher.finish() >> 11) as f64 / (1_u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::time::Duration;
+
+    use super::Backoff;
+
+    #[test]
+    fn test_delay() {
+        let backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(2));
+        for (attempt, ceiling) in [(0, 100), (1, 200), (2, 400), (4, 1600), (5, 2000), (40, 2000)] {
+            let ceiling = Duration::from_millis(ceiling);
+            for _ in 0..20 {
+                let delay = backoff.delay(attempt);
+                assert!(delay >= ceiling / 2 && delay <= ceiling, "attempt {attempt}: {delay:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use tokio::time::{Duration, Instant};
+
+use crate::{CorrelationType, NodeType, RoutedMessage, op};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum AnyStrategy {
+    First,
+    #[default]
+    RoundRobin,
+    LeastOutstanding,
+}
+
+type Outstanding = HashMap<(NodeType, CorrelationType), Instant>;
+
+// Requests delivered to each connection and not answered yet, by requester and correlation. Shared so a router
+// that passes replies on without the server, like a Courtyard mesh, can settle them too.
+#[derive(Clone)]
+pub struct InFlight {
+    requests: Arc<Mutex<HashMap<NodeType, Outstanding>>>,
+    expiry: Duration,
+}
+
+impl InFlight {
+    // Requesters give up on their own, so a request nobody answers stops counting after `expiry`
+    pub fn new(expiry: Duration) -> Self {
+        Self {
+            requests: Arc::new(Mutex::new(HashMap::new())),
+            expiry,
+        }
+    }
+
+    // Counts a frame delivered to `responder` that starts with [Request(correlation)][requester]
+    pub(crate) fn delivered(&self, responder: NodeType, msg: &RoutedMessage) {
+        let mut buf = msg.buf.clone();
+        buf.rewind();
+        if let Ok(op::Command::Request(correlation)) = buf.pull::<op::Command>()
+            && let Ok(requester) = buf.pull::<NodeType>()
+        {
+            self.lock().entry(responder).or_default().insert((requester, correlation), Instant::now());
+        }
+    }
+
+    // A [Reply(correlation)] on its way back to the requester settles the request with the connection that sent it
+    pub fn answered(&self, msg: &RoutedMessage) {
+        let (Some(responder), op::Route::One(requester)) = (msg.origin, &msg.route) else {
+            return;
+        };
+        let mut buf = msg.buf.clone();
+        buf.rewind();
+        if let Ok(op::Command::Reply(correlation)) = buf.pull::<op::Command>()
+            && let Some(outstanding) = self.lock().get_mut(&responder)
+        {
+            outstanding.remove(&(*requester, correlation));
+        }
+    }
+
+    pub(crate) fn count(&self, responder: NodeType) -> usize {
+        self.lock().get(&responder).map_or(0, HashMap::len)
+    }
+
+    pub(crate) fn forget(&self, responder: NodeType) {
+        self.lock().remove(&responder);
+    }
+
+    pub(crate) fn expire(&self) {
+        self.lock().retain(|_, outstanding| {
+            outstanding.retain(|_, delivered| delivered.elapsed() < self.expiry);
+            !outstanding.is_empty()
+        });
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<NodeType, Outstanding>> {
+        self.requests.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Default for InFlight {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(30))
+    }
+}
+
+pub(crate) struct Balancer {
+    strategy: AnyStrategy,
+    last: HashMap<op::Flavor, NodeType>,
+}
+
+impl Balancer {
+    pub(crate) fn new(strategy: AnyStrategy) -> Self {
+        Self {
+            strategy,
+            last: HashMap::new(),
+        }
+    }
+
+    // candidates are (id, requests in flight) for every connection of the flavor
+    pub(crate) fn select(&mut self, flavor: op::Flavor, candidates: impl Iterator<Item = (NodeType, usize)>) -> Option<NodeType> {
+        let mut candidates = candidates.collect::<Vec<_>>();
+        if candidates.is_empty() {
+            return None;
+        }
+        candidates.sort_unstable_by_key(|(id, _)| *id);
+
+        // start just after the previous pick so ties keep rotating
+        let last = self.last.get(&flavor).copied().unwrap_or(0);
+        let start = candidates.partition_point(|(id, _)| *id <= last) % candidates.len();
+        candidates.rotate_left(start);
+
+        let (id, _) = match self.strategy {
+            AnyStrategy::First => *candidates.iter().min_by_key(|(id, _)| *id)?,
+            AnyStrategy::RoundRobin => candidates[0],
+            AnyStrategy::LeastOutstanding => *candidates.iter().min_by_key(|(_, outstanding)| *outstanding)?,
+        };
+        self.last.insert(flavor, id);
+        Some(id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::time::Duration;
+
+    use super::{AnyStrategy, Balancer, InFlight};
+    use crate::{NodeType, RoutedMessage, SizedBuffer, op};
+
+    // what Courtyard delivers: [Command][sender], routed with the sender as its origin
+    fn frame(route: op::Route, command: op::Command, sender: NodeType) -> RoutedMessage {
+        let mut buf = SizedBuffer::new(32);
+        buf.push(&command).and_then(|_| buf.push(&sender)).unwrap();
+        RoutedMessage::new(route, buf).with_origin(sender)
+    }
+
+    fn picks(balancer: &mut Balancer, candidates: &[(u16, usize)], count: usize) -> Vec<u16> {
+        (0..count).filter_map(|_| balancer.select(op::Flavor::Archive, candidates.iter().copied())).collect()
+    }
+
+    #[test]
+    fn test_first() {
+        let mut balancer = Balancer::new(AnyStrategy::First);
+        assert_eq!(picks(&mut balancer, &[(9, 0), (4, 0), (6, 0)], 3), [4, 4, 4]);
+        assert_eq!(balancer.select(op::Flavor::Archive, std::iter::empty()), None);
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let mut balancer = Balancer::new(AnyStrategy::RoundRobin);
+        assert_eq!(picks(&mut balancer, &[(9, 0), (4, 0), (6, 0)], 5), [4, 6, 9, 4, 6]);
+
+        // a departed connection does not break the rotation
+        assert_eq!(picks(&mut balancer, &[(4, 0), (9, 0)], 3), [9, 4, 9]);
+
+        // flavors rotate independently
+        assert_eq!(balancer.select(op::Flavor::Lookout, [(4, 0), (9, 0)].into_iter()), Some(4));
+    }
+
+    #[test]
+    fn test_least_outstanding() {
+        let mut balancer = Balancer::new(AnyStrategy::LeastOutstanding);
+        assert_eq!(picks(&mut balancer, &[(1, 5), (2, 0), (3, 7)], 2), [2, 2]);
+        assert_eq!(picks(&mut balancer, &[(1, 0), (2, 0), (3, 0)], 4), [3, 1, 2, 3]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_in_flight() {
+        let in_flight = InFlight::new(Duration::from_secs(1));
+        for correlation in 1..=2 {
+            in_flight.delivered(5, &frame(op::Route::Any(op::Flavor::Archive), op::Command::Request(correlation), 9));
+        }
+        in_flight.delivered(5, &frame(op::Route::Any(op::Flavor::Archive), op::Command::Request(1), 8));
+        in_flight.delivered(5, &frame(op::Route::One(5), op::Command::Inventory(1), 9));
+        assert_eq!(in_flight.count(5), 3);
+
+        // a reply only settles the request from the requester it goes back to
+        in_flight.answered(&frame(op::Route::One(9), op::Command::Reply(1), 5));
+        in_flight.answered(&frame(op::Route::One(9), op::Command::Reply(1), 5));
+        assert_eq!(in_flight.count(5), 2);
+        in_flight.answered(&frame(op::Route::One(9), op::Command::Reply(2), 6));
+        assert_eq!(in_flight.count(5), 2);
+
+        tokio::time::advance(Duration::from_secs(2)).await;
+        in_flight.delivered(5, &frame(op::Route::Any(op::Flavor::Archive), op::Command::Request(3), 9));
+        in_flight.expire();
+        assert_eq!(in_flight.count(5), 1);
+
+        in_flight.forget(5);
+        assert_eq!(in_flight.count(5), 0);
+    }
+}
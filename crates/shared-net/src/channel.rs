@@ -0,0 +1,337 @@
+use std::collections::VecDeque;
+use std::pin::pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use tokio::sync::Notify;
+pub use tokio::sync::mpsc::error::SendError;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OverflowPolicy {
+    DropOldest,
+    Disconnect,
+    Block,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QueueConfig {
+    pub capacity: usize,
+    pub policy: OverflowPolicy,
+}
+
+impl QueueConfig {
+    pub const fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        Self {
+            capacity,
+            policy,
+        }
+    }
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self::new(1024, OverflowPolicy::Block)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct QueueStats {
+    pub depth: usize,
+    pub capacity: usize,
+    pub high_water: usize,
+    pub dropped: usize,
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    senders: usize,
+    receiver: bool,
+    closed: bool,
+    high_water: usize,
+    dropped: usize,
+}
+
+struct Shared<T> {
+    config: QueueConfig,
+    state: Mutex<State<T>>,
+    readable: Notify,
+    writable: Notify,
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn stats(&self) -> QueueStats {
+        let state = self.lock();
+        QueueStats {
+            depth: state.queue.len(),
+            capacity: self.config.capacity,
+            high_water: state.high_water,
+            dropped: state.dropped,
+        }
+    }
+}
+
+pub struct VSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub struct VReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub fn bounded<T>(config: QueueConfig) -> (VSender<T>, VReceiver<T>) {
+    let shared = Arc::new(Shared {
+        config,
+        state: Mutex::new(State {
+            queue: VecDeque::with_capacity(config.capacity.min(64)),
+            senders: 1,
+            receiver: true,
+            closed: false,
+            high_water: 0,
+            dropped: 0,
+        }),
+        readable: Notify::new(),
+        writable: Notify::new(),
+    });
+    (
+        VSender {
+            shared: shared.clone(),
+        },
+        VReceiver {
+            shared,
+        },
+    )
+}
+
+enum Admit<T> {
+    Sent,
+    Full(T),
+    Closed(T),
+}
+
+impl<T> VSender<T> {
+    // Never waits: a full `Block` queue refuses the value so synchronous callers can react
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        match self.admit(value) {
+            Admit::Sent => Ok(()),
+            Admit::Full(value) | Admit::Closed(value) => Err(SendError(value)),
+        }
+    }
+
+    pub async fn send_async(&self, mut value: T) -> Result<(), SendError<T>> {
+        loop {
+            let mut writable = pin!(self.shared.writable.notified());
+            writable.as_mut().enable();
+
+            match self.admit(value) {
+                Admit::Sent => return Ok(()),
+                Admit::Closed(returned) => return Err(SendError(returned)),
+                Admit::Full(returned) => value = returned,
+            }
+            writable.await;
+        }
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.shared.stats()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        let state = self.shared.lock();
+        !state.receiver || state.closed
+    }
+
+    // Drops everything still queued and ends the receiver
+    pub fn close(&self) {
+        let mut state = self.shared.lock();
+        state.closed = true;
+        state.queue.clear();
+        drop(state);
+        self.shared.readable.notify_one();
+        self.shared.writable.notify_waiters();
+    }
+
+    fn admit(&self, value: T) -> Admit<T> {
+        let config = self.shared.config;
+        let mut state = self.shared.lock();
+        if !state.receiver || state.closed {
+            return Admit::Closed(value);
+        }
+
+        if state.queue.len() >= config.capacity {
+            match config.policy {
+                OverflowPolicy::DropOldest => {
+                    state.queue.pop_front();
+                    state.dropped += 1;
+                }
+                OverflowPolicy::Disconnect => {
+                    state.closed = true;
+                    state.dropped += state.queue.len() + 1;
+                    state.queue.clear();
+                    drop(state);
+                    self.shared.readable.notify_one();
+                    self.shared.writable.notify_waiters();
+                    return Admit::Closed(value);
+                }
+                OverflowPolicy::Block => return Admit::Full(value),
+            }
+        }
+
+        state.queue.push_back(value);
+        state.high_water = state.high_water.max(state.queue.len());
+        drop(state);
+        self.shared.readable.notify_one();
+        Admit::Sent
+    }
+}
+
+impl<T> Clone for VSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for VSender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            drop(state);
+            self.shared.readable.notify_one();
+        }
+    }
+}
+
+impl<T> VReceiver<T> {
+    // Cancel safe: a value is only removed from the queue when it is returned
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            let mut readable = pin!(self.shared.readable.notified());
+            readable.as_mut().enable();
+
+            {
+                let mut state = self.shared.lock();
+                if state.closed {
+                    return None;
+                }
+                if let Some(value) = state.queue.pop_front() {
+                    drop(state);
+                    self.shared.writable.notify_one();
+                    return Some(value);
+                }
+                if state.senders == 0 {
+                    return None;
+                }
+            }
+            readable.await;
+        }
+    }
+
+    pub fn try_recv(&mut self) -> Option<T> {
+        let mut state = self.shared.lock();
+        let value = if state.closed { None } else { state.queue.pop_front() };
+        drop(state);
+        if value.is_some() {
+            self.shared.writable.notify_one();
+        }
+        value
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.shared.stats()
+    }
+}
+
+impl<T> Drop for VReceiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.receiver = false;
+        state.queue.clear();
+        drop(state);
+        self.shared.writable.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::time::{Duration, timeout};
+
+    use super::{OverflowPolicy, QueueConfig, bounded};
+
+    #[tokio::test]
+    async fn test_fifo() {
+        let (tx, mut rx) = bounded(QueueConfig::new(4, OverflowPolicy::Block));
+        for idx in 0..4 {
+            tx.send(idx).unwrap();
+        }
+        assert_eq!(tx.stats().depth, 4);
+
+        for idx in 0..4 {
+            assert_eq!(rx.recv().await, Some(idx));
+        }
+        assert_eq!(rx.try_recv(), None);
+        drop(tx);
+        assert_eq!(rx.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest() {
+        let (tx, mut rx) = bounded(QueueConfig::new(2, OverflowPolicy::DropOldest));
+        for idx in 0..5 {
+            tx.send(idx).unwrap();
+        }
+
+        let stats = rx.stats();
+        assert_eq!((stats.depth, stats.high_water, stats.dropped), (2, 2, 3));
+        assert_eq!(rx.recv().await, Some(3));
+        assert_eq!(rx.recv().await, Some(4));
+    }
+
+    #[tokio::test]
+    async fn test_disconnect() {
+        let (tx, mut rx) = bounded(QueueConfig::new(2, OverflowPolicy::Disconnect));
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        assert_eq!(tx.send(3).err().map(|err| err.0), Some(3));
+
+        assert!(tx.is_closed());
+        assert_eq!(rx.recv().await, None);
+        assert!(tx.send(4).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_block() {
+        let (tx, mut rx) = bounded(QueueConfig::new(1, OverflowPolicy::Block));
+        tx.send(1).unwrap();
+        assert!(tx.send(2).is_err());
+
+        let blocked = tx.clone();
+        let sender = tokio::spawn(async move { blocked.send_async(2).await });
+        tokio::task::yield_now().await;
+        assert!(!sender.is_finished());
+
+        assert_eq!(rx.recv().await, Some(1));
+        timeout(Duration::from_secs(1), sender).await.unwrap().unwrap().unwrap();
+        assert_eq!(rx.recv().await, Some(2));
+        assert_eq!(rx.stats().dropped, 0);
+    }
+
+    #[tokio::test]
+    async fn test_receiver_dropped() {
+        let (tx, rx) = bounded(QueueConfig::new(1, OverflowPolicy::Block));
+        tx.send(1).unwrap();
+
+        let blocked = tx.clone();
+        let sender = tokio::spawn(async move { blocked.send_async(2).await });
+        tokio::task::yield_now().await;
+        drop(rx);
+
+        assert!(timeout(Duration::from_secs(1), sender).await.unwrap().unwrap().is_err());
+        assert!(tx.is_closed());
+    }
+}
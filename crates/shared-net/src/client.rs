@@ -1,15 +1,22 @@
-use std::io::ErrorKind;
-use std::net::{SocketAddr, ToSocketAddrs};
+use std::collections::VecDeque;
+use std::pin::pin;
+use std::sync::Arc;
 
-use crate::op;
+use crate::backoff::Backoff;
+use crate::channel::{VReceiver, VSender};
+use crate::framing::FrameReader;
+use crate::handler::{HandlerStats, Handlers, Process};
+use crate::heartbeat::{Heartbeat, beat};
+use crate::registration::Secret;
+use crate::request::Requester;
+use crate::shutdown::{CancellationToken, DRAIN_LIMIT};
+use crate::transport::{self, BoxedStream, Endpoint};
 use crate::util::write_buf;
 use crate::{RoutedMessage, SizedBuffer};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
-use tokio::signal;
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
-use tokio::time::{Duration, sleep};
-use tracing::error;
+use crate::{op, tls};
+use tokio::io::{AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::time::{Instant, MissedTickBehavior, interval_at, sleep, timeout};
+use tracing::{debug, error, warn};
 
 #[derive(PartialEq)]
 pub enum VClientMode {
@@ -18,88 +25,609 @@ pub enum VClientMode {
     Shutdown,
 }
 
-type FnProcess<T> = fn(context: T, UnboundedSender<RoutedMessage>, msg: SizedBuffer) -> VClientMode;
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VClientEvent {
+    Connected,
+    Disconnected,
+}
+
+#[derive(Clone)]
+pub struct VClientConfig {
+    pub interface: String,
+    pub tls: Option<Arc<tls::ClientConfig>>,
+    pub server_name: Option<String>,
+    pub heartbeat: Heartbeat,
+    pub backoff: Backoff,
+    pub replay: usize,
+    pub requester: Option<Requester>,
+    pub shutdown: CancellationToken,
+    pub handler_stats: HandlerStats,
+    pub secret: Option<Secret>,
+}
+
+impl VClientConfig {
+    pub fn new(interface: String) -> Self {
+        Self {
+            interface,
+            tls: None,
+            server_name: None,
+            heartbeat: Heartbeat::default(),
+            backoff: Backoff::default(),
+            replay: 256,
+            requester: None,
+            shutdown: CancellationToken::new(),
+            handler_stats: HandlerStats::default(),
+            secret: None,
+        }
+    }
+
+    pub fn with_tls(mut self, tls: Arc<tls::ClientConfig>) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    pub fn with_server_name(mut self, server_name: String) -> Self {
+        self.server_name = Some(server_name);
+        self
+    }
+
+    pub fn with_heartbeat(mut self, heartbeat: Heartbeat) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    // How many outgoing messages are kept while disconnected, the oldest are dropped first
+    pub fn with_replay(mut self, replay: usize) -> Self {
+        self.replay = replay;
+        self
+    }
+
+    // Replies to this requester are taken off the connection before `process` sees them
+    pub fn with_requester(mut self, requester: Requester) -> Self {
+        self.requester = Some(requester);
+        self
+    }
+
+    pub fn with_shutdown(mut self, shutdown: CancellationToken) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    pub fn with_handler_stats(mut self, handler_stats: HandlerStats) -> Self {
+        self.handler_stats = handler_stats;
+        self
+    }
+
+    // Answers the server's registration challenge, servers that don't ask never see it
+    pub fn with_secret(mut self, secret: Secret) -> Self {
+        self.secret = Some(secret);
+        self
+    }
+
+    fn server_name<'a>(&'a self, interface: &'a str) -> &'a str {
+        self.server_name.as_deref().unwrap_or_else(|| transport::host(interface))
+    }
+}
+
+impl From<String> for VClientConfig {
+    fn from(value: String) -> Self {
+        Self::new(value)
+    }
+}
+
+type FnEvent<T> = fn(context: T, VSender<RoutedMessage>, event: VClientEvent);
+
+type ClientConnection = (FrameReader<ReadHalf<BoxedStream>>, WriteHalf<BoxedStream>, SizedBuffer);
+
+// Outgoing messages held while there is no connection, written again after the next Register
+struct Replay {
+    queue: VecDeque<SizedBuffer>,
+    capacity: usize,
+    dropped: usize,
+}
+
+impl Replay {
+    fn new(capacity: usize) -> Self {
+        Self {
+            queue: VecDeque::new(),
+            capacity,
+            dropped: 0,
+        }
+    }
 
-pub async fn async_client<T>(context: T, flavor: op::Flavor, external_tx: UnboundedSender<RoutedMessage>, mut external_rx: UnboundedReceiver<RoutedMessage>, interface: String, process: FnProcess<T>) -> Result<(), ()>
+    fn push(&mut self, buf: SizedBuffer) {
+        if self.queue.len() >= self.capacity {
+            self.dropped += 1;
+            if self.queue.pop_front().is_none() {
+                return;
+            }
+        }
+        self.queue.push_back(buf);
+    }
+
+    async fn flush<W>(&mut self, write: &mut W) -> Result<(), ()>
+    where
+        W: Unpin + AsyncWrite,
+    {
+        if self.dropped > 0 {
+            warn!(dropped = self.dropped, "Replay overflowed while disconnected");
+            self.dropped = 0;
+        }
+        while let Some(buf) = self.queue.front() {
+            write_buf(write, buf).await.map_err(|_| ())?;
+            self.queue.pop_front();
+        }
+        Ok(())
+    }
+}
+
+pub async fn async_client<T>(context: T, flavor: op::Flavor, external_tx: VSender<RoutedMessage>, mut external_rx: VReceiver<RoutedMessage>, config: impl Into<VClientConfig>, process: impl Process<T, SizedBuffer, VClientMode>, event: FnEvent<T>) -> Result<(), ()>
 where
     T: Clone,
 {
-    let mut addr = interface.to_socket_addrs().expect("Invalid interface for async_client");
-    let addr = addr.next().unwrap();
+    let config = config.into();
+    // several interfaces separated by commas are tried in turn, so a service can fall back to another Courtyard
+    let addrs = config.interface.split(',').map(|interface| Endpoint::resolve(interface).map(|addr| (interface, addr))).collect::<Result<Vec<_>, _>>().expect("Invalid interface for async_client");
+    let mut replay = Replay::new(config.replay);
+    let mut handlers = Handlers::new(config.handler_stats.clone());
 
-    while let Some(mut active_connection) = handle_client_connection(&addr, flavor).await {
-        let mut buf = [0_u8; SizedBuffer::sizesize()];
-        let mode = loop {
+    loop {
+        let mut connecting = pin!(handle_client_connection(&addrs, &config, flavor));
+        let connection = loop {
             tokio::select! {
-                read_result = active_connection.read_exact(&mut buf[..]) => {
-                    match read_result {
-                        Ok(bytes) => {
-                            if bytes == SizedBuffer::sizesize() {
-                                let expected_bytes = SizedBuffer::extract_size(&buf);
-                                let mut sized_buf = SizedBuffer::new(expected_bytes);
-                                match active_connection.read_exact(&mut sized_buf.raw[SizedBuffer::sizesize()..]).await {
-                                    Ok(bytes) => {
-                                        if bytes != expected_bytes {
-                                            error!("Bytes:{} Expected:{}", bytes, expected_bytes);
-                                            break VClientMode::Shutdown;
+                connection = &mut connecting => break connection,
+                Some(msg) = external_rx.recv() => replay.push(msg.buf),
+                _ = config.shutdown.cancelled() => return Ok(()),
+            }
+        };
+        let Some((addr, (mut frames, mut write, hello))) = connection else {
+            break;
+        };
+        event(context.clone(), external_tx.clone(), VClientEvent::Connected);
+
+        let mut last_seen = Instant::now();
+        let mut heartbeat = interval_at(last_seen + config.heartbeat.interval, config.heartbeat.interval);
+        heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        let mut mode = handlers.run(process.process(context.clone(), external_tx.clone(), hello), VClientMode::Continue);
+        if mode == VClientMode::Continue && replay.flush(&mut write).await.is_err() {
+            mode = VClientMode::Disconnect;
+        }
+
+        let mode = match mode {
+            VClientMode::Continue => loop {
+                tokio::select! {
+                    read_result = frames.next_frame() => {
+                        match read_result {
+                            Ok(Some(mut sized_buf)) => {
+                                last_seen = Instant::now();
+                                let mode = match sized_buf.pull::<op::Command>() {
+                                    Ok(op::Command::Ping) => match write_buf(&mut write, &beat(op::Command::Pong)).await {
+                                        Ok(_) => VClientMode::Continue,
+                                        Err(_) => VClientMode::Disconnect,
+                                    },
+                                    Ok(op::Command::Pong) => VClientMode::Continue,
+                                    Ok(op::Command::Reply(correlation)) if let Some(requester) = &config.requester => {
+                                        if !requester.resolve(correlation, sized_buf) {
+                                            debug!(correlation, "Late reply");
                                         }
-                                        sized_buf.set_size(expected_bytes);
-                                        let mode = process(context.clone(), external_tx.clone(), sized_buf);
-                                        if mode != VClientMode::Continue {
-                                            break mode;
+                                        VClientMode::Continue
+                                    }
+                                    Ok(op::Command::Undeliverable)
+                                        if let Some(requester) = &config.requester
+                                            && let Ok(notice) = sized_buf.clone().pull::<op::Undeliverable>()
+                                            && let op::Command::Request(correlation) = notice.command =>
+                                    {
+                                        if !requester.fail(correlation, notice.reason) {
+                                            debug!(correlation, "Late undeliverable");
                                         }
+                                        VClientMode::Continue
                                     }
-                                    Err(_) => {
-                                        break VClientMode::Shutdown;
+                                    _ => {
+                                        sized_buf.rewind();
+                                        handlers.run(process.process(context.clone(), external_tx.clone(), sized_buf), VClientMode::Continue)
                                     }
+                                };
+                                if mode != VClientMode::Continue {
+                                    break mode;
                                 }
-                            } else if bytes == 0 {
-                                break VClientMode::Shutdown;
+                            }
+                            Ok(None) => break VClientMode::Disconnect,
+                            Err(err) => {
+                                error!(?err);
+                                break VClientMode::Disconnect;
                             }
                         }
-                        Err(ref err) if err.kind() == ErrorKind::WouldBlock => {}
-                        Err(_) => break VClientMode::Shutdown,
                     }
-                }
-                Some(msg) = external_rx.recv() => {
-                    if write_buf(&mut active_connection, &msg.buf).await.is_err() {
+                    Some(msg) = external_rx.recv() => {
+                        if write_buf(&mut write, &msg.buf).await.is_err() {
+                            // the peer may have seen none of it, so it goes out again on the next connection
+                            replay.push(msg.buf);
+                            break VClientMode::Disconnect;
+                        }
+                    }
+                    _ = heartbeat.tick() => {
+                        if config.heartbeat.is_expired(last_seen) {
+                            error!("Timed out {}", addr);
+                            break VClientMode::Disconnect;
+                        }
+                        if write_buf(&mut write, &beat(op::Command::Ping)).await.is_err() {
+                            break VClientMode::Disconnect;
+                        }
+                    }
+                    Some(()) = handlers.next() => {}
+                    _ = config.shutdown.cancelled() => {
+                        handlers.drain().await;
+                        // only what is queued right now, `process` may keep adding
+                        let pending = external_rx.stats().depth;
+                        let _ = timeout(DRAIN_LIMIT, async {
+                            for _ in 0..pending {
+                                match external_rx.try_recv() {
+                                    Some(msg) if write_buf(&mut write, &msg.buf).await.is_ok() => {}
+                                    _ => break,
+                                }
+                            }
+                        })
+                        .await;
                         break VClientMode::Shutdown;
                     }
                 }
-                _ = signal::ctrl_c() => {
-                    break VClientMode::Shutdown;
-                }
-            }
+            },
+            mode => mode,
         };
 
         if mode != VClientMode::Continue {
-            let _ = active_connection.shutdown().await;
+            if let Some(requester) = &config.requester {
+                requester.cancel_all();
+            }
+            let _ = write.shutdown().await;
+            event(context.clone(), external_tx.clone(), VClientEvent::Disconnected);
+            if config.shutdown.is_cancelled() {
+                return Ok(());
+            }
             if mode == VClientMode::Shutdown {
                 return Err(());
             }
         }
     }
 
-    Ok(())
+    Err(())
 }
 
-async fn handle_client_connection(addr: &SocketAddr, flavor: op::Flavor) -> Option<TcpStream> {
+async fn handle_client_connection<'a>(addrs: &'a [(&str, Endpoint)], config: &VClientConfig, flavor: op::Flavor) -> Option<(&'a Endpoint, ClientConnection)> {
+    let mut attempt = 0;
     loop {
-        if let Ok(mut stream) = TcpStream::connect(addr).await {
-            let mut buf = SizedBuffer::new(32);
-            buf.push(&op::Command::Register).ok()?;
-            buf.push(&flavor).ok()?;
-
-            if write_buf(&mut stream, &buf).await.is_err() {
-                let _ = stream.shutdown().await;
-                break;
-            } else {
-                return Some(stream);
+        let (interface, addr) = &addrs[attempt as usize % addrs.len()];
+        match try_connect(addr, config.server_name(interface), config, flavor).await {
+            Ok(connection) => return Some((addr, connection)),
+            Err(Some(reason)) => {
+                error!("Rejected by {}: {}", addr, reason);
+                return None;
             }
-        } else {
-            sleep(Duration::from_secs(5)).await;
+            Err(None) => {}
         }
+        sleep(config.backoff.delay(attempt)).await;
+        attempt = attempt.saturating_add(1);
+    }
+}
+
+// Err(None) is worth another attempt, a reject is not
+async fn try_connect(addr: &Endpoint, server_name: &str, config: &VClientConfig, flavor: op::Flavor) -> Result<ClientConnection, Option<op::RejectReason>> {
+    let stream = addr.open().await.map_err(|_| None)?;
+    let stream = transport::connect(stream, config.tls.clone(), server_name).await.map_err(|err| {
+        error!(%addr, ?err);
+        None
+    })?;
+    let (read, mut write) = tokio::io::split(stream);
+
+    let mut buf = SizedBuffer::new(32);
+    buf.push(&op::Command::Register).map_err(|_| None)?;
+    buf.push(&flavor).map_err(|_| None)?;
+    buf.push(&op::Handshake::default()).map_err(|_| None)?;
+    write_buf(&mut write, &buf).await.map_err(|_| None)?;
+
+    let mut frames = FrameReader::new(read);
+    let mut reply = handshake_frame(&mut frames, config.heartbeat).await?;
+    if let Some(nonce) = check_challenge(&mut reply) {
+        let Some(secret) = &config.secret else {
+            let _ = write.shutdown().await;
+            return Err(Some(op::RejectReason::Unauthorized));
+        };
+        let mut proof = SizedBuffer::new(64);
+        proof.push(&op::Command::Challenge).map_err(|_| None)?;
+        proof.push(&secret.prove(flavor, nonce)).map_err(|_| None)?;
+        write_buf(&mut write, &proof).await.map_err(|_| None)?;
+        reply = handshake_frame(&mut frames, config.heartbeat).await?;
     }
 
-    None
+    match check_hello(reply) {
+        Ok(hello) => Ok((frames, write, hello)),
+        Err(reason) => {
+            let _ = write.shutdown().await;
+            Err(Some(reason))
+        }
+    }
+}
+
+// A server that accepts and then says nothing is worth another attempt, not a wait forever.
+// Heartbeats are not part of the handshake, so they are passed over rather than taken for a malformed reply.
+async fn handshake_frame(frames: &mut FrameReader<ReadHalf<BoxedStream>>, heartbeat: Heartbeat) -> Result<SizedBuffer, Option<op::RejectReason>> {
+    let next = async {
+        while let Some(mut buf) = frames.next_frame().await.ok().flatten() {
+            match buf.pull::<op::Command>() {
+                Ok(op::Command::Ping | op::Command::Pong) => continue,
+                _ => {
+                    buf.rewind();
+                    return Some(buf);
+                }
+            }
+        }
+        None
+    };
+    timeout(heartbeat.timeout, next).await.ok().flatten().ok_or(None)
+}
+
+fn check_challenge(buf: &mut SizedBuffer) -> Option<op::NonceType> {
+    let nonce = match buf.pull::<op::Command>() {
+        Ok(op::Command::Challenge) => buf.pull::<op::NonceType>().ok(),
+        _ => None,
+    };
+    buf.rewind();
+    nonce
+}
+
+fn check_hello(mut buf: SizedBuffer) -> Result<SizedBuffer, op::RejectReason> {
+    match buf.pull::<op::Command>() {
+        Ok(op::Command::Hello) => {
+            let handshake = buf.pull::<op::Handshake>().map_err(|_| op::RejectReason::Malformed)?;
+            op::Handshake::default().negotiate(&handshake)?;
+            buf.rewind();
+            Ok(buf)
+        }
+        Ok(op::Command::Reject) => Err(buf.pull::<op::RejectReason>().unwrap_or(op::RejectReason::Malformed)),
+        _ => Err(op::RejectReason::Malformed),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::io::{ReadHalf, WriteHalf};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::mpsc::{self, UnboundedSender};
+    use tokio::time::{Duration, timeout};
+
+    use super::{VClientConfig, VClientEvent, VClientMode, async_client, check_hello};
+    use crate::channel::{QueueConfig, VSender, bounded};
+    use crate::framing::FrameReader;
+    use crate::heartbeat::beat;
+    use crate::util::write_buf;
+    use crate::{Backoff, CancellationToken, Heartbeat, RoutedMessage, Secret, SizedBuffer, op};
+
+    #[test]
+    fn test_check_hello() {
+        let mut hello = SizedBuffer::new(32);
+        hello.push(&op::Command::Hello).unwrap();
+        hello.push(&op::Handshake::default()).unwrap();
+        let mut result = check_hello(hello).unwrap();
+        assert_eq!(result.pull::<op::Command>().unwrap(), op::Command::Hello);
+
+        let mut old_hello = SizedBuffer::new(32);
+        old_hello.push(&op::Command::Hello).unwrap();
+        assert!(matches!(check_hello(old_hello), Err(op::RejectReason::Malformed)));
+
+        let reason = op::RejectReason::Version {
+            expected: op::PROTOCOL_VERSION + 1,
+            received: op::PROTOCOL_VERSION,
+        };
+        let mut reject = SizedBuffer::new(32);
+        reject.push(&op::Command::Reject).unwrap();
+        reject.push(&reason).unwrap();
+        assert_eq!(check_hello(reject).err(), Some(reason));
+    }
+
+    fn ignore(_context: (), _tx: VSender<RoutedMessage>, _buf: SizedBuffer) -> VClientMode {
+        VClientMode::Continue
+    }
+
+    fn events(context: UnboundedSender<VClientEvent>, _tx: VSender<RoutedMessage>, event: VClientEvent) {
+        let _ = context.send(event);
+    }
+
+    async fn accept_hello(listener: &TcpListener) -> (FrameReader<ReadHalf<TcpStream>>, WriteHalf<TcpStream>) {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (read, mut write) = tokio::io::split(stream);
+        let mut frames = FrameReader::new(read);
+
+        let mut register = frames.next_frame().await.unwrap().unwrap();
+        assert_eq!(register.pull::<op::Command>().unwrap(), op::Command::Register);
+
+        let mut hello = SizedBuffer::new(32);
+        hello.push(&op::Command::Hello).unwrap();
+        hello.push(&op::Handshake::default()).unwrap();
+        write_buf(&mut write, &hello).await.unwrap();
+        (frames, write)
+    }
+
+    fn received(context: UnboundedSender<SizedBuffer>, _tx: VSender<RoutedMessage>, buf: SizedBuffer) -> VClientMode {
+        let _ = context.send(buf);
+        VClientMode::Continue
+    }
+
+    #[tokio::test]
+    async fn test_silent_server_reconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let interface = listener.local_addr().unwrap().to_string();
+        let heartbeat = Heartbeat::new(Duration::from_millis(50), Duration::from_millis(200));
+        let config = VClientConfig::new(interface).with_heartbeat(heartbeat);
+
+        let (client_tx, _client_rx) = bounded(QueueConfig::default());
+        let (_external_tx, external_rx) = bounded(QueueConfig::default());
+        tokio::spawn(async_client((), op::Flavor::Gate, client_tx, external_rx, config, ignore, |_, _, _| {}));
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let (read, mut write) = tokio::io::split(stream);
+        let mut frames = FrameReader::new(read);
+
+        let mut register = frames.next_frame().await.unwrap().unwrap();
+        assert_eq!(register.pull::<op::Command>().unwrap(), op::Command::Register);
+
+        let mut hello = SizedBuffer::new(32);
+        hello.push(&op::Command::Hello).unwrap();
+        hello.push(&op::Handshake::default()).unwrap();
+        write_buf(&mut write, &hello).await.unwrap();
+
+        let mut ping = frames.next_frame().await.unwrap().unwrap();
+        assert_eq!(ping.pull::<op::Command>().unwrap(), op::Command::Ping);
+
+        // never answer, the client should give up on this connection and dial again
+        let reconnect = timeout(Duration::from_secs(10), listener.accept()).await.unwrap();
+        assert!(reconnect.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_silent_handshake_reconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let interface = listener.local_addr().unwrap().to_string();
+        let heartbeat = Heartbeat::new(Duration::from_millis(50), Duration::from_millis(200));
+        let config = VClientConfig::new(interface).with_heartbeat(heartbeat).with_backoff(Backoff::new(Duration::from_millis(10), Duration::from_millis(50)));
+
+        let (client_tx, _client_rx) = bounded(QueueConfig::default());
+        let (_external_tx, external_rx) = bounded(QueueConfig::default());
+        tokio::spawn(async_client((), op::Flavor::Gate, client_tx, external_rx, config, ignore, |_, _, _| {}));
+
+        // take the Register and never send Hello
+        let (stream, _) = listener.accept().await.unwrap();
+        let (read, _write) = tokio::io::split(stream);
+        let mut frames = FrameReader::new(read);
+        let mut register = frames.next_frame().await.unwrap().unwrap();
+        assert_eq!(register.pull::<op::Command>().unwrap(), op::Command::Register);
+
+        let reconnect = timeout(Duration::from_secs(10), listener.accept()).await.unwrap();
+        assert!(reconnect.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_handshake_skips_heartbeats() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let interface = listener.local_addr().unwrap().to_string();
+        let secret = Secret::new(b"courtyard");
+        let config = VClientConfig::new(interface).with_secret(secret.clone());
+
+        let (client_tx, _client_rx) = bounded(QueueConfig::default());
+        let (_external_tx, external_rx) = bounded(QueueConfig::default());
+        let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async_client(events_tx, op::Flavor::Hall, client_tx, external_rx, config, |_, _, _| VClientMode::Continue, events));
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let (read, mut write) = tokio::io::split(stream);
+        let mut frames = FrameReader::new(read);
+        let _register = frames.next_frame().await.unwrap().unwrap();
+
+        let nonce = 7;
+        let mut challenge = SizedBuffer::new(32);
+        challenge.push(&op::Command::Challenge).unwrap();
+        challenge.push(&nonce).unwrap();
+        write_buf(&mut write, &beat(op::Command::Ping)).await.unwrap();
+        write_buf(&mut write, &challenge).await.unwrap();
+
+        let mut proof = frames.next_frame().await.unwrap().unwrap();
+        assert_eq!(proof.pull::<op::Command>().unwrap(), op::Command::Challenge);
+        assert!(secret.verify(op::Flavor::Hall, nonce, &proof.pull::<op::ProofType>().unwrap()));
+
+        let mut hello = SizedBuffer::new(32);
+        hello.push(&op::Command::Hello).unwrap();
+        hello.push(&op::Handshake::default()).unwrap();
+        write_buf(&mut write, &beat(op::Command::Pong)).await.unwrap();
+        write_buf(&mut write, &hello).await.unwrap();
+        assert_eq!(timeout(Duration::from_secs(10), events_rx.recv()).await.unwrap(), Some(VClientEvent::Connected));
+    }
+
+    #[tokio::test]
+    async fn test_fallback_interface() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let interface = format!("{closed},{}", listener.local_addr().unwrap());
+        let config = VClientConfig::new(interface).with_backoff(Backoff::new(Duration::from_millis(10), Duration::from_millis(50)));
+
+        let (client_tx, _client_rx) = bounded(QueueConfig::default());
+        let (_external_tx, external_rx) = bounded(QueueConfig::default());
+        let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async_client(events_tx, op::Flavor::Gate, client_tx, external_rx, config, |_, _, _| VClientMode::Continue, events));
+
+        let _connection = timeout(Duration::from_secs(10), accept_hello(&listener)).await.unwrap();
+        assert_eq!(timeout(Duration::from_secs(10), events_rx.recv()).await.unwrap(), Some(VClientEvent::Connected));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_flushes() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let interface = listener.local_addr().unwrap().to_string();
+        let shutdown = CancellationToken::new();
+        let config = VClientConfig::new(interface).with_shutdown(shutdown.clone());
+
+        let (client_tx, _client_rx) = bounded(QueueConfig::default());
+        let (external_tx, external_rx) = bounded(QueueConfig::default());
+        let (received_tx, mut received_rx) = mpsc::unbounded_channel();
+        let client = tokio::spawn(async_client(received_tx, op::Flavor::Gate, client_tx, external_rx, config, received, |_, _, _| {}));
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let (read, mut write) = tokio::io::split(stream);
+        let mut frames = FrameReader::new(read);
+        let _register = frames.next_frame().await.unwrap().unwrap();
+
+        let mut hello = SizedBuffer::new(32);
+        hello.push(&op::Command::Hello).unwrap();
+        hello.push(&op::Handshake::default()).unwrap();
+        write_buf(&mut write, &hello).await.unwrap();
+        let _hello = timeout(Duration::from_secs(10), received_rx.recv()).await.unwrap().unwrap();
+
+        for idx in 0..3 {
+            external_tx.send(RoutedMessage::local(SizedBuffer::from(&op::Command::Message(idx)).unwrap())).unwrap();
+        }
+        shutdown.cancel();
+        assert_eq!(timeout(Duration::from_secs(10), client).await.unwrap().unwrap(), Ok(()));
+
+        for idx in 0..3 {
+            let mut buf = frames.next_frame().await.unwrap().unwrap();
+            assert_eq!(buf.pull::<op::Command>().unwrap(), op::Command::Message(idx));
+        }
+        assert!(frames.next_frame().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_replay_after_reconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let interface = listener.local_addr().unwrap().to_string();
+        let config = VClientConfig::new(interface).with_backoff(Backoff::new(Duration::from_millis(10), Duration::from_millis(50))).with_replay(2);
+
+        let (client_tx, _client_rx) = bounded(QueueConfig::default());
+        let (external_tx, external_rx) = bounded(QueueConfig::default());
+        let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async_client(events_tx, op::Flavor::Gate, client_tx, external_rx, config, |_, _, _| VClientMode::Continue, events));
+
+        let first = accept_hello(&listener).await;
+        assert_eq!(timeout(Duration::from_secs(10), events_rx.recv()).await.unwrap(), Some(VClientEvent::Connected));
+        drop(first);
+        assert_eq!(timeout(Duration::from_secs(10), events_rx.recv()).await.unwrap(), Some(VClientEvent::Disconnected));
+
+        // queued while there is no connection, only the newest two fit
+        for idx in 0..3 {
+            external_tx.send(RoutedMessage::local(SizedBuffer::from(&op::Command::Message(idx)).unwrap())).unwrap();
+        }
+        while external_tx.stats().depth > 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let (mut frames, _write) = accept_hello(&listener).await;
+        assert_eq!(timeout(Duration::from_secs(10), events_rx.recv()).await.unwrap(), Some(VClientEvent::Connected));
+        for idx in 1..3 {
+            let mut buf = frames.next_frame().await.unwrap().unwrap();
+            assert_eq!(buf.pull::<op::Command>().unwrap(), op::Command::Message(idx));
+        }
+    }
 }
@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+use crate::{NodeType, op};
+
+type Nodes = HashMap<NodeType, op::Flavor>;
+
+// The flavor of every registered connection, kept by the server for whoever routes beside it
+#[derive(Clone)]
+pub struct Directory {
+    nodes: Arc<watch::Sender<Nodes>>,
+}
+
+impl Directory {
+    pub fn flavor(&self, id: NodeType) -> Option<op::Flavor> {
+        self.nodes.borrow().get(&id).copied()
+    }
+
+    pub fn offers(&self, flavor: op::Flavor) -> bool {
+        self.nodes.borrow().values().any(|registered| *registered == flavor)
+    }
+
+    pub fn flavors(&self) -> Vec<op::Flavor> {
+        let mut flavors = self.nodes.borrow().values().copied().collect::<Vec<_>>();
+        flavors.sort_by_key(|flavor| u8::from(*flavor));
+        flavors.dedup();
+        flavors
+    }
+
+    // Resolves `changed()` whenever a connection registers or goes away
+    pub fn watch(&self) -> watch::Receiver<Nodes> {
+        self.nodes.subscribe()
+    }
+
+    pub(crate) fn insert(&self, id: NodeType, flavor: op::Flavor) {
+        self.nodes.send_modify(|nodes| {
+            nodes.insert(id, flavor);
+        });
+    }
+
+    pub(crate) fn remove(&self, id: NodeType) {
+        self.nodes.send_if_modified(|nodes| nodes.remove(&id).is_some());
+    }
+
+    pub(crate) fn clear(&self) {
+        self.nodes.send_if_modified(|nodes| {
+            let modified = !nodes.is_empty();
+            nodes.clear();
+            modified
+        });
+    }
+}
+
+impl Default for Directory {
+    fn default() -> Self {
+        Self {
+            nodes: Arc::new(watch::Sender::new(Nodes::new())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Directory;
+    use crate::op;
+
+    #[test]
+    fn test_directory() {
+        let directory = Directory::default();
+        let mut changes = directory.watch();
+
+        directory.insert(3, op::Flavor::Hall);
+        directory.insert(1, op::Flavor::Lookout);
+        directory.insert(2, op::Flavor::Hall);
+        assert!(changes.has_changed().unwrap());
+        changes.mark_unchanged();
+
+        assert_eq!(directory.flavor(2), Some(op::Flavor::Hall));
+        assert_eq!(directory.flavors(), vec![op::Flavor::Hall, op::Flavor::Lookout]);
+
+        directory.remove(7);
+        assert!(!changes.has_changed().unwrap());
+        directory.remove(1);
+        assert!(changes.has_changed().unwrap());
+        assert!(!directory.offers(op::Flavor::Lookout));
+
+        directory.clear();
+        assert_eq!(directory.flavor(3), None);
+    }
+}
@@ -0,0 +1,150 @@
+use std::io::{Error, ErrorKind};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::SizedBuffer;
+
+const READ_CHUNK: usize = 4096;
+
+pub(crate) struct FrameReader<R> {
+    read: R,
+    pending: Vec<u8>,
+}
+
+impl<R> FrameReader<R>
+where
+    R: Unpin + AsyncRead,
+{
+    pub(crate) fn new(read: R) -> Self {
+        Self {
+            read,
+            pending: Vec::with_capacity(READ_CHUNK),
+        }
+    }
+
+    // Cancel safe: bytes are moved into `pending` before the next await point.
+    pub(crate) async fn next_frame(&mut self) -> Result<Option<SizedBuffer>, Error> {
+        let mut chunk = [0_u8; READ_CHUNK];
+        loop {
+            if let Some(frame) = self.take_frame()? {
+                return Ok(Some(frame));
+            }
+
+            let bytes = self.read.read(&mut chunk).await?;
+            if bytes == 0 {
+                return if self.pending.is_empty() {
+                    Ok(None)
+                } else {
+                    Err(Error::new(ErrorKind::UnexpectedEof, format!("Bytes:{} Partial frame", self.pending.len())))
+                };
+            }
+            self.pending.extend_from_slice(&chunk[..bytes]);
+        }
+    }
+
+    fn take_frame(&mut self) -> Result<Option<SizedBuffer>, Error> {
+        if self.pending.len() < SizedBuffer::sizesize() {
+            return Ok(None);
+        }
+
+        let expected_bytes = SizedBuffer::extract_size(&self.pending);
+        if expected_bytes > SizedBuffer::MAX_SIZE {
+            return Err(Error::new(ErrorKind::InvalidData, format!("Bytes:{} Max:{}", expected_bytes, SizedBuffer::MAX_SIZE)));
+        }
+
+        let frame_len = SizedBuffer::sizesize() + expected_bytes;
+        if self.pending.len() < frame_len {
+            return Ok(None);
+        }
+
+        let mut buf = SizedBuffer::with_headroom(SizedBuffer::HEADROOM, expected_bytes);
+        buf.push_bytes(&self.pending[SizedBuffer::sizesize()..frame_len]).map_err(|err| Error::new(ErrorKind::InvalidData, format!("{err:?}")))?;
+        self.pending.drain(..frame_len);
+        Ok(Some(buf))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::io::{AsyncWriteExt, duplex};
+
+    use super::FrameReader;
+    use crate::SizedBuffer;
+
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let mut buf = SizedBuffer::new(payload.len());
+        buf.push_bytes(payload).unwrap();
+        buf.frame().to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_split_frame() {
+        let (mut client, server) = duplex(64);
+        let bytes = frame(&[1, 2, 3, 4, 5, 6, 7, 8]);
+
+        tokio::spawn(async move {
+            for byte in bytes {
+                client.write_all(&[byte]).await.unwrap();
+                tokio::task::yield_now().await;
+            }
+        });
+
+        let mut reader = FrameReader::new(server);
+        let mut result = reader.next_frame().await.unwrap().unwrap();
+        assert_eq!(result.pull_remaining().unwrap(), [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert!(reader.next_frame().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_coalesced_frames() {
+        let (mut client, server) = duplex(64);
+        let mut bytes = frame(&[1, 2, 3]);
+        bytes.extend(frame(&[]));
+        bytes.extend(frame(&[4, 5]));
+        client.write_all(&bytes).await.unwrap();
+        drop(client);
+
+        let mut reader = FrameReader::new(server);
+        assert_eq!(reader.next_frame().await.unwrap().unwrap().pull_remaining().unwrap(), [1, 2, 3]);
+        assert_eq!(reader.next_frame().await.unwrap().unwrap().size(), 0);
+        assert_eq!(reader.next_frame().await.unwrap().unwrap().pull_remaining().unwrap(), [4, 5]);
+        assert!(reader.next_frame().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_large_frame() {
+        let (mut client, server) = duplex(64);
+        let payload = (0..100_000).map(|idx| idx as u8).collect::<Vec<_>>();
+        let bytes = frame(&payload);
+
+        tokio::spawn(async move {
+            client.write_all(&bytes).await.unwrap();
+        });
+
+        let mut reader = FrameReader::new(server);
+        let mut result = reader.next_frame().await.unwrap().unwrap();
+        assert_eq!(result.size(), payload.len());
+        assert_eq!(result.pull_remaining().unwrap(), payload);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_frame() {
+        let (mut client, server) = duplex(64);
+        let size = (SizedBuffer::MAX_SIZE + 1) as u32;
+        client.write_all(&size.to_le_bytes()).await.unwrap();
+
+        let mut reader = FrameReader::new(server);
+        assert!(reader.next_frame().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_truncated_frame() {
+        let (mut client, server) = duplex(64);
+        let bytes = frame(&[1, 2, 3, 4]);
+        client.write_all(&bytes[..bytes.len() - 1]).await.unwrap();
+        drop(client);
+
+        let mut reader = FrameReader::new(server);
+        assert!(reader.next_frame().await.is_err());
+    }
+}
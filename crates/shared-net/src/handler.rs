@@ -0,0 +1,162 @@
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tokio::task::{JoinError, JoinSet};
+use tokio::time::timeout;
+use tracing::error;
+
+use crate::RoutedMessage;
+use crate::channel::VSender;
+use crate::shutdown::DRAIN_LIMIT;
+
+pub type HandlerError = Box<dyn fmt::Debug + Send>;
+
+pub type HandlerFuture = Pin<Box<dyn Future<Output = Result<(), HandlerError>> + Send>>;
+
+pub enum Processed<R> {
+    Done(R),
+    Spawned(HandlerFuture),
+}
+
+// Implemented for plain `fn(context, tx, msg) -> R` callbacks and for async handlers wrapped in `Spawn`
+pub trait Process<T, M, R> {
+    fn process(&self, context: T, tx: VSender<RoutedMessage>, msg: M) -> Processed<R>;
+}
+
+impl<T, M, R, F> Process<T, M, R> for F
+where
+    F: Fn(T, VSender<RoutedMessage>, M) -> R,
+{
+    fn process(&self, context: T, tx: VSender<RoutedMessage>, msg: M) -> Processed<R> {
+        Processed::Done(self(context, tx, msg))
+    }
+}
+
+// An async handler runs beside the connection loop, so it may `.await` without stalling reads or heartbeats
+pub struct Spawn<F>(pub F);
+
+impl<T, M, R, F, Fut, E> Process<T, M, R> for Spawn<F>
+where
+    F: Fn(T, VSender<RoutedMessage>, M) -> Fut,
+    Fut: Future<Output = Result<(), E>> + Send + 'static,
+    E: fmt::Debug + Send + 'static,
+{
+    fn process(&self, context: T, tx: VSender<RoutedMessage>, msg: M) -> Processed<R> {
+        let future = (self.0)(context, tx, msg);
+        Processed::Spawned(Box::pin(async move { future.await.map_err(|err| Box::new(err) as HandlerError) }))
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct HandlerStats {
+    handled: Arc<AtomicUsize>,
+    failed: Arc<AtomicUsize>,
+}
+
+impl HandlerStats {
+    pub fn handled(&self) -> usize {
+        self.handled.load(Ordering::Relaxed)
+    }
+
+    pub fn failed(&self) -> usize {
+        self.failed.load(Ordering::Relaxed)
+    }
+}
+
+pub(crate) struct Handlers {
+    tasks: JoinSet<Result<(), HandlerError>>,
+    stats: HandlerStats,
+}
+
+impl Handlers {
+    pub(crate) fn new(stats: HandlerStats) -> Self {
+        Self {
+            tasks: JoinSet::new(),
+            stats,
+        }
+    }
+
+    // `spawned` is what the loop carries on with while the handler is still running
+    pub(crate) fn run<R>(&mut self, processed: Processed<R>, spawned: R) -> R {
+        match processed {
+            Processed::Done(result) => result,
+            Processed::Spawned(future) => {
+                self.tasks.spawn(future);
+                spawned
+            }
+        }
+    }
+
+    pub(crate) async fn next(&mut self) -> Option<()> {
+        let result = self.tasks.join_next().await?;
+        self.record(result);
+        Some(())
+    }
+
+    // Lets in-flight handlers queue their replies before the connection goes away
+    pub(crate) async fn drain(&mut self) {
+        let _ = timeout(DRAIN_LIMIT, async {
+            while let Some(result) = self.tasks.join_next().await {
+                self.record(result);
+            }
+        })
+        .await;
+        self.tasks.abort_all();
+    }
+
+    fn record(&self, result: Result<Result<(), HandlerError>, JoinError>) {
+        match result {
+            Ok(Ok(())) => {
+                self.stats.handled.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(Err(err)) => {
+                let failed = self.stats.failed.fetch_add(1, Ordering::Relaxed) + 1;
+                error!(?err, failed, "Handler failed");
+            }
+            Err(err) => {
+                let failed = self.stats.failed.fetch_add(1, Ordering::Relaxed) + 1;
+                error!(?err, failed, "Handler panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{HandlerStats, Handlers, Process, Spawn};
+    use crate::channel::{QueueConfig, VSender, bounded};
+    use crate::{RoutedMessage, SizedBuffer};
+
+    fn inline(_context: (), _tx: VSender<RoutedMessage>, msg: u8) -> bool {
+        msg > 0
+    }
+
+    async fn spawned(_context: (), tx: VSender<RoutedMessage>, msg: u8) -> Result<(), u8> {
+        match msg {
+            0 => Err(msg),
+            _ => tx.send_async(RoutedMessage::local(SizedBuffer::from(&msg).unwrap())).await.map_err(|_| msg),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handlers() {
+        let stats = HandlerStats::default();
+        let mut handlers = Handlers::new(stats.clone());
+        let (tx, mut rx) = bounded(QueueConfig::default());
+
+        assert!(!handlers.run(inline.process((), tx.clone(), 0), true));
+        assert!(handlers.run(inline.process((), tx.clone(), 3), false));
+
+        let handler = Spawn(spawned);
+        for msg in [0, 7] {
+            assert!(handlers.run(handler.process((), tx.clone(), msg), true));
+        }
+        handlers.drain().await;
+
+        assert_eq!((stats.handled(), stats.failed()), (1, 1));
+        assert_eq!(rx.recv().await.unwrap().buf.pull::<u8>().unwrap(), 7);
+    }
+}
@@ -0,0 +1,62 @@
+use tokio::time::{Duration, Instant};
+
+use crate::{SizedBuffer, op};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Heartbeat {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Heartbeat {
+    pub const fn new(interval: Duration, timeout: Duration) -> Self {
+        Self {
+            interval,
+            timeout,
+        }
+    }
+
+    pub(crate) fn is_expired(&self, last_seen: Instant) -> bool {
+        last_seen.elapsed() >= self.timeout
+    }
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(10), Duration::from_secs(30))
+    }
+}
+
+pub(crate) fn beat(command: op::Command) -> SizedBuffer {
+    let mut buf = SizedBuffer::new(1);
+    let _ = buf.push(&command);
+    buf
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::time::{Duration, Instant};
+
+    use super::{Heartbeat, beat};
+    use crate::op;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_expired() {
+        let heartbeat = Heartbeat::new(Duration::from_secs(1), Duration::from_secs(3));
+        let last_seen = Instant::now();
+        assert!(!heartbeat.is_expired(last_seen));
+
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert!(!heartbeat.is_expired(last_seen));
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(heartbeat.is_expired(last_seen));
+    }
+
+    #[test]
+    fn test_beat() {
+        let mut ping = beat(op::Command::Ping);
+        assert_eq!(ping.pull::<op::Command>().unwrap(), op::Command::Ping);
+        assert_eq!(ping.read_remain(), 0);
+    }
+}
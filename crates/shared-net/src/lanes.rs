@@ -0,0 +1,116 @@
+use crate::channel::{QueueConfig, QueueStats, SendError, VReceiver, VSender, bounded};
+use crate::{SizedBuffer, op};
+
+// A queue per priority on the way out to a connection, each with the connection's own queue config
+pub(crate) struct Lanes {
+    lanes: [VSender<SizedBuffer>; 3],
+}
+
+pub(crate) struct LanesReceiver {
+    lanes: [VReceiver<SizedBuffer>; 3],
+}
+
+pub(crate) fn lanes(config: QueueConfig) -> (Lanes, LanesReceiver) {
+    let (high_tx, high_rx) = bounded(config);
+    let (normal_tx, normal_rx) = bounded(config);
+    let (low_tx, low_rx) = bounded(config);
+    (
+        Lanes {
+            lanes: [high_tx, normal_tx, low_tx],
+        },
+        LanesReceiver {
+            lanes: [high_rx, normal_rx, low_rx],
+        },
+    )
+}
+
+impl Lanes {
+    // Frames the server makes up itself, heartbeats and registration, go in the Normal lane
+    pub(crate) fn send(&self, buf: SizedBuffer) -> Result<(), SendError<SizedBuffer>> {
+        self.send_lane(op::Priority::Normal, buf)
+    }
+
+    // Never waits, the router serves every connection from one loop. A Block lane that fills up is a slow consumer
+    // and closes the connection like the Disconnect policy would.
+    pub(crate) fn send_lane(&self, priority: op::Priority, buf: SizedBuffer) -> Result<(), SendError<SizedBuffer>> {
+        let lane = self.lane(priority);
+        let result = lane.send(buf);
+        if result.is_err() && !lane.is_closed() {
+            self.close();
+        }
+        result
+    }
+
+    // Everything waiting across the lanes
+    pub(crate) fn stats(&self) -> QueueStats {
+        self.lanes.iter().map(VSender::stats).fold(QueueStats::default(), |total, stats| QueueStats {
+            depth: total.depth + stats.depth,
+            capacity: total.capacity + stats.capacity,
+            high_water: total.high_water.max(stats.high_water),
+            dropped: total.dropped + stats.dropped,
+        })
+    }
+
+    // A lane closed by its overflow policy closes the connection
+    pub(crate) fn is_closed(&self) -> bool {
+        self.lanes.iter().any(VSender::is_closed)
+    }
+
+    pub(crate) fn close(&self) {
+        for lane in &self.lanes {
+            lane.close();
+        }
+    }
+
+    fn lane(&self, priority: op::Priority) -> &VSender<SizedBuffer> {
+        &self.lanes[priority as usize]
+    }
+}
+
+impl LanesReceiver {
+    // A lower lane only gets a turn while every higher one is empty
+    pub(crate) async fn recv(&mut self) -> Option<SizedBuffer> {
+        let [high, normal, low] = &mut self.lanes;
+        tokio::select! {
+            biased;
+            Some(buf) = high.recv() => Some(buf),
+            Some(buf) = normal.recv() => Some(buf),
+            Some(buf) = low.recv() => Some(buf),
+            else => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::lanes;
+    use crate::channel::{OverflowPolicy, QueueConfig};
+    use crate::{SizedBuffer, op};
+
+    #[tokio::test]
+    async fn test_lanes() {
+        let (tx, mut rx) = lanes(QueueConfig::default());
+        for (priority, command) in [(op::Priority::Low, op::Command::Message(1)), (op::Priority::Normal, op::Command::Ping), (op::Priority::Low, op::Command::Message(2)), (op::Priority::High, op::Command::Game(1))] {
+            tx.send_lane(priority, SizedBuffer::from(&command).unwrap()).unwrap();
+        }
+        assert_eq!(tx.stats().depth, 4);
+        drop(tx);
+
+        let mut received = Vec::new();
+        while let Some(mut buf) = rx.recv().await {
+            received.push(buf.pull::<op::Command>().unwrap());
+        }
+        assert_eq!(received, vec![op::Command::Game(1), op::Command::Ping, op::Command::Message(1), op::Command::Message(2)]);
+    }
+
+    #[test]
+    fn test_full_lane_closes() {
+        let (tx, _rx) = lanes(QueueConfig::new(1, OverflowPolicy::Block));
+        tx.send(SizedBuffer::from(&op::Command::Ping).unwrap()).unwrap();
+        assert!(tx.send_lane(op::Priority::High, SizedBuffer::from(&op::Command::Game(1)).unwrap()).is_ok());
+        assert!(!tx.is_closed());
+
+        assert!(tx.send(SizedBuffer::from(&op::Command::Ping).unwrap()).is_err());
+        assert!(tx.is_closed());
+    }
+}
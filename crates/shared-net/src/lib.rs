@@ -1,14 +1,42 @@
+mod backoff;
+mod balance;
 mod client;
+mod directory;
+mod framing;
+mod handler;
+mod heartbeat;
+mod lanes;
+mod memory;
+mod ratelimit;
+mod recording;
+mod registration;
+mod request;
 mod server;
+mod shutdown;
 mod sizedbuffers;
+mod transport;
 mod types;
+#[cfg(unix)]
+mod unix;
 mod util;
 
+pub mod channel;
 pub mod op;
+pub mod tls;
 
+pub use backoff::Backoff;
+pub use balance::{AnyStrategy, InFlight};
 pub use bufferable_derive::Bufferable;
-pub use client::{VClientMode, async_client};
-pub use server::async_server;
+pub use client::{VClientConfig, VClientEvent, VClientMode, async_client};
+pub use directory::Directory;
+pub use handler::{HandlerError, HandlerFuture, HandlerStats, Process, Processed, Spawn};
+pub use heartbeat::Heartbeat;
+pub use ratelimit::{RateLimit, RateLimitPolicy, RateLimits, RateViolation};
+pub use recording::{Record, RecordReader, Recorder, RecordingError};
+pub use registration::{Registration, Secret};
+pub use request::{Request, RequestError, Requester};
+pub use server::{VServerConfig, async_server};
+pub use shutdown::{CancellationToken, shutdown_on_ctrl_c};
 pub use sizedbuffers::{Bufferable, SizedBuffer, SizedBufferError};
 pub use types::*;
 
@@ -16,6 +44,9 @@ pub use types::*;
 pub struct RoutedMessage {
     pub route: op::Route,
     pub buf: SizedBuffer,
+    // The connection told when the route reaches no one
+    pub origin: Option<NodeType>,
+    pub priority: op::Priority,
 }
 
 impl RoutedMessage {
@@ -23,14 +54,24 @@ impl RoutedMessage {
         Self {
             route,
             buf,
+            origin: None,
+            priority: op::Priority::Normal,
         }
     }
 
     pub fn local(buf: SizedBuffer) -> Self {
-        Self {
-            route: op::Route::Local,
-            buf,
-        }
+        Self::new(op::Route::Local, buf)
+    }
+
+    pub fn with_origin(mut self, origin: NodeType) -> Self {
+        self.origin = Some(origin);
+        self
+    }
+
+    // Servers write Game frames out before anything else waiting for the same connection
+    pub fn with_priority(mut self, priority: op::Priority) -> Self {
+        self.priority = priority;
+        self
     }
 }
 
@@ -41,6 +82,7 @@ impl From<SizedBuffer> for RoutedMessage {
 }
 
 pub struct IdMessage {
-    pub id: u8,
+    pub id: NodeType,
+    pub flavor: op::Flavor,
     pub buf: SizedBuffer,
 }
@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::sync::{LazyLock, Mutex, MutexGuard};
+
+use tokio::io::{DuplexStream, duplex};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+// Interfaces starting with this never touch the network, both ends have to live in the same process
+pub(crate) const PREFIX: &str = "mem:";
+
+const BUFFER: usize = 64 * 1024;
+
+type Registry = HashMap<String, UnboundedSender<DuplexStream>>;
+
+static LISTENERS: LazyLock<Mutex<Registry>> = LazyLock::new(|| Mutex::new(Registry::new()));
+
+fn listeners() -> MutexGuard<'static, Registry> {
+    LISTENERS.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+pub(crate) struct MemoryListener {
+    name: String,
+    registered: UnboundedSender<DuplexStream>,
+    incoming: UnboundedReceiver<DuplexStream>,
+}
+
+impl MemoryListener {
+    pub(crate) fn bind(name: &str) -> Result<Self, Error> {
+        let mut listeners = listeners();
+        if listeners.contains_key(name) {
+            return Err(Error::new(ErrorKind::AddrInUse, name));
+        }
+        let (registered, incoming) = mpsc::unbounded_channel();
+        listeners.insert(name.to_string(), registered.clone());
+        Ok(Self {
+            name: name.to_string(),
+            registered,
+            incoming,
+        })
+    }
+
+    pub(crate) async fn accept(&mut self) -> Result<DuplexStream, Error> {
+        self.incoming.recv().await.ok_or_else(|| Error::from(ErrorKind::NotConnected))
+    }
+}
+
+impl Drop for MemoryListener {
+    fn drop(&mut self) {
+        let mut listeners = listeners();
+        if listeners.get(&self.name).is_some_and(|tx| tx.same_channel(&self.registered)) {
+            listeners.remove(&self.name);
+        }
+    }
+}
+
+pub(crate) fn connect(name: &str) -> Result<DuplexStream, Error> {
+    let (near, far) = duplex(BUFFER);
+    match listeners().get(name) {
+        Some(tx) if tx.send(far).is_ok() => Ok(near),
+        _ => Err(Error::new(ErrorKind::ConnectionRefused, name)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::ErrorKind;
+
+    use tokio::sync::mpsc::{self, UnboundedSender};
+    use tokio::time::{Duration, timeout};
+
+    use super::{MemoryListener, connect};
+    use crate::channel::{QueueConfig, VSender, bounded};
+    use crate::{IdMessage, Request, RequestError, Requester, RoutedMessage, SizedBuffer, VClientConfig, VClientEvent, VClientMode, async_client, async_server, op};
+
+    #[test]
+    fn test_bind() {
+        let listener = MemoryListener::bind("bind-test").unwrap();
+        assert_eq!(MemoryListener::bind("bind-test").err().map(|err| err.kind()), Some(ErrorKind::AddrInUse));
+        assert!(connect("bind-test").is_ok());
+
+        drop(listener);
+        assert_eq!(connect("bind-test").err().map(|err| err.kind()), Some(ErrorKind::ConnectionRefused));
+        assert!(MemoryListener::bind("bind-test").is_ok());
+    }
+
+    // what Courtyard does: swap the route for the sender id and pass it on
+    fn route(_context: (), tx: VSender<RoutedMessage>, mut msg: IdMessage) -> bool {
+        let (Ok(route), Ok(command)) = (msg.buf.pull::<op::Route>(), msg.buf.pull::<op::Command>()) else {
+            return false;
+        };
+        msg.buf.prepend(&msg.id).and_then(|_| msg.buf.prepend(&command)).is_ok() && tx.send(RoutedMessage::new(route, msg.buf).with_origin(msg.id)).is_ok()
+    }
+
+    fn double(_context: UnboundedSender<VClientEvent>, tx: VSender<RoutedMessage>, mut buf: SizedBuffer) -> VClientMode {
+        if let Ok(op::Command::Request(correlation)) = buf.pull::<op::Command>()
+            && let Ok(request) = Request::read(correlation, &mut buf)
+            && let Ok(value) = buf.pull::<u64>()
+            && let Ok(reply) = request.reply(request.command, SizedBuffer::from(&(value * 2)).unwrap())
+        {
+            let _ = tx.send(reply);
+        }
+        VClientMode::Continue
+    }
+
+    fn connected(context: UnboundedSender<VClientEvent>, _tx: VSender<RoutedMessage>, event: VClientEvent) {
+        let _ = context.send(event);
+    }
+
+    #[tokio::test]
+    async fn test_request_through_router() {
+        let interface = "mem:router-test".to_string();
+        let (router_tx, _router_rx) = bounded(QueueConfig::default());
+        let (_external_tx, external_rx) = bounded(QueueConfig::default());
+        tokio::spawn(async_server((), router_tx, external_rx, interface.clone(), route, |_, _, _| {}));
+
+        let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+        let (archive_tx, archive_rx) = bounded(QueueConfig::default());
+        tokio::spawn(async_client(events_tx, op::Flavor::Archive, archive_tx, archive_rx, interface.clone(), double, connected));
+        assert_eq!(timeout(Duration::from_secs(10), events_rx.recv()).await.unwrap(), Some(VClientEvent::Connected));
+
+        let (gate_tx, gate_rx) = bounded(QueueConfig::default());
+        let requester = Requester::new(gate_tx.clone());
+        let config = VClientConfig::new(interface).with_requester(requester.clone());
+        tokio::spawn(async_client((), op::Flavor::Gate, gate_tx, gate_rx, config, |_, _, _| VClientMode::Continue, |_, _, _| {}));
+
+        let mut reply = requester.request(op::Route::Any(op::Flavor::Archive), op::Command::Inventory(1), SizedBuffer::from(&21_u64).unwrap(), Duration::from_secs(10)).await.unwrap();
+        assert_eq!(reply.pull::<op::Command>().unwrap(), op::Command::Inventory(1));
+        assert_eq!(reply.pull::<u64>().unwrap(), 42);
+
+        // nobody serves Lookout, so the request fails without waiting out its limit
+        let result = requester.request(op::Route::Any(op::Flavor::Lookout), op::Command::Authorize, SizedBuffer::new(0), Duration::from_secs(60)).await;
+        assert!(matches!(result, Err(RequestError::Undeliverable(op::UndeliverableReason::Unavailable))));
+        assert_eq!(requester.outstanding(), 0);
+    }
+}
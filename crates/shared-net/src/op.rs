@@ -1,75 +1,20 @@
 use num_enum::{FromPrimitive, IntoPrimitive};
 use std::fmt;
-use std::mem::size_of;
 #[cfg(test)]
 use strum_macros::EnumIter;
 
-use crate::sizedbuffers::{Bufferable, SizedBufferError};
-use crate::types::NodeType;
-use crate::SizedBuffer;
+use crate::types::{CorrelationType, NodeType, TimestampType};
+use crate::{Bufferable, SizedBuffer, SizedBufferError};
 
-type RouteType = u8;
-
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Bufferable)]
 pub enum Route {
+    #[bufferable(default)]
     None,
     Local,
     One(NodeType),
     Any(Flavor),
     All(Flavor),
-}
-
-impl Route {
-    const REPR_NONE: RouteType = 0;
-    const REPR_LOCAL: RouteType = 1;
-    const REPR_ONE: RouteType = 2;
-    const REPR_ANY: RouteType = 3;
-    const REPR_ALL: RouteType = 4;
-}
-
-impl Bufferable for Route {
-    fn push_into(&self, buf: &mut SizedBuffer) -> Result<usize, SizedBufferError> {
-        let mut pushed = 0;
-        match *self {
-            Route::None => pushed += Self::REPR_NONE.push_into(buf)?,
-            Route::Local => pushed += Self::REPR_LOCAL.push_into(buf)?,
-            Route::One(destination) => {
-                pushed += Self::REPR_ONE.push_into(buf)?;
-                pushed += destination.push_into(buf)?;
-            }
-            Route::Any(flavor) => {
-                pushed += Self::REPR_ANY.push_into(buf)?;
-                pushed += flavor.push_into(buf)?;
-            }
-            Route::All(flavor) => {
-                pushed += Self::REPR_ALL.push_into(buf)?;
-                pushed += flavor.push_into(buf)?;
-            }
-        };
-        Ok(pushed)
-    }
-
-    fn pull_from(buf: &mut SizedBuffer) -> Result<Self, SizedBufferError> {
-        let route = u8::pull_from(buf)?;
-        let result = match route {
-            Self::REPR_LOCAL => Route::Local,
-            Self::REPR_ONE => Route::One(u8::pull_from(buf)?),
-            Self::REPR_ANY => Route::Any(Flavor::pull_from(buf)?),
-            Self::REPR_ALL => Route::All(Flavor::pull_from(buf)?),
-            _ => Route::None,
-        };
-        Ok(result)
-    }
-
-    fn size_in_buffer(&self) -> usize {
-        match *self {
-            Route::None => size_of::<RouteType>(),
-            Route::Local => size_of::<RouteType>(),
-            Route::One(one) => size_of::<RouteType>() + one.size_in_buffer(),
-            Route::Any(any) => size_of::<RouteType>() + any.size_in_buffer(),
-            Route::All(all) => size_of::<RouteType>() + all.size_in_buffer(),
-        }
-    }
+    Multi(Vec<Target>),
 }
 
 impl fmt::Debug for Route {
@@ -80,17 +25,24 @@ impl fmt::Debug for Route {
             Route::One(id) => write!(f, "One({id})"),
             Route::Any(flavor) => write!(f, "Any({flavor:?})"),
             Route::All(flavor) => write!(f, "All({flavor:?})"),
+            Route::Multi(targets) => write!(f, "Multi({targets:?})"),
         }
     }
 }
 
-type FlavorType = u8;
+// A Vagabond behind a Gate: a Multi route reaches each Gate once, and the Gate passes it on to its Vagabonds
+#[derive(Clone, Copy, Debug, PartialEq, Bufferable)]
+pub struct Target {
+    pub gate: NodeType,
+    pub vagabond: NodeType,
+}
 
 #[repr(u8)]
-#[derive(Clone, Copy, Debug, PartialEq, FromPrimitive, IntoPrimitive)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive, Bufferable)]
 #[cfg_attr(test, derive(EnumIter))]
 pub enum Flavor {
     #[num_enum(default)]
+    #[bufferable(default)]
     NoOp = 0,
     Archive = 1,
     Bazaar = 2,
@@ -101,30 +53,14 @@ pub enum Flavor {
     Hall = 8,
     Jail = 10,
     Lookout = 12,
+    Steward = 19,
     Vagabond = 22,
     Warehouse = 23,
 }
 
-impl Bufferable for Flavor {
-    fn push_into(&self, buf: &mut SizedBuffer) -> Result<usize, SizedBufferError> {
-        let flavor: FlavorType = (*self).into();
-        flavor.push_into(buf)
-    }
-
-    fn pull_from(buf: &mut SizedBuffer) -> Result<Self, SizedBufferError> {
-        let flavor = FlavorType::pull_from(buf)?;
-        Ok(flavor.into())
-    }
-
-    fn size_in_buffer(&self) -> usize {
-        size_of::<FlavorType>()
-    }
-}
-
-type CommandType = u8;
 pub type SubCommandType = u8;
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Bufferable)]
 #[cfg_attr(test, derive(EnumIter))]
 pub enum Command {
     NoOp,
@@ -135,68 +71,161 @@ pub enum Command {
     Message(SubCommandType),
     Inventory(SubCommandType),
     Game(SubCommandType),
+    Reject,
+    Ping,
+    Pong,
+    Request(CorrelationType),
+    Reply(CorrelationType),
+    Challenge,
+    Undeliverable,
+    Advertise,
+    Forward,
+    Status,
+    Multicast,
+}
+
+// The lane a frame waits in on its way out of a server, so Game traffic never queues behind chat
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
 }
 
-impl Command {
-    const REPR_NOOP: CommandType = 0;
-    const REPR_REGISTER: CommandType = 1;
-    const REPR_AUTHORIZE: CommandType = 2;
-    const REPR_HELLO: CommandType = 3;
-    const REPR_USERATTR: CommandType = 4;
-    const REPR_MESSAGE: CommandType = 5;
-    const REPR_INVENTORY: CommandType = 6;
-    const REPR_GAME: CommandType = 7;
-}
-
-impl Bufferable for Command {
-    fn push_into(&self, buf: &mut SizedBuffer) -> Result<usize, SizedBufferError> {
-        match *self {
-            Command::NoOp => Command::REPR_NOOP.push_into(buf),
-            Command::Register => Command::REPR_REGISTER.push_into(buf),
-            Command::Authorize => Command::REPR_AUTHORIZE.push_into(buf),
-            Command::Hello => Command::REPR_HELLO.push_into(buf),
-            Command::UserAttr => Command::REPR_USERATTR.push_into(buf),
-            Command::Message(sub) => (Command::REPR_MESSAGE, sub).push_into(buf),
-            Command::Inventory(sub) => (Command::REPR_INVENTORY, sub).push_into(buf),
-            Command::Game(sub) => (Command::REPR_GAME, sub).push_into(buf),
+impl From<Command> for Priority {
+    fn from(command: Command) -> Self {
+        match command {
+            Command::Game(_) => Priority::High,
+            Command::Message(_) => Priority::Low,
+            _ => Priority::Normal,
         }
     }
+}
 
-    fn pull_from(buf: &mut SizedBuffer) -> Result<Self, SizedBufferError> {
-        let command = CommandType::pull_from(buf)?;
-        let result = match command {
-            Command::REPR_NOOP => Command::NoOp,
-            Command::REPR_REGISTER => Command::Register,
-            Command::REPR_AUTHORIZE => Command::Authorize,
-            Command::REPR_HELLO => Command::Hello,
-            Command::REPR_USERATTR => Command::UserAttr,
-            Command::REPR_MESSAGE => Command::Message(SubCommandType::pull_from(buf)?),
-            Command::REPR_INVENTORY => Command::Inventory(SubCommandType::pull_from(buf)?),
-            Command::REPR_GAME => Command::Game(SubCommandType::pull_from(buf)?),
-            _ => return Err(SizedBufferError::UnexpectedEnum(command)),
-        };
-        Ok(result)
+pub type ProtocolVersionType = u16;
+pub type CapabilityType = u32;
+
+pub const PROTOCOL_VERSION: ProtocolVersionType = 9;
+
+// A server that wants proof sends [Challenge][nonce], the client answers [Challenge][proof]
+pub type NonceType = u128;
+pub type ProofType = [u8; 32];
+
+#[derive(Clone, Copy, Debug, PartialEq, Bufferable)]
+pub struct Handshake {
+    pub version: ProtocolVersionType,
+    pub capabilities: CapabilityType,
+}
+
+impl Handshake {
+    pub const fn new(capabilities: CapabilityType) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            capabilities,
+        }
+    }
+
+    pub fn negotiate(&self, peer: &Handshake) -> Result<Handshake, RejectReason> {
+        if self.version != peer.version {
+            return Err(RejectReason::Version {
+                expected: self.version,
+                received: peer.version,
+            });
+        }
+        Ok(Handshake {
+            version: self.version,
+            capabilities: self.capabilities & peer.capabilities,
+        })
+    }
+}
+
+impl Default for Handshake {
+    fn default() -> Self {
+        Self::new(0)
     }
+}
+
+#[derive(Clone, Debug, PartialEq, Bufferable)]
+pub enum RejectReason {
+    Malformed,
+    Version {
+        expected: ProtocolVersionType,
+        received: ProtocolVersionType,
+    },
+    Unexpected(Flavor),
+    Unauthorized,
+}
 
-    fn size_in_buffer(&self) -> usize {
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Command::NoOp => size_of::<CommandType>(),
-            Command::Register => size_of::<CommandType>(),
-            Command::Authorize => size_of::<CommandType>(),
-            Command::Hello => size_of::<CommandType>(),
-            Command::UserAttr => size_of::<CommandType>(),
-            Command::Message(sub) => size_of::<CommandType>() + sub.size_in_buffer(),
-            Command::Inventory(sub) => size_of::<CommandType>() + sub.size_in_buffer(),
-            Command::Game(sub) => size_of::<CommandType>() + sub.size_in_buffer(),
+            RejectReason::Malformed => write!(f, "malformed registration"),
+            RejectReason::Version {
+                expected,
+                received,
+            } => write!(f, "protocol version {received} is not supported (expected {expected})"),
+            RejectReason::Unexpected(flavor) => write!(f, "{flavor:?} may not register here"),
+            RejectReason::Unauthorized => write!(f, "registration challenge failed"),
         }
     }
 }
 
+// Sent back to the origin of a frame that reached no one: [Undeliverable][Undeliverable][sender][payload], the sender and
+// payload being what the recipient would have been handed after the command
+#[derive(Clone, Debug, PartialEq, Bufferable)]
+pub struct Undeliverable {
+    pub route: Route,
+    pub command: Command,
+    pub reason: UndeliverableReason,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Bufferable)]
+pub enum UndeliverableReason {
+    Disconnected,
+    Unavailable,
+}
+
+impl fmt::Display for UndeliverableReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UndeliverableReason::Disconnected => write!(f, "the recipient is no longer connected"),
+            UndeliverableReason::Unavailable => write!(f, "no service is available"),
+        }
+    }
+}
+
+// A Steward sends [Status] and the server answers [Status][Vec<ConnectionStatus>] itself, one entry per connection
+#[derive(Clone, Debug, PartialEq, Bufferable)]
+pub struct ConnectionStatus {
+    pub id: NodeType,
+    // NoOp until the connection has registered
+    pub flavor: Flavor,
+    // microseconds since the epoch
+    pub connected: TimestampType,
+    pub received: Traffic,
+    pub sent: Traffic,
+}
+
+// Routed frames only, heartbeats and registration aside
+#[derive(Clone, Copy, Debug, Default, PartialEq, Bufferable)]
+pub struct Traffic {
+    pub messages: u64,
+    pub bytes: u64,
+}
+
+impl Traffic {
+    pub(crate) fn count(&mut self, buf: &SizedBuffer) {
+        self.messages += 1;
+        self.bytes += buf.size() as u64;
+    }
+}
+
 #[cfg(test)]
 mod test {
     use strum::IntoEnumIterator;
 
-    use super::{Command, Flavor, Route};
+    use super::{Command, Flavor, Handshake, RejectReason, Route, Target};
     use crate::sizedbuffers::{SizedBuffer, SizedBufferError};
 
     #[test]
@@ -214,6 +243,10 @@ mod test {
         buf2.xfer::<Route>(&mut buf1)?;
 
         assert_eq!(route, buf2.pull::<Route>()?);
+
+        let multi = Route::Multi(vec![Target { gate: 1, vagabond: 7 }, Target { gate: 2, vagabond: 9 }]);
+        let mut buf3 = SizedBuffer::from(&multi)?;
+        assert_eq!(multi, buf3.pull::<Route>()?);
         Ok(())
     }
 
@@ -251,4 +284,23 @@ mod test {
         }
         Ok(())
     }
+
+    #[test]
+    fn test_handshake() -> Result<(), SizedBufferError> {
+        let orig = Handshake::new(0b1011);
+
+        let mut buf = SizedBuffer::from(&orig)?;
+        assert_eq!(orig, buf.pull::<Handshake>()?);
+
+        let peer = Handshake::new(0b0110);
+        assert_eq!(orig.negotiate(&peer).map(|negotiated| negotiated.capabilities), Ok(0b0010));
+
+        let old = Handshake {
+            version: 0,
+            capabilities: 0,
+        };
+        assert!(matches!(orig.negotiate(&old), Err(RejectReason::Version { received: 0, .. })));
+        Ok(())
+    }
 }
+
@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::mem::{Discriminant, discriminant};
+
+use tokio::time::Instant;
+
+use crate::channel::VSender;
+use crate::{NodeType, op};
+
+// A token bucket: `per_second` tokens flow back each second up to `burst`, and every frame takes one
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub per_second: u32,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    pub const fn new(per_second: u32, burst: u32) -> Self {
+        Self {
+            per_second,
+            burst,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum RateLimitPolicy {
+    #[default]
+    Drop,
+    Disconnect,
+}
+
+// Reported once each time a connection runs out, not for every frame past the limit
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateViolation {
+    pub id: NodeType,
+    pub command: op::Command,
+}
+
+type Category = Discriminant<op::Command>;
+
+// Limits apply to a kind of command, so a limit set with Game(0) holds back every Game(_).
+// Commands without a limit are never held back.
+#[derive(Clone, Default)]
+pub struct RateLimits {
+    limits: HashMap<Category, RateLimit>,
+    policy: RateLimitPolicy,
+    violations: Option<VSender<RateViolation>>,
+}
+
+impl RateLimits {
+    pub fn with_limit(mut self, command: op::Command, limit: RateLimit) -> Self {
+        self.limits.insert(discriminant(&command), limit);
+        self
+    }
+
+    pub fn with_policy(mut self, policy: RateLimitPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn with_violations(mut self, violations: VSender<RateViolation>) -> Self {
+        self.violations = Some(violations);
+        self
+    }
+
+    pub(crate) fn policy(&self) -> RateLimitPolicy {
+        self.policy
+    }
+
+    pub(crate) fn report(&self, violation: RateViolation) {
+        if let Some(violations) = &self.violations {
+            let _ = violations.send(violation);
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Admission {
+    Admitted,
+    // true for the frame that ran the bucket dry
+    Limited(bool),
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+    exceeded: bool,
+}
+
+// The buckets of one connection, filled the first time each kind of command shows up
+#[derive(Default)]
+pub(crate) struct Limiter {
+    buckets: HashMap<Category, Bucket>,
+}
+
+impl Limiter {
+    pub(crate) fn admit(&mut self, limits: &RateLimits, command: op::Command, now: Instant) -> Admission {
+        let category = discriminant(&command);
+        let Some(limit) = limits.limits.get(&category) else {
+            return Admission::Admitted;
+        };
+        let bucket = self.buckets.entry(category).or_insert_with(|| Bucket {
+            tokens: f64::from(limit.burst),
+            refilled: now,
+            exceeded: false,
+        });
+
+        let refill = now.saturating_duration_since(bucket.refilled).as_secs_f64() * f64::from(limit.per_second);
+        bucket.tokens = (bucket.tokens + refill).min(f64::from(limit.burst));
+        bucket.refilled = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            bucket.exceeded = false;
+            Admission::Admitted
+        } else {
+            let first = !bucket.exceeded;
+            bucket.exceeded = true;
+            Admission::Limited(first)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::time::{Duration, Instant};
+
+    use super::{Admission, Limiter, RateLimit, RateLimits};
+    use crate::op;
+
+    #[test]
+    fn test_limiter() {
+        let limits = RateLimits::default().with_limit(op::Command::Game(0), RateLimit::new(2, 3));
+        let mut limiter = Limiter::default();
+        let start = Instant::now();
+
+        for sub in 0..3 {
+            assert_eq!(limiter.admit(&limits, op::Command::Game(sub), start), Admission::Admitted);
+        }
+        assert_eq!(limiter.admit(&limits, op::Command::Game(7), start), Admission::Limited(true));
+        assert_eq!(limiter.admit(&limits, op::Command::Game(7), start), Admission::Limited(false));
+
+        // other kinds of command are not held back
+        assert_eq!(limiter.admit(&limits, op::Command::Message(1), start), Admission::Admitted);
+
+        let later = start + Duration::from_millis(500);
+        assert_eq!(limiter.admit(&limits, op::Command::Game(1), later), Admission::Admitted);
+        assert_eq!(limiter.admit(&limits, op::Command::Game(1), later), Admission::Limited(true));
+
+        // the bucket never holds more than the burst
+        let idle = later + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert_eq!(limiter.admit(&limits, op::Command::Game(1), idle), Admission::Admitted);
+        }
+        assert_eq!(limiter.admit(&limits, op::Command::Game(1), idle), Admission::Limited(true));
+    }
+}
@@ -0,0 +1,217 @@
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+
+use tracing::warn;
+
+use crate::util::now;
+use crate::{Bufferable, NodeType, SizedBuffer, SizedBufferError, TimestampType, op};
+
+#[derive(Debug)]
+pub enum RecordingError {
+    Io(Error),
+    Buffer(SizedBufferError),
+    Closed,
+}
+
+// Each record is a frame of [Header][payload], the payload being whatever followed [Route][Command] on the wire
+#[derive(Clone, Debug, PartialEq, Bufferable)]
+pub struct Header {
+    pub timestamp: TimestampType,
+    pub source: NodeType,
+    pub flavor: op::Flavor,
+    pub route: op::Route,
+    pub command: op::Command,
+}
+
+pub struct Record {
+    pub header: Header,
+    pub payload: SizedBuffer,
+}
+
+impl Record {
+    // Rebuilds the frame the source sent: [Route][Command][payload]
+    pub fn frame(&self) -> Result<SizedBuffer, SizedBufferError> {
+        let mut buf = self.payload.clone();
+        buf.prepend(&self.header.command)?;
+        buf.prepend(&self.header.route)?;
+        Ok(buf)
+    }
+}
+
+// Records waiting on the disk, past this they are dropped and counted
+const CAPACITY: usize = 1024;
+
+// Hands frames to a writer thread so recording never blocks the router on disk
+#[derive(Clone)]
+pub struct Recorder {
+    tx: SyncSender<SizedBuffer>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl Recorder {
+    // The writer finishes once every clone of the recorder is dropped
+    pub fn create(path: impl AsRef<Path>) -> Result<(Self, JoinHandle<Result<(), Error>>), Error> {
+        let out = BufWriter::new(File::create(path)?);
+        let (tx, rx) = mpsc::sync_channel(CAPACITY);
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let writer_dropped = dropped.clone();
+        let writer = thread::Builder::new().name("recorder".to_string()).spawn(move || write_records(out, rx, &writer_dropped))?;
+        Ok((
+            Self {
+                tx,
+                dropped,
+            },
+            writer,
+        ))
+    }
+
+    // Expects `payload` positioned just past [Route][Command]
+    pub fn record(&self, source: NodeType, flavor: op::Flavor, route: &op::Route, command: op::Command, payload: &SizedBuffer) -> Result<(), RecordingError> {
+        let header = Header {
+            timestamp: now(),
+            source,
+            flavor,
+            route: route.clone(),
+            command,
+        };
+        let mut payload = payload.clone();
+        let mut buf = SizedBuffer::new(header.size_in_buffer() + payload.read_remain());
+        buf.push(&header).and_then(|_| buf.xfer_bytes(&mut payload)).map_err(RecordingError::Buffer)?;
+        match self.tx.try_send(buf) {
+            Ok(()) => Ok(()),
+            // a disk that can't keep up loses records rather than growing the queue, the writer reports how many
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            Err(TrySendError::Disconnected(_)) => Err(RecordingError::Closed),
+        }
+    }
+}
+
+fn write_records(mut out: BufWriter<File>, rx: Receiver<SizedBuffer>, dropped: &AtomicUsize) -> Result<(), Error> {
+    while let Ok(buf) = rx.recv() {
+        out.write_all(buf.frame())?;
+        // flush after each burst, so a crash loses at most what was still queued
+        while let Ok(buf) = rx.try_recv() {
+            out.write_all(buf.frame())?;
+        }
+        out.flush()?;
+        report_dropped(dropped);
+    }
+    report_dropped(dropped);
+    Ok(())
+}
+
+fn report_dropped(dropped: &AtomicUsize) {
+    let dropped = dropped.swap(0, Ordering::Relaxed);
+    if dropped > 0 {
+        warn!(dropped, "Recorder fell behind");
+    }
+}
+
+pub struct RecordReader<R> {
+    read: R,
+}
+
+impl<R: Read> RecordReader<R> {
+    pub fn new(read: R) -> Self {
+        Self {
+            read,
+        }
+    }
+
+    fn read_record(&mut self, size: usize) -> Result<Record, RecordingError> {
+        if size > SizedBuffer::MAX_SIZE {
+            return Err(RecordingError::Buffer(SizedBufferError::TooLarge(size, SizedBuffer::MAX_SIZE)));
+        }
+        let mut bytes = vec![0; size];
+        self.read.read_exact(&mut bytes).map_err(RecordingError::Io)?;
+
+        let mut payload = SizedBuffer::new(size);
+        payload.push_bytes(&bytes).map_err(RecordingError::Buffer)?;
+        Ok(Record {
+            header: payload.pull::<Header>().map_err(RecordingError::Buffer)?,
+            payload,
+        })
+    }
+}
+
+impl<R: Read> Iterator for RecordReader<R> {
+    type Item = Result<Record, RecordingError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut size = [0_u8; SizedBuffer::sizesize()];
+        match self.read.read_exact(&mut size) {
+            Ok(()) => Some(self.read_record(SizedBuffer::extract_size(&size))),
+            // a recording cut off mid size marker just ends there
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => None,
+            Err(err) => Some(Err(RecordingError::Io(err))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+
+    use super::{RecordReader, Recorder, RecordingError};
+    use crate::{SizedBuffer, op};
+
+    #[test]
+    fn test_full_queue_drops() {
+        let (tx, rx) = mpsc::sync_channel(1);
+        let recorder = Recorder {
+            tx,
+            dropped: Arc::new(AtomicUsize::new(0)),
+        };
+        for _ in 0..3 {
+            recorder.record(7, op::Flavor::Gate, &op::Route::One(9), op::Command::Ping, &SizedBuffer::new(0)).unwrap();
+        }
+        assert_eq!(recorder.dropped.load(Ordering::Relaxed), 2);
+
+        drop(rx);
+        assert!(matches!(recorder.record(7, op::Flavor::Gate, &op::Route::One(9), op::Command::Ping, &SizedBuffer::new(0)), Err(RecordingError::Closed)));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let path = std::env::temp_dir().join(format!("recording-test-{}", std::process::id()));
+        let (recorder, writer) = Recorder::create(&path).unwrap();
+
+        let mut sent = SizedBuffer::new(32);
+        sent.push(&op::Route::Any(op::Flavor::Hall)).unwrap();
+        sent.push(&op::Command::Game(3)).unwrap();
+        sent.push(&42_u64).unwrap();
+        let _ = sent.pull::<op::Route>().unwrap();
+        let _ = sent.pull::<op::Command>().unwrap();
+        recorder.record(7, op::Flavor::Gate, &op::Route::Any(op::Flavor::Hall), op::Command::Game(3), &sent).unwrap();
+        recorder.record(9, op::Flavor::Hall, &op::Route::One(7), op::Command::Pong, &SizedBuffer::new(0)).unwrap();
+        assert_eq!(sent.pull::<u64>().unwrap(), 42);
+
+        drop(recorder);
+        writer.join().unwrap().unwrap();
+
+        let records = RecordReader::new(File::open(&path).unwrap()).collect::<Result<Vec<_>, RecordingError>>().unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(records.len(), 2);
+        assert!(records[0].header.timestamp <= records[1].header.timestamp);
+
+        let game = &records[0];
+        assert_eq!((game.header.source, game.header.flavor, &game.header.route, game.header.command), (7, op::Flavor::Gate, &op::Route::Any(op::Flavor::Hall), op::Command::Game(3)));
+        let mut frame = game.frame().unwrap();
+        assert_eq!(frame.pull::<op::Route>().unwrap(), op::Route::Any(op::Flavor::Hall));
+        assert_eq!(frame.pull::<op::Command>().unwrap(), op::Command::Game(3));
+        assert_eq!(frame.pull::<u64>().unwrap(), 42);
+
+        assert_eq!((records[1].header.route.clone(), records[1].payload.read_remain()), (op::Route::One(7), 0));
+    }
+}
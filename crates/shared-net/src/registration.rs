@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::env::VarError;
+
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::op;
+
+// A key the client and server both hold, the client proves it has it without ever sending it
+#[derive(Clone)]
+pub struct Secret(hmac::Key);
+
+impl Secret {
+    pub fn new(secret: &[u8]) -> Self {
+        Self(hmac::Key::new(hmac::HMAC_SHA256, secret))
+    }
+
+    pub fn from_env(key: &str) -> Result<Self, VarError> {
+        Ok(Self::new(std::env::var(key)?.as_bytes()))
+    }
+
+    pub(crate) fn prove(&self, flavor: op::Flavor, nonce: op::NonceType) -> op::ProofType {
+        let mut proof = op::ProofType::default();
+        proof.copy_from_slice(hmac::sign(&self.0, &signed(flavor, nonce)).as_ref());
+        proof
+    }
+
+    pub(crate) fn verify(&self, flavor: op::Flavor, nonce: op::NonceType, proof: &op::ProofType) -> bool {
+        hmac::verify(&self.0, &signed(flavor, nonce), proof).is_ok()
+    }
+}
+
+// The flavor is signed too, a proof for one flavor is no good for another
+fn signed(flavor: op::Flavor, nonce: op::NonceType) -> Vec<u8> {
+    let mut signed = vec![flavor.into()];
+    signed.extend_from_slice(&nonce.to_le_bytes());
+    signed
+}
+
+pub(crate) fn nonce() -> Option<op::NonceType> {
+    let mut bytes = [0_u8; size_of::<op::NonceType>()];
+    SystemRandom::new().fill(&mut bytes).ok()?;
+    Some(op::NonceType::from_le_bytes(bytes))
+}
+
+// Which flavors may register with a server, and which of them have to answer a challenge first.
+// Until a flavor is allowed any flavor may register unchallenged.
+#[derive(Clone, Default)]
+pub struct Registration {
+    flavors: Option<HashMap<op::Flavor, Option<Secret>>>,
+}
+
+impl Registration {
+    pub fn allow(mut self, flavor: op::Flavor) -> Self {
+        self.flavors.get_or_insert_default().insert(flavor, None);
+        self
+    }
+
+    pub fn with_secret(mut self, flavor: op::Flavor, secret: Secret) -> Self {
+        self.flavors.get_or_insert_default().insert(flavor, Some(secret));
+        self
+    }
+
+    // Ok(None) registers straight away, Ok(Some(secret)) has to be proven first
+    pub(crate) fn admit(&self, flavor: op::Flavor) -> Result<Option<&Secret>, op::RejectReason> {
+        match &self.flavors {
+            None => Ok(None),
+            Some(flavors) => flavors.get(&flavor).map(Option::as_ref).ok_or(op::RejectReason::Unexpected(flavor)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Registration, Secret, nonce};
+    use crate::op::{self, Flavor, RejectReason};
+
+    #[test]
+    fn test_prove() {
+        let secret = Secret::new(b"courtyard");
+        let nonce = nonce().unwrap();
+        let proof = secret.prove(Flavor::Hall, nonce);
+
+        assert!(secret.verify(Flavor::Hall, nonce, &proof));
+        assert!(!secret.verify(Flavor::Lookout, nonce, &proof));
+        assert!(!secret.verify(Flavor::Hall, nonce.wrapping_add(1), &proof));
+        assert!(!Secret::new(b"guess").verify(Flavor::Hall, nonce, &proof));
+        assert_ne!(nonce, super::nonce().unwrap());
+    }
+
+    #[test]
+    fn test_admit() {
+        assert!(matches!(Registration::default().admit(Flavor::Vagabond), Ok(None)));
+
+        let registration = Registration::default().allow(Flavor::Vagabond).with_secret(Flavor::Hall, Secret::new(b"courtyard"));
+        assert!(matches!(registration.admit(Flavor::Vagabond), Ok(None)));
+        assert!(matches!(registration.admit(Flavor::Hall), Ok(Some(_))));
+        assert_eq!(registration.admit(Flavor::Lookout).err(), Some(RejectReason::Unexpected(op::Flavor::Lookout)));
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use tokio::sync::oneshot;
+use tokio::time::{Duration, timeout};
+
+use crate::channel::VSender;
+use crate::{CorrelationType, NodeType, RoutedMessage, SizedBuffer, SizedBufferError, op};
+
+#[derive(Debug)]
+pub enum RequestError {
+    Buffer(SizedBufferError),
+    Closed,
+    Timeout,
+    Undeliverable(op::UndeliverableReason),
+}
+
+#[derive(Default)]
+struct Pending {
+    last: CorrelationType,
+    waiting: HashMap<CorrelationType, oneshot::Sender<Result<SizedBuffer, RequestError>>>,
+}
+
+impl Pending {
+    fn insert(&mut self, reply_tx: oneshot::Sender<Result<SizedBuffer, RequestError>>) -> CorrelationType {
+        loop {
+            self.last = self.last.wrapping_add(1);
+            if !self.waiting.contains_key(&self.last) {
+                self.waiting.insert(self.last, reply_tx);
+                return self.last;
+            }
+        }
+    }
+}
+
+// Sends [Route][Request(correlation)][Command][body] and waits for the matching Reply
+#[derive(Clone)]
+pub struct Requester {
+    tx: VSender<RoutedMessage>,
+    pending: Arc<Mutex<Pending>>,
+}
+
+impl Requester {
+    pub fn new(tx: VSender<RoutedMessage>) -> Self {
+        Self {
+            tx,
+            pending: Arc::new(Mutex::new(Pending::default())),
+        }
+    }
+
+    // Resolves to the reply positioned at its [Command][payload]
+    pub async fn request(&self, route: op::Route, command: op::Command, mut body: SizedBuffer, limit: Duration) -> Result<SizedBuffer, RequestError> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        let waiting = Waiting {
+            requester: self,
+            correlation: self.lock().insert(reply_tx),
+        };
+
+        body.prepend(&command).map_err(RequestError::Buffer)?;
+        body.prepend(&op::Command::Request(waiting.correlation)).map_err(RequestError::Buffer)?;
+        body.prepend(&route).map_err(RequestError::Buffer)?;
+        self.tx.send_async(RoutedMessage::new(route, body)).await.map_err(|_| RequestError::Closed)?;
+
+        match timeout(limit, reply_rx).await {
+            Ok(Ok(reply)) => reply,
+            Ok(Err(_)) => Err(RequestError::Closed),
+            Err(_) => Err(RequestError::Timeout),
+        }
+    }
+
+    pub fn outstanding(&self) -> usize {
+        self.lock().waiting.len()
+    }
+
+    // Expects the buffer just past [Reply(correlation)], i.e. at [sender][Command][payload]
+    pub(crate) fn resolve(&self, correlation: CorrelationType, mut reply: SizedBuffer) -> bool {
+        let Some(reply_tx) = self.lock().waiting.remove(&correlation) else {
+            return false;
+        };
+        reply.pull::<NodeType>().is_ok() && reply_tx.send(Ok(reply)).is_ok()
+    }
+
+    // Courtyard could not deliver the request, so no reply is coming
+    pub(crate) fn fail(&self, correlation: CorrelationType, reason: op::UndeliverableReason) -> bool {
+        let Some(reply_tx) = self.lock().waiting.remove(&correlation) else {
+            return false;
+        };
+        reply_tx.send(Err(RequestError::Undeliverable(reason))).is_ok()
+    }
+
+    // Replies can no longer arrive once the connection is gone
+    pub(crate) fn cancel_all(&self) {
+        self.lock().waiting.clear();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Pending> {
+        self.pending.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// Forgets the correlation id however the request ends, including when the caller drops it
+struct Waiting<'a> {
+    requester: &'a Requester,
+    correlation: CorrelationType,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.requester.lock().waiting.remove(&self.correlation);
+    }
+}
+
+// The envelope of a request as delivered by Courtyard: [Request(correlation)][sender][Command][body]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Request {
+    pub correlation: CorrelationType,
+    pub sender: NodeType,
+    pub command: op::Command,
+}
+
+impl Request {
+    // Expects the buffer just past [Request(correlation)]
+    pub fn read(correlation: CorrelationType, buf: &mut SizedBuffer) -> Result<Self, SizedBufferError> {
+        Ok(Self {
+            correlation,
+            sender: buf.pull::<NodeType>()?,
+            command: buf.pull::<op::Command>()?,
+        })
+    }
+
+    pub fn reply(&self, command: op::Command, mut body: SizedBuffer) -> Result<RoutedMessage, SizedBufferError> {
+        let route = op::Route::One(self.sender);
+        body.prepend(&command)?;
+        body.prepend(&op::Command::Reply(self.correlation))?;
+        body.prepend(&route)?;
+        Ok(RoutedMessage::new(route, body))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::time::Duration;
+
+    use super::{Request, RequestError, Requester};
+    use crate::channel::{QueueConfig, bounded};
+    use crate::{NodeType, SizedBuffer, op};
+
+    // What Courtyard does to a frame: swap the route for the sender id
+    fn forward(sender: NodeType, mut buf: SizedBuffer) -> SizedBuffer {
+        let _ = buf.pull::<op::Route>().unwrap();
+        let command = buf.pull::<op::Command>().unwrap();
+        let mut out = SizedBuffer::new(64);
+        out.push(&command).unwrap();
+        out.push(&sender).unwrap();
+        out.xfer_bytes(&mut buf).unwrap();
+        out
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let (tx, mut rx) = bounded(QueueConfig::default());
+        let requester = Requester::new(tx);
+
+        let waiter = requester.clone();
+        let pending = tokio::spawn(async move { waiter.request(op::Route::Any(op::Flavor::Archive), op::Command::Inventory(1), SizedBuffer::from(&7_u64).unwrap(), Duration::from_secs(10)).await });
+
+        let sent = rx.recv().await.unwrap();
+        assert_eq!(sent.route, op::Route::Any(op::Flavor::Archive));
+        let mut delivered = forward(5, sent.buf);
+        let op::Command::Request(correlation) = delivered.pull::<op::Command>().unwrap() else {
+            panic!("expected a request envelope");
+        };
+        let request = Request::read(correlation, &mut delivered).unwrap();
+        assert_eq!((request.sender, request.command), (5, op::Command::Inventory(1)));
+        assert_eq!(delivered.pull::<u64>().unwrap(), 7);
+
+        let mut answer = forward(9, request.reply(op::Command::Inventory(2), SizedBuffer::from(&11_u64).unwrap()).unwrap().buf);
+        assert_eq!(answer.pull::<op::Command>().unwrap(), op::Command::Reply(correlation));
+        assert!(requester.resolve(correlation, answer.clone()));
+        assert!(!requester.resolve(correlation, answer));
+
+        let mut reply = pending.await.unwrap().unwrap();
+        assert_eq!(reply.pull::<op::Command>().unwrap(), op::Command::Inventory(2));
+        assert_eq!(reply.pull::<u64>().unwrap(), 11);
+        assert_eq!(requester.outstanding(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timeout() {
+        let (tx, _rx) = bounded(QueueConfig::default());
+        let requester = Requester::new(tx);
+
+        let result = requester.request(op::Route::Any(op::Flavor::Lookout), op::Command::Authorize, SizedBuffer::new(0), Duration::from_secs(5)).await;
+        assert!(matches!(result, Err(RequestError::Timeout)));
+        assert_eq!(requester.outstanding(), 0);
+
+        let waiter = requester.clone();
+        let pending = tokio::spawn(async move { waiter.request(op::Route::Any(op::Flavor::Lookout), op::Command::Authorize, SizedBuffer::new(0), Duration::from_secs(5)).await });
+        tokio::task::yield_now().await;
+        requester.cancel_all();
+        assert!(matches!(pending.await.unwrap(), Err(RequestError::Closed)));
+    }
+}
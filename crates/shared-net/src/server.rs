@@ -1,37 +1,178 @@
-use std::collections::HashMap;
-use std::io;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::RangeInclusive;
 use std::sync::Arc;
 
-use tokio::io::{AsyncReadExt, WriteHalf};
-use tokio::net::TcpListener;
-use tokio::signal;
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
-use tokio::sync::{mpsc, Mutex};
-use tracing::{error, info};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tokio::task::AbortHandle;
+use tokio::time::{interval, timeout, Instant, MissedTickBehavior};
+use tracing::{debug, error, info, warn};
 
-use crate::util::write_buf;
-use crate::{op, IdMessage, RoutedMessage, SizedBuffer};
+use crate::balance::{AnyStrategy, Balancer, InFlight};
+use crate::channel::{bounded, OverflowPolicy, QueueConfig, VReceiver, VSender};
+use crate::directory::Directory;
+use crate::framing::FrameReader;
+use crate::handler::{HandlerStats, Handlers, Process};
+use crate::heartbeat::{beat, Heartbeat};
+use crate::lanes::{lanes, Lanes};
+use crate::ratelimit::{Admission, Limiter, RateLimitPolicy, RateLimits, RateViolation};
+use crate::registration::{self, Registration};
+use crate::shutdown::{CancellationToken, DRAIN_LIMIT};
+use crate::transport;
+use crate::util::{now, write_buf};
+use crate::{op, tls, Bufferable, IdMessage, NodeType, RoutedMessage, SizedBuffer, SizedBufferError, TimestampType};
 
-struct VConnection<T> {
-    write: WriteHalf<T>,
+#[derive(Clone)]
+pub struct VServerConfig {
+    pub interface: String,
+    pub tls: Option<Arc<tls::ServerConfig>>,
+    pub heartbeat: Heartbeat,
+    pub queue: QueueConfig,
+    pub any_strategy: AnyStrategy,
+    pub in_flight: InFlight,
+    pub shutdown: CancellationToken,
+    pub handler_stats: HandlerStats,
+    pub registration: Registration,
+    pub directory: Directory,
+    pub node_ids: RangeInclusive<NodeType>,
+    pub rate_limits: RateLimits,
+}
+
+impl VServerConfig {
+    pub fn new(interface: String) -> Self {
+        Self {
+            interface,
+            tls: None,
+            heartbeat: Heartbeat::default(),
+            queue: QueueConfig::default(),
+            any_strategy: AnyStrategy::default(),
+            in_flight: InFlight::default(),
+            shutdown: CancellationToken::new(),
+            handler_stats: HandlerStats::default(),
+            registration: Registration::default(),
+            directory: Directory::default(),
+            node_ids: 1..=NodeType::MAX,
+            rate_limits: RateLimits::default(),
+        }
+    }
+
+    pub fn with_tls(mut self, tls: Arc<tls::ServerConfig>) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    pub fn with_heartbeat(mut self, heartbeat: Heartbeat) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    pub fn with_queue(mut self, queue: QueueConfig) -> Self {
+        self.queue = queue;
+        self
+    }
+
+    pub fn with_any_strategy(mut self, any_strategy: AnyStrategy) -> Self {
+        self.any_strategy = any_strategy;
+        self
+    }
+
+    // Shared with whatever passes replies on without the server, so LeastOutstanding sees them settle
+    pub fn with_in_flight(mut self, in_flight: InFlight) -> Self {
+        self.in_flight = in_flight;
+        self
+    }
+
+    pub fn with_shutdown(mut self, shutdown: CancellationToken) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    pub fn with_handler_stats(mut self, handler_stats: HandlerStats) -> Self {
+        self.handler_stats = handler_stats;
+        self
+    }
+
+    pub fn with_registration(mut self, registration: Registration) -> Self {
+        self.registration = registration;
+        self
+    }
+
+    pub fn with_directory(mut self, directory: Directory) -> Self {
+        self.directory = directory;
+        self
+    }
+
+    // Routers sharing a mesh each hand out their own range, so an id names one connection across all of them
+    pub fn with_node_ids(mut self, node_ids: RangeInclusive<NodeType>) -> Self {
+        self.node_ids = node_ids;
+        self
+    }
+
+    pub fn with_rate_limits(mut self, rate_limits: RateLimits) -> Self {
+        self.rate_limits = rate_limits;
+        self
+    }
+}
+
+impl From<String> for VServerConfig {
+    fn from(value: String) -> Self {
+        Self::new(value)
+    }
+}
+
+// A registration waiting on the proof for its nonce
+struct Challenge {
+    flavor: op::Flavor,
+    negotiated: op::Handshake,
+    nonce: op::NonceType,
+}
+
+struct VConnection {
+    queue: Lanes,
     flavor: Option<op::Flavor>,
+    challenge: Option<Challenge>,
+    reader: AbortHandle,
+    writer: AbortHandle,
+    last_seen: Instant,
+    connected: TimestampType,
+    received: op::Traffic,
+    sent: op::Traffic,
+    limiter: Limiter,
+}
+
+impl VConnection {
+    fn owns(&self, task: tokio::task::Id) -> bool {
+        self.reader.id() == task || self.writer.id() == task
+    }
 }
 
-type VConnectionMap<T> = HashMap<u8, VConnection<T>>;
+type VConnectionMap = HashMap<NodeType, VConnection>;
 
-type FnProcess<T> = fn(context: T, UnboundedSender<RoutedMessage>, msg: IdMessage) -> bool;
+type ClosedReceiver = mpsc::UnboundedReceiver<(NodeType, tokio::task::Id)>;
 
-pub async fn async_server<T>(context: T, external_tx: UnboundedSender<RoutedMessage>, mut external_rx: UnboundedReceiver<RoutedMessage>, interface: String, process: FnProcess<T>) -> Result<(), ()>
+type FnDisconnect<T> = fn(context: T, VSender<RoutedMessage>, id: NodeType);
+
+pub async fn async_server<T>(context: T, external_tx: VSender<RoutedMessage>, mut external_rx: VReceiver<RoutedMessage>, config: impl Into<VServerConfig>, process: impl Process<T, IdMessage, bool>, disconnect: FnDisconnect<T>) -> Result<(), ()>
 where
     T: Clone,
 {
-    let listener = TcpListener::bind(interface).await.unwrap();
+    let config = config.into();
+    let mut listener = transport::Listener::bind(&config.interface).await.unwrap();
+
+    let mut connections = VConnectionMap::new();
+    let mut balancer = Balancer::new(config.any_strategy);
+    let mut handlers = Handlers::new(config.handler_stats.clone());
+    let mut last_id = *config.node_ids.end();
 
-    let connections = Arc::new(Mutex::new(VConnectionMap::new()));
-    let mut last_id = 0_u8;
+    // readers wait for room so a flood from one peer turns into TCP backpressure
+    let internal = QueueConfig::new(config.queue.capacity, OverflowPolicy::Block);
+    let (accepted_tx, mut accepted_rx) = mpsc::unbounded_channel();
+    let (incoming_tx, mut incoming_rx) = bounded(internal);
+    let (outgoing_tx, mut outgoing_rx) = bounded(internal);
+    let (closed_tx, mut closed_rx) = mpsc::unbounded_channel();
 
-    let (incoming_tx, mut incoming_rx) = mpsc::unbounded_channel();
-    let (outgoing_tx, mut outgoing_rx) = mpsc::unbounded_channel();
+    let mut heartbeat = interval(config.heartbeat.interval);
+    heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
     let mut cleanup_needed = Vec::new();
 
@@ -39,91 +180,184 @@ where
         tokio::select! {
             result = listener.accept() => {
                 // handle connections
-                let stream = match result {
-                    Ok((socket,_)) => socket,
+                let (socket, peer_addr) = match result {
+                    Ok(accepted) => accepted,
                     Err(_) => continue,
                 };
 
-                let local_addr = stream.local_addr().unwrap();
-                let (mut read, write) = tokio::io::split(stream);
-
-                let connection = VConnection {
-                    write,
-                    flavor: None,
-                };
+                // TLS handshakes happen off the main loop so a slow peer cannot stall routing
+                let tls = config.tls.clone();
+                let accepted_tx = accepted_tx.clone();
+                tokio::spawn(async move {
+                    match transport::accept(socket, tls).await {
+                        Ok(stream) => {
+                            let _ = accepted_tx.send((stream, peer_addr));
+                        }
+                        Err(err) => error!(%peer_addr, ?err),
+                    }
+                });
+            }
+            Some((stream, peer_addr)) = accepted_rx.recv() => {
+                let (read, mut write) = tokio::io::split(stream);
 
-                let mut connections = connections.lock().await;
-                let id = match next_available_id(&connections, last_id) {
+                let id = match next_available_id(&connections, &config.node_ids, last_id) {
                     Ok(id) => id,
                     Err(_) => continue,
                 };
-                info!("Connection {} from {}", id, local_addr);
-                connections.insert(id, connection);
+                info!("Connection {} from {}", id, peer_addr);
                 last_id = id;
 
                 let incoming_tx = incoming_tx.clone();
-
-                tokio::spawn( async move {
+                let reader_closed_tx = closed_tx.clone();
+                let reader = tokio::spawn( async move {
+                    let mut frames = FrameReader::new(read);
                     loop {
-                        let mut size_buf = [0_u8; SizedBuffer::sizesize()];
-                        let error = match read.read(&mut size_buf[..]).await {
-                            Ok(bytes) => {
-                                let mut error = false;
-                                if bytes == SizedBuffer::sizesize() {
-                                    let expected_bytes = SizedBuffer::extract_size(&size_buf);
-                                    let mut buf = SizedBuffer::new(expected_bytes);
-
-                                    error = match read.read(&mut buf.raw[SizedBuffer::sizesize()..]).await {
-                                        Ok(bytes) => {
-
-                                            if bytes != expected_bytes {
-                                                error!("Bytes:{} Expected:{}", bytes, expected_bytes);
-                                                true
-                                            } else {
-                                                buf.set_size(expected_bytes);
-                                                incoming_tx.send( IdMessage { id, buf } ).is_err()
-                                            }
-                                        }
-                                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => false,
-                                        Err(_) => true,
-                                    }
-                                } else if bytes == 0 {
-                                    error = true;
+                        match frames.next_frame().await {
+                            Ok(Some(buf)) => {
+                                if incoming_tx.send_async( IdMessage { id, flavor: op::Flavor::NoOp, buf } ).await.is_err() {
+                                    break;
                                 }
-                                error
                             }
-                            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => false,
-                            Err(_) => true,
-                        };
-                        if error {
+                            Ok(None) => break,
+                            Err(err) => {
+                                error!(id, ?err);
+                                break;
+                            }
+                        }
+                    }
+                    let _ = reader_closed_tx.send((id, tokio::task::id()));
+                });
+
+                let (queue, mut queue_rx) = lanes(config.queue);
+                let writer_closed_tx = closed_tx.clone();
+                let writer = tokio::spawn(async move {
+                    while let Some(buf) = queue_rx.recv().await {
+                        if let Err(err) = write_buf(&mut write, &buf).await {
+                            error!(id, ?err);
                             break;
                         }
                     }
+                    let _ = write.shutdown().await;
+                    let _ = writer_closed_tx.send((id, tokio::task::id()));
                 });
+
+                let connection = VConnection {
+                    queue,
+                    flavor: None,
+                    challenge: None,
+                    reader: reader.abort_handle(),
+                    writer: writer.abort_handle(),
+                    last_seen: Instant::now(),
+                    connected: now(),
+                    received: op::Traffic::default(),
+                    sent: op::Traffic::default(),
+                    limiter: Limiter::default(),
+                };
+                connections.insert(id, connection);
+            }
+            Some((id, task)) = closed_rx.recv() => {
+                // ignore tasks of connections that were already replaced
+                if connections.get(&id).is_some_and(|cx| cx.owns(task)) {
+                    cleanup_needed.push(id);
+                }
+            }
+            _ = heartbeat.tick() => {
+                // only the sweep rides on the heartbeat, how long a request counts is up to the InFlight
+                config.in_flight.expire();
+                for (id, cx) in connections.iter() {
+                    let stats = cx.queue.stats();
+                    if stats.depth > 0 || stats.dropped > 0 {
+                        debug!(id, ?stats);
+                    }
+                    // a stalled handshake times out too, but gets no Ping since one could beat the Hello out
+                    if config.heartbeat.is_expired(cx.last_seen) {
+                        info!("Timed out {}", id);
+                        cleanup_needed.push(*id);
+                    } else if cx.flavor.is_some() && cx.queue.send(beat(op::Command::Ping)).is_err() && cx.queue.is_closed() {
+                        cleanup_needed.push(*id);
+                    }
+                }
             }
             Some(msg) = incoming_rx.recv() => {
                 let mut msg = msg;
                 let id = msg.id;
+                if let Some(cx) = connections.get_mut(&id) {
+                    cx.last_seen = Instant::now();
+                }
                 let builtin = msg.buf.pull::<op::Command>();
                 let is_ok = match builtin {
                     Ok(op::Command::NoOp) => false,
+                    Ok(op::Command::Ping) => match connections.get(&id) {
+                        Some(cx) => cx.queue.send(beat(op::Command::Pong)).is_ok(),
+                        None => false,
+                    },
+                    Ok(op::Command::Pong) => true,
                     Ok(op::Command::Register) => {
-                        if let Ok(flavor) = msg.buf.pull::<op::Flavor>() {
-                            let mut connections = connections.lock().await;
-                            if let Some(cx) = connections.get_mut(&id) {
-                                cx.flavor = Some(flavor);
+                        let flavor = msg.buf.pull::<op::Flavor>();
+                        let handshake = msg.buf.pull::<op::Handshake>().unwrap_or(op::Handshake {
+                            version: 0,
+                            capabilities: 0,
+                        });
+                        let admitted = match (flavor, op::Handshake::default().negotiate(&handshake)) {
+                            (Ok(flavor), Ok(negotiated)) => config.registration.admit(flavor).map(|secret| (flavor, negotiated, secret.is_some())),
+                            (Ok(_), Err(reason)) => Err(reason),
+                            (Err(_), _) => Err(op::RejectReason::Malformed),
+                        };
+                        match (admitted, connections.get_mut(&id)) {
+                            (Ok((flavor, negotiated, false)), Some(cx)) => welcome(cx, id, flavor, negotiated, &config.directory),
+                            (Ok((flavor, negotiated, true)), Some(cx)) => match registration::nonce() {
+                                Some(nonce) => {
+                                    cx.flavor = None;
+                                    config.directory.remove(id);
+                                    cx.challenge = Some(Challenge { flavor, negotiated, nonce });
+                                    let mut out = SizedBuffer::new(32);
+                                    out.push(&op::Command::Challenge).and_then(|_| out.push(&nonce)).is_ok() && cx.queue.send(out).is_ok()
+                                }
+                                None => false,
+                            },
+                            (Ok(_), None) => false,
+                            (Err(reason), _) => {
+                                refuse(&mut connections, &config.directory, id, reason);
+                                false
                             }
-                            info!("Registered {} as {:?}", id, flavor);
-                            let mut out = SizedBuffer::new(32);
-                            out.push(&op::Command::Hello).ok().and_then(|_| outgoing_tx.send(RoutedMessage { route: op::Route::One(id), buf: out }).ok()).is_some()
-                        } else {
-                            false
                         }
                     }
-                    Ok(_) => {
-                        msg.buf.rewind();
-                        process( context.clone(), outgoing_tx.clone(), msg )
+                    Ok(op::Command::Challenge) => {
+                        let proof = msg.buf.pull::<op::ProofType>();
+                        let challenge = connections.get_mut(&id).and_then(|cx| cx.challenge.take());
+                        let proven = match (&challenge, proof) {
+                            (Some(challenge), Ok(proof)) => config.registration.admit(challenge.flavor).is_ok_and(|secret| secret.is_some_and(|secret| secret.verify(challenge.flavor, challenge.nonce, &proof))),
+                            _ => false,
+                        };
+                        match (challenge, connections.get_mut(&id)) {
+                            (Some(challenge), Some(cx)) if proven => welcome(cx, id, challenge.flavor, challenge.negotiated, &config.directory),
+                            _ => {
+                                refuse(&mut connections, &config.directory, id, op::RejectReason::Unauthorized);
+                                false
+                            }
+                        }
                     }
+                    // a server that has no business answering keeps Stewards from registering
+                    Ok(op::Command::Status) => match connections.get(&id) {
+                        Some(cx) if cx.flavor == Some(op::Flavor::Steward) => match status(&connections) {
+                            Ok(out) => cx.queue.send(out).is_ok(),
+                            Err(_) => false,
+                        },
+                        _ => false,
+                    },
+                    Ok(command) => match connections.get_mut(&id) {
+                        Some(cx) if let Some(flavor) = cx.flavor => match cx.limiter.admit(&config.rate_limits, command, Instant::now()) {
+                            Admission::Admitted => {
+                                cx.received.count(&msg.buf);
+                                msg.flavor = flavor;
+                                msg.buf.rewind();
+                                handlers.run(process.process(context.clone(), outgoing_tx.clone(), msg), true)
+                            }
+                            Admission::Limited(first) => limited(&config.rate_limits, id, command, first),
+                        },
+                        // nothing is routed for a connection until it has registered
+                        _ => false,
+                    },
                     Err(_) => false,
                 };
                 if !is_ok {
@@ -132,74 +366,225 @@ where
 
             }
             Some(msg) = external_rx.recv() => {
-                let _ = outgoing_tx.send(msg);
+                route_message(&mut connections, &mut balancer, &config.in_flight, &external_tx, &mut cleanup_needed, msg).await;
             }
             Some(msg) = outgoing_rx.recv() => {
-                // handle outgoing
-                match msg.route {
-                    op::Route::Local => {
-                        let _ = external_tx.send( msg );
+                route_message(&mut connections, &mut balancer, &config.in_flight, &external_tx, &mut cleanup_needed, msg).await;
+            }
+            Some(()) = handlers.next() => {}
+            _ = config.shutdown.cancelled() => {
+                handlers.drain().await;
+                // only what is queued right now, a Local route can feed external_rx forever
+                for _ in 0..outgoing_rx.stats().depth {
+                    if let Some(msg) = outgoing_rx.try_recv() {
+                        route_message(&mut connections, &mut balancer, &config.in_flight, &external_tx, &mut cleanup_needed, msg).await;
+                    }
+                }
+                for _ in 0..external_rx.stats().depth {
+                    if let Some(msg) = external_rx.try_recv() {
+                        route_message(&mut connections, &mut balancer, &config.in_flight, &external_tx, &mut cleanup_needed, msg).await;
                     }
-                    op::Route::One(msg_id) => {
-                        let msg_buf = msg.buf;
-                        let mut connections = connections.lock().await;
+                }
+                config.directory.clear();
+                drain(connections, &mut closed_rx).await;
+                return Ok(())
+            },
+        }
 
-                        if let Some(cx) = connections.get_mut(&msg_id)
-                            && write_buf(&mut cx.write, &msg_buf).await.is_err()
-                        {
-                                cleanup_needed.push(msg_id);
+        for id in cleanup_needed.drain(..) {
+            if let Some(cx) = connections.remove(&id) {
+                info!("Disconnected {}", id);
+                config.directory.remove(id);
+                config.in_flight.forget(id);
+                cx.queue.close();
+                cx.reader.abort();
+                cx.writer.abort();
+                disconnect(context.clone(), outgoing_tx.clone(), id);
+            }
+        }
+    }
+}
 
-                        }
+async fn drain(connections: VConnectionMap, closed_rx: &mut ClosedReceiver) {
+    // dropping each queue lets its writer flush what is left and close the stream
+    let mut writers = connections
+        .into_values()
+        .map(|cx| {
+            cx.reader.abort();
+            cx.writer
+        })
+        .collect::<Vec<_>>();
 
-                    }
-                    op::Route::Any(flavor) => {
-                        let msg_buf = msg.buf;
-                        let mut connections = connections.lock().await;
-                        if let Some((id,cx)) = connections.iter_mut().find(|(_,cx)| cx.flavor == Some(flavor))
-                            && write_buf(&mut cx.write, &msg_buf).await.is_err()
-                        {
-                                cleanup_needed.push(*id);
-                        }
+    let _ = timeout(DRAIN_LIMIT, async {
+        while !writers.is_empty()
+            && let Some((_, task)) = closed_rx.recv().await
+        {
+            writers.retain(|writer| writer.id() != task);
+        }
+    })
+    .await;
 
-                    }
-                    op::Route::All(flavor) => {
-                        let msg_buf = msg.buf;
-                        let mut connections = connections.lock().await;
-                        for (id,cx) in connections.iter_mut().filter(|(_,cx)| cx.flavor == Some(flavor)) {
-                            if write_buf(&mut cx.write, &msg_buf).await.is_err() {
-                                cleanup_needed.push(*id);
-                            }
-                        }
-                    }
-                    op::Route::None => {}
+    for writer in writers {
+        writer.abort();
+    }
+}
+
+async fn route_message(connections: &mut VConnectionMap, balancer: &mut Balancer, in_flight: &InFlight, external_tx: &VSender<RoutedMessage>, cleanup_needed: &mut Vec<NodeType>, msg: RoutedMessage) {
+    in_flight.answered(&msg);
+    match msg.route {
+        op::Route::Local => {
+            let _ = external_tx.send_async(msg).await;
+        }
+        op::Route::One(msg_id) => match connections.get_mut(&msg_id) {
+            Some(cx) => {
+                in_flight.delivered(msg_id, &msg);
+                deliver(msg_id, cx, msg.priority, msg.buf, cleanup_needed)
+            }
+            None => undeliverable(connections, cleanup_needed, msg, op::UndeliverableReason::Disconnected),
+        },
+        op::Route::Any(flavor) => {
+            let candidates = connections.iter().filter(|(_, cx)| cx.flavor == Some(flavor)).map(|(id, _)| (*id, in_flight.count(*id)));
+            match balancer.select(flavor, candidates).and_then(|id| connections.get_mut(&id).map(|cx| (id, cx))) {
+                Some((id, cx)) => {
+                    in_flight.delivered(id, &msg);
+                    deliver(id, cx, msg.priority, msg.buf, cleanup_needed)
                 }
+                None => undeliverable(connections, cleanup_needed, msg, op::UndeliverableReason::Unavailable),
+            }
+        }
+        op::Route::All(flavor) => {
+            for (id, cx) in connections.iter_mut().filter(|(_, cx)| cx.flavor == Some(flavor)) {
+                deliver(*id, cx, msg.priority, msg.buf.clone(), cleanup_needed);
             }
-            _ = signal::ctrl_c() => {
-                cleanup_needed.clear();
-                connections.lock().await.clear();
-                return Err(())
-            },
         }
+        op::Route::Multi(_) => multicast(connections, cleanup_needed, msg),
+        op::Route::None => {}
+    }
+}
+
+// Each Gate gets one copy as [Multicast][Vec<vagabond>][Command][sender][payload], a Gate that is gone is reported to
+// the origin with a Multi route naming just its targets
+fn multicast(connections: &mut VConnectionMap, cleanup_needed: &mut Vec<NodeType>, msg: RoutedMessage) {
+    let op::Route::Multi(targets) = &msg.route else {
+        return;
+    };
+    let mut gates = BTreeMap::<NodeType, Vec<op::Target>>::new();
+    for target in targets {
+        gates.entry(target.gate).or_default().push(*target);
+    }
 
-        if !cleanup_needed.is_empty() {
-            let mut connections = connections.lock().await;
-            connections.retain(|id, _| !cleanup_needed.contains(id));
-            cleanup_needed.clear();
+    for (gate, targets) in gates {
+        let Some(cx) = connections.get_mut(&gate) else {
+            let missed = RoutedMessage {
+                route: op::Route::Multi(targets),
+                ..msg.clone()
+            };
+            undeliverable(connections, cleanup_needed, missed, op::UndeliverableReason::Disconnected);
+            continue;
+        };
+        let vagabonds = targets.iter().map(|target| target.vagabond).collect::<Vec<_>>();
+        let mut buf = msg.buf.clone();
+        if buf.prepend(&vagabonds).and_then(|_| buf.prepend(&op::Command::Multicast)).is_ok() {
+            deliver(gate, cx, msg.priority, buf, cleanup_needed);
         }
     }
 }
 
-fn next_available_id<T>(connections: &VConnectionMap<T>, last_id: u8) -> Result<u8, ()> {
-    let mut id = last_id;
+fn deliver(id: NodeType, cx: &mut VConnection, priority: op::Priority, buf: SizedBuffer, cleanup_needed: &mut Vec<NodeType>) {
+    cx.sent.count(&buf);
+    // only fails once the queue is closed, either by the writer or by the Disconnect policy
+    if cx.queue.send_lane(priority, buf).is_err() {
+        warn!(id, stats = ?cx.queue.stats(), "Slow consumer");
+        cleanup_needed.push(id);
+    }
+}
 
-    loop {
-        id = id.wrapping_add(1);
+// Hands the frame back to its origin behind an [Undeliverable] notice, a frame without an origin is just dropped
+fn undeliverable(connections: &mut VConnectionMap, cleanup_needed: &mut Vec<NodeType>, msg: RoutedMessage, reason: op::UndeliverableReason) {
+    let Some((origin, cx)) = msg.origin.and_then(|origin| connections.get_mut(&origin).map(|cx| (origin, cx))) else {
+        return;
+    };
+    let mut buf = msg.buf;
+    buf.rewind();
+    let Ok(command) = buf.pull::<op::Command>() else {
+        return;
+    };
+    warn!(origin, route = ?msg.route, ?command, %reason, "Undeliverable");
 
-        if id == last_id {
-            break;
-        }
+    let notice = op::Undeliverable {
+        route: msg.route,
+        command,
+        reason,
+    };
+    if buf.prepend(&notice).and_then(|_| buf.prepend(&op::Command::Undeliverable)).is_ok() {
+        deliver(origin, cx, op::Priority::Normal, buf, cleanup_needed);
+    }
+}
+
+fn welcome(cx: &mut VConnection, id: NodeType, flavor: op::Flavor, negotiated: op::Handshake, directory: &Directory) -> bool {
+    cx.flavor = Some(flavor);
+    directory.insert(id, flavor);
+    info!("Registered {} as {:?} (v{})", id, flavor, negotiated.version);
+    let mut out = SizedBuffer::new(32);
+    out.push(&op::Command::Hello).and_then(|_| out.push(&negotiated)).is_ok() && cx.queue.send(out).is_ok()
+}
+
+// Keeps the connection unless the policy says otherwise, reporting only the first frame over the limit
+fn limited(rate_limits: &RateLimits, id: NodeType, command: op::Command, first: bool) -> bool {
+    if first {
+        warn!(id, ?command, "Rate limited");
+        rate_limits.report(RateViolation {
+            id,
+            command,
+        });
+    }
+    rate_limits.policy() == RateLimitPolicy::Drop
+}
+
+// [Status][Vec<ConnectionStatus>] in id order
+fn status(connections: &VConnectionMap) -> Result<SizedBuffer, SizedBufferError> {
+    let mut statuses = connections
+        .iter()
+        .map(|(id, cx)| op::ConnectionStatus {
+            id: *id,
+            flavor: cx.flavor.unwrap_or(op::Flavor::NoOp),
+            connected: cx.connected,
+            received: cx.received,
+            sent: cx.sent,
+        })
+        .collect::<Vec<_>>();
+    statuses.sort_by_key(|status| status.id);
+    let mut out = SizedBuffer::new(op::Command::Status.size_in_buffer() + statuses.size_in_buffer());
+    out.push(&op::Command::Status)?;
+    out.push(&statuses)?;
+    Ok(out)
+}
+
+// dropping the queue lets the writer flush the reject before closing
+fn refuse(connections: &mut VConnectionMap, directory: &Directory, id: NodeType, reason: op::RejectReason) {
+    directory.remove(id);
+    if let Some(cx) = connections.remove(&id) {
+        cx.reader.abort();
+        reject(&cx.queue, id, reason);
+    }
+}
+
+fn reject(queue: &Lanes, id: NodeType, reason: op::RejectReason) {
+    error!("Rejected {}: {}", id, reason);
+    let mut out = SizedBuffer::new(op::Command::Reject.size_in_buffer() + reason.size_in_buffer());
+    if out.push(&op::Command::Reject).and_then(|_| out.push(&reason)).is_ok() {
+        let _ = queue.send(out);
+    }
+}
+
+fn next_available_id<V>(connections: &HashMap<NodeType, V>, node_ids: &RangeInclusive<NodeType>, last_id: NodeType) -> Result<NodeType, ()> {
+    let mut id = last_id;
+
+    for _ in node_ids.clone() {
+        id = if id < *node_ids.start() || id >= *node_ids.end() { *node_ids.start() } else { id + 1 };
 
-        if connections.contains_key(&id) {
+        // 0 is never handed out so services can use it as "no connection"
+        if id == 0 || connections.contains_key(&id) {
             continue;
         }
 
@@ -208,3 +593,533 @@ fn next_available_id<T>(connections: &VConnectionMap<T>, last_id: u8) -> Result<
 
     Err(())
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use tokio::io::{ReadHalf, WriteHalf};
+    use tokio::net::TcpStream;
+    use tokio::sync::mpsc::{self, UnboundedSender};
+    use tokio::time::{Duration, timeout};
+
+    use super::{VServerConfig, next_available_id};
+    use crate::channel::{OverflowPolicy, QueueConfig, VSender, bounded};
+    use crate::framing::FrameReader;
+    use crate::tls::test::TestCerts;
+    use crate::tls::{load_client_config, load_server_config};
+    use crate::util::write_buf;
+    use crate::{AnyStrategy, CancellationToken, Heartbeat, IdMessage, NodeType, RateLimit, RateLimitPolicy, RateLimits, RateViolation, Registration, RoutedMessage, Secret, SizedBuffer, VClientConfig, VClientMode, async_client, async_server, op};
+
+    #[test]
+    fn test_next_available_id() {
+        let mut connections = HashMap::new();
+        let node_ids = 1..=NodeType::MAX;
+        let mut last_id = 0;
+        for _ in 0..1000 {
+            last_id = next_available_id(&connections, &node_ids, last_id).unwrap();
+            connections.insert(last_id, ());
+        }
+        assert_eq!(last_id, 1000);
+
+        connections.remove(&3);
+        assert_eq!(next_available_id(&connections, &node_ids, NodeType::MAX - 1), Ok(NodeType::MAX));
+        assert_eq!(next_available_id(&connections, &node_ids, NodeType::MAX), Ok(3));
+
+        let full = (1..=NodeType::MAX).map(|id| (id, ())).collect::<HashMap<_, _>>();
+        assert_eq!(next_available_id(&full, &node_ids, 7), Err(()));
+
+        let block = 4096..=8191;
+        assert_eq!(next_available_id(&connections, &block, *block.end()), Ok(4096));
+        assert_eq!(next_available_id(&connections, &block, 5000), Ok(5001));
+        let taken = block.clone().filter(|id| *id != 4100).map(|id| (id, ())).collect::<HashMap<_, _>>();
+        assert_eq!(next_available_id(&taken, &block, 8000), Ok(4100));
+    }
+
+    fn free_interface() -> String {
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        format!("127.0.0.1:{}", port)
+    }
+
+    fn echo<T>(_context: T, tx: VSender<RoutedMessage>, msg: IdMessage) -> bool {
+        tx.send(RoutedMessage::new(op::Route::One(msg.id), msg.buf)).is_ok()
+    }
+
+    // what Courtyard does, naming the sender as the origin
+    fn forward<T>(_context: T, tx: VSender<RoutedMessage>, mut msg: IdMessage) -> bool {
+        let (Ok(route), Ok(command)) = (msg.buf.pull::<op::Route>(), msg.buf.pull::<op::Command>()) else {
+            return false;
+        };
+        msg.buf.prepend(&msg.id).and_then(|_| msg.buf.prepend(&command)).is_ok() && tx.send(RoutedMessage::new(route, msg.buf).with_origin(msg.id)).is_ok()
+    }
+
+    fn disconnected(context: UnboundedSender<NodeType>, _tx: VSender<RoutedMessage>, id: NodeType) {
+        let _ = context.send(id);
+    }
+
+    fn received(context: UnboundedSender<SizedBuffer>, _tx: VSender<RoutedMessage>, buf: SizedBuffer) -> VClientMode {
+        let _ = context.send(buf);
+        VClientMode::Continue
+    }
+
+    // the heartbeat may get a Ping in at any point
+    async fn next_message(frames: &mut FrameReader<ReadHalf<TcpStream>>) -> Option<(op::Command, SizedBuffer)> {
+        while let Some(mut buf) = frames.next_frame().await.unwrap() {
+            match buf.pull::<op::Command>().unwrap() {
+                op::Command::Ping => continue,
+                command => return Some((command, buf)),
+            }
+        }
+        None
+    }
+
+    async fn next_command(frames: &mut FrameReader<ReadHalf<TcpStream>>) -> Option<op::Command> {
+        next_message(frames).await.map(|(command, _)| command)
+    }
+
+    #[tokio::test]
+    async fn test_tls_round_trip() {
+        let certs = TestCerts::generate("round-trip");
+        let interface = free_interface();
+
+        let server_config = VServerConfig::new(interface.clone()).with_tls(load_server_config(&certs.cert, &certs.key).unwrap());
+        let (server_tx, _server_rx) = bounded(QueueConfig::default());
+        let (_external_tx, external_rx) = bounded(QueueConfig::default());
+        tokio::spawn(async_server((), server_tx, external_rx, server_config, echo, |_, _, _| {}));
+
+        let client_config = VClientConfig::new(interface).with_tls(load_client_config(&certs.cert).unwrap()).with_server_name("localhost".to_string());
+        let (received_tx, mut received_rx) = mpsc::unbounded_channel();
+        let (client_tx, _client_rx) = bounded(QueueConfig::default());
+        let (external_tx, external_rx) = bounded(QueueConfig::default());
+        tokio::spawn(async_client(received_tx, op::Flavor::Gate, client_tx, external_rx, client_config, received, |_, _, _| {}));
+
+        let mut hello = timeout(Duration::from_secs(10), received_rx.recv()).await.unwrap().unwrap();
+        assert_eq!(hello.pull::<op::Command>().unwrap(), op::Command::Hello);
+
+        let mut out = SizedBuffer::new(32);
+        out.push(&op::Command::Message(7)).unwrap();
+        out.push(&"over tls".to_string()).unwrap();
+        external_tx.send(RoutedMessage::new(op::Route::None, out)).unwrap();
+
+        let mut echoed = timeout(Duration::from_secs(10), received_rx.recv()).await.unwrap().unwrap();
+        assert_eq!(echoed.pull::<op::Command>().unwrap(), op::Command::Message(7));
+        assert_eq!(echoed.pull::<String>().unwrap(), "over tls");
+    }
+
+    #[tokio::test]
+    async fn test_silent_peer_disconnected() {
+        let interface = free_interface();
+        let heartbeat = Heartbeat::new(Duration::from_millis(50), Duration::from_millis(200));
+        let server_config = VServerConfig::new(interface.clone()).with_heartbeat(heartbeat);
+
+        let (disconnected_tx, mut disconnected_rx) = mpsc::unbounded_channel();
+        let (server_tx, _server_rx) = bounded(QueueConfig::default());
+        let (_external_tx, external_rx) = bounded(QueueConfig::default());
+        tokio::spawn(async_server(disconnected_tx, server_tx, external_rx, server_config, echo, disconnected));
+
+        let stream = loop {
+            if let Ok(stream) = TcpStream::connect(&interface).await {
+                break stream;
+            }
+            tokio::task::yield_now().await;
+        };
+        let (read, mut write) = tokio::io::split(stream);
+        let mut frames = FrameReader::new(read);
+
+        let mut register = SizedBuffer::new(32);
+        register.push(&op::Command::Register).unwrap();
+        register.push(&op::Flavor::Gate).unwrap();
+        register.push(&op::Handshake::default()).unwrap();
+        write_buf(&mut write, &register).await.unwrap();
+
+        let mut hello = frames.next_frame().await.unwrap().unwrap();
+        assert_eq!(hello.pull::<op::Command>().unwrap(), op::Command::Hello);
+        let mut ping = frames.next_frame().await.unwrap().unwrap();
+        assert_eq!(ping.pull::<op::Command>().unwrap(), op::Command::Ping);
+
+        let id = timeout(Duration::from_secs(10), disconnected_rx.recv()).await.unwrap().unwrap();
+        assert_eq!(id, 1);
+        while let Ok(Some(mut buf)) = frames.next_frame().await {
+            assert_eq!(buf.pull::<op::Command>().unwrap(), op::Command::Ping);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_slow_consumer_disconnected() {
+        let interface = free_interface();
+        let server_config = VServerConfig::new(interface.clone()).with_queue(QueueConfig::new(2, OverflowPolicy::Disconnect));
+
+        let (disconnected_tx, mut disconnected_rx) = mpsc::unbounded_channel();
+        let (server_tx, _server_rx) = bounded(QueueConfig::default());
+        let (external_tx, external_rx) = bounded(QueueConfig::default());
+        tokio::spawn(async_server(disconnected_tx, server_tx, external_rx, server_config, echo, disconnected));
+
+        let stream = loop {
+            if let Ok(stream) = TcpStream::connect(&interface).await {
+                break stream;
+            }
+            tokio::task::yield_now().await;
+        };
+        let (_read, mut write) = tokio::io::split(stream);
+        let mut register = SizedBuffer::new(32);
+        register.push(&op::Command::Register).unwrap();
+        register.push(&op::Flavor::Vagabond).unwrap();
+        register.push(&op::Handshake::default()).unwrap();
+        write_buf(&mut write, &register).await.unwrap();
+
+        // never read, so the socket buffers fill and then the queue overflows
+        let payload = vec![0_u8; 1 << 20];
+        let flood = tokio::spawn(async move {
+            loop {
+                let mut buf = SizedBuffer::new(payload.len());
+                buf.push_bytes(&payload).unwrap();
+                if external_tx.send_async(RoutedMessage::new(op::Route::One(1), buf)).await.is_err() {
+                    break;
+                }
+                tokio::task::yield_now().await;
+            }
+        });
+
+        let id = timeout(Duration::from_secs(10), disconnected_rx.recv()).await.unwrap().unwrap();
+        assert_eq!(id, 1);
+        flood.abort();
+    }
+
+    #[tokio::test]
+    async fn test_slow_consumer_does_not_stall() {
+        let interface = free_interface();
+        let server_config = VServerConfig::new(interface.clone()).with_queue(QueueConfig::new(2, OverflowPolicy::Block));
+
+        let (disconnected_tx, mut disconnected_rx) = mpsc::unbounded_channel();
+        let (server_tx, _server_rx) = bounded(QueueConfig::default());
+        let (external_tx, external_rx) = bounded(QueueConfig::default());
+        tokio::spawn(async_server(disconnected_tx, server_tx, external_rx, server_config, echo, disconnected));
+
+        let (_slow, _slow_write) = register(&interface, op::Flavor::Vagabond).await;
+        let (mut fast, mut fast_write) = register(&interface, op::Flavor::Vagabond).await;
+        assert_eq!(next_command(&mut fast).await, Some(op::Command::Hello));
+
+        // the slow one never reads, so its Block queue fills instead of the router waiting on it
+        let payload = vec![0_u8; 1 << 20];
+        let flood = tokio::spawn(async move {
+            loop {
+                let mut buf = SizedBuffer::new(payload.len());
+                buf.push_bytes(&payload).unwrap();
+                if external_tx.send_async(RoutedMessage::new(op::Route::One(1), buf)).await.is_err() {
+                    break;
+                }
+                tokio::task::yield_now().await;
+            }
+        });
+
+        let id = timeout(Duration::from_secs(10), disconnected_rx.recv()).await.unwrap().unwrap();
+        assert_eq!(id, 1);
+        flood.abort();
+
+        write_buf(&mut fast_write, &SizedBuffer::from(&op::Command::Message(1)).unwrap()).await.unwrap();
+        assert_eq!(timeout(Duration::from_secs(10), next_command(&mut fast)).await.unwrap(), Some(op::Command::Message(1)));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drains() {
+        let interface = free_interface();
+        let shutdown = CancellationToken::new();
+        let server_config = VServerConfig::new(interface.clone()).with_shutdown(shutdown.clone());
+
+        let (server_tx, _server_rx) = bounded(QueueConfig::default());
+        let (external_tx, external_rx) = bounded(QueueConfig::default());
+        let server = tokio::spawn(async_server((), server_tx, external_rx, server_config, echo, |_, _, _| {}));
+
+        let stream = loop {
+            if let Ok(stream) = TcpStream::connect(&interface).await {
+                break stream;
+            }
+            tokio::task::yield_now().await;
+        };
+        let (read, mut write) = tokio::io::split(stream);
+        let mut frames = FrameReader::new(read);
+
+        let mut register = SizedBuffer::new(32);
+        register.push(&op::Command::Register).unwrap();
+        register.push(&op::Flavor::Gate).unwrap();
+        register.push(&op::Handshake::default()).unwrap();
+        write_buf(&mut write, &register).await.unwrap();
+
+        assert_eq!(next_command(&mut frames).await, Some(op::Command::Hello));
+
+        for idx in 0..3 {
+            external_tx.send(RoutedMessage::new(op::Route::One(1), SizedBuffer::from(&op::Command::Message(idx)).unwrap())).unwrap();
+        }
+        shutdown.cancel();
+        assert_eq!(timeout(Duration::from_secs(10), server).await.unwrap().unwrap(), Ok(()));
+
+        for idx in 0..3 {
+            assert_eq!(next_command(&mut frames).await, Some(op::Command::Message(idx)));
+        }
+        assert_eq!(next_command(&mut frames).await, None);
+    }
+
+    async fn register(interface: &str, flavor: op::Flavor) -> (FrameReader<ReadHalf<TcpStream>>, WriteHalf<TcpStream>) {
+        let stream = loop {
+            if let Ok(stream) = TcpStream::connect(interface).await {
+                break stream;
+            }
+            tokio::task::yield_now().await;
+        };
+        let (read, mut write) = tokio::io::split(stream);
+
+        let mut register = SizedBuffer::new(32);
+        register.push(&op::Command::Register).unwrap();
+        register.push(&flavor).unwrap();
+        register.push(&op::Handshake::default()).unwrap();
+        write_buf(&mut write, &register).await.unwrap();
+        (FrameReader::new(read), write)
+    }
+
+    #[tokio::test]
+    async fn test_registration_challenge() {
+        let interface = free_interface();
+        let registration = Registration::default().allow(op::Flavor::Vagabond).with_secret(op::Flavor::Hall, Secret::new(b"courtyard"));
+        let server_config = VServerConfig::new(interface.clone()).with_registration(registration);
+        let (server_tx, _server_rx) = bounded(QueueConfig::default());
+        let (_external_tx, external_rx) = bounded(QueueConfig::default());
+        tokio::spawn(async_server((), server_tx, external_rx, server_config, echo, |_, _, _| {}));
+
+        let (mut frames, _write) = register(&interface, op::Flavor::Lookout).await;
+        let (command, mut reject) = next_message(&mut frames).await.unwrap();
+        assert_eq!(command, op::Command::Reject);
+        assert_eq!(reject.pull::<op::RejectReason>().unwrap(), op::RejectReason::Unexpected(op::Flavor::Lookout));
+
+        let (mut frames, mut write) = register(&interface, op::Flavor::Hall).await;
+        assert_eq!(next_command(&mut frames).await, Some(op::Command::Challenge));
+        let mut guess = SizedBuffer::new(64);
+        guess.push(&op::Command::Challenge).unwrap();
+        guess.push(&op::ProofType::default()).unwrap();
+        write_buf(&mut write, &guess).await.unwrap();
+        let (command, mut reject) = next_message(&mut frames).await.unwrap();
+        assert_eq!(command, op::Command::Reject);
+        assert_eq!(reject.pull::<op::RejectReason>().unwrap(), op::RejectReason::Unauthorized);
+
+        // a vagabond may connect, but gets nothing routed before it registers
+        let (mut frames, _write) = register(&interface, op::Flavor::Vagabond).await;
+        assert_eq!(next_command(&mut frames).await, Some(op::Command::Hello));
+        let (read, mut write) = tokio::io::split(TcpStream::connect(&interface).await.unwrap());
+        let mut frames = FrameReader::new(read);
+        write_buf(&mut write, &SizedBuffer::from(&op::Command::Message(1)).unwrap()).await.unwrap();
+        assert_eq!(next_command(&mut frames).await, None);
+
+        let client_config = VClientConfig::new(interface).with_secret(Secret::new(b"courtyard"));
+        let (received_tx, mut received_rx) = mpsc::unbounded_channel();
+        let (client_tx, client_rx) = bounded(QueueConfig::default());
+        tokio::spawn(async_client(received_tx, op::Flavor::Hall, client_tx, client_rx, client_config, received, |_, _, _| {}));
+        let mut hello = timeout(Duration::from_secs(10), received_rx.recv()).await.unwrap().unwrap();
+        assert_eq!(hello.pull::<op::Command>().unwrap(), op::Command::Hello);
+    }
+
+    #[tokio::test]
+    async fn test_handshake_heartbeat() {
+        let interface = free_interface();
+        let secret = Secret::new(b"courtyard");
+        let heartbeat = Heartbeat::new(Duration::from_millis(1), Duration::from_millis(200));
+        let registration = Registration::default().with_secret(op::Flavor::Hall, secret.clone());
+        let server_config = VServerConfig::new(interface.clone()).with_heartbeat(heartbeat).with_registration(registration);
+        let (server_tx, _server_rx) = bounded(QueueConfig::default());
+        let (_external_tx, external_rx) = bounded(QueueConfig::default());
+        tokio::spawn(async_server((), server_tx, external_rx, server_config, echo, |_, _, _| {}));
+
+        // no Ping gets in ahead of the Challenge or the Hello
+        let (mut frames, mut write) = register(&interface, op::Flavor::Hall).await;
+        let mut challenge = frames.next_frame().await.unwrap().unwrap();
+        assert_eq!(challenge.pull::<op::Command>().unwrap(), op::Command::Challenge);
+        let nonce = challenge.pull::<op::NonceType>().unwrap();
+        let mut proof = SizedBuffer::new(64);
+        proof.push(&op::Command::Challenge).unwrap();
+        proof.push(&secret.prove(op::Flavor::Hall, nonce)).unwrap();
+        write_buf(&mut write, &proof).await.unwrap();
+        let mut hello = frames.next_frame().await.unwrap().unwrap();
+        assert_eq!(hello.pull::<op::Command>().unwrap(), op::Command::Hello);
+
+        // a handshake that stops at the Challenge still times out
+        let (mut stalled, _stalled_write) = register(&interface, op::Flavor::Hall).await;
+        let mut challenge = stalled.next_frame().await.unwrap().unwrap();
+        assert_eq!(challenge.pull::<op::Command>().unwrap(), op::Command::Challenge);
+        assert!(timeout(Duration::from_secs(10), stalled.next_frame()).await.unwrap().unwrap().is_none());
+
+        let client_config = VClientConfig::new(interface).with_secret(secret).with_heartbeat(heartbeat);
+        let (received_tx, mut received_rx) = mpsc::unbounded_channel();
+        let (client_tx, client_rx) = bounded(QueueConfig::default());
+        tokio::spawn(async_client(received_tx, op::Flavor::Hall, client_tx, client_rx, client_config, received, |_, _, _| {}));
+        let mut hello = timeout(Duration::from_secs(10), received_rx.recv()).await.unwrap().unwrap();
+        assert_eq!(hello.pull::<op::Command>().unwrap(), op::Command::Hello);
+    }
+
+    #[tokio::test]
+    async fn test_undeliverable() {
+        let interface = free_interface();
+        let (server_tx, _server_rx) = bounded(QueueConfig::default());
+        let (_external_tx, external_rx) = bounded(QueueConfig::default());
+        tokio::spawn(async_server((), server_tx, external_rx, interface.clone(), forward, |_, _, _| {}));
+
+        let (mut frames, mut write) = register(&interface, op::Flavor::Gate).await;
+        assert_eq!(next_command(&mut frames).await, Some(op::Command::Hello));
+
+        let routes = [(op::Route::One(999), op::UndeliverableReason::Disconnected), (op::Route::Any(op::Flavor::Lookout), op::UndeliverableReason::Unavailable)];
+        for (route, _) in &routes {
+            let mut out = SizedBuffer::new(32);
+            out.push(route).and_then(|_| out.push(&op::Command::Authorize)).and_then(|_| out.push(&7_u64)).unwrap();
+            write_buf(&mut write, &out).await.unwrap();
+        }
+        for (route, reason) in routes {
+            let (command, mut notice) = next_message(&mut frames).await.unwrap();
+            assert_eq!(command, op::Command::Undeliverable);
+            assert_eq!(notice.pull::<op::Undeliverable>().unwrap(), op::Undeliverable { route, command: op::Command::Authorize, reason });
+            assert_eq!(notice.pull::<NodeType>().unwrap(), 1);
+            assert_eq!(notice.pull::<u64>().unwrap(), 7);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_least_outstanding() {
+        let interface = free_interface();
+        let server_config = VServerConfig::new(interface.clone()).with_any_strategy(AnyStrategy::LeastOutstanding);
+        let (server_tx, _server_rx) = bounded(QueueConfig::default());
+        let (_external_tx, external_rx) = bounded(QueueConfig::default());
+        tokio::spawn(async_server((), server_tx, external_rx, server_config, forward, |_, _, _| {}));
+
+        let (mut slow, _slow_write) = register(&interface, op::Flavor::Archive).await;
+        assert_eq!(next_command(&mut slow).await, Some(op::Command::Hello));
+        let (mut fast, mut fast_write) = register(&interface, op::Flavor::Archive).await;
+        assert_eq!(next_command(&mut fast).await, Some(op::Command::Hello));
+        let (mut gate, mut gate_write) = register(&interface, op::Flavor::Gate).await;
+        assert_eq!(next_command(&mut gate).await, Some(op::Command::Hello));
+
+        let mut slow_requests = 0;
+        for correlation in 0..10 {
+            let mut out = SizedBuffer::new(32);
+            out.push(&op::Route::Any(op::Flavor::Archive)).and_then(|_| out.push(&op::Command::Request(correlation))).and_then(|_| out.push(&op::Command::Inventory(1))).unwrap();
+            write_buf(&mut gate_write, &out).await.unwrap();
+
+            tokio::select! {
+                Some((command, _)) = next_message(&mut slow) => {
+                    assert_eq!(command, op::Command::Request(correlation));
+                    slow_requests += 1;
+                }
+                Some((command, mut request)) = next_message(&mut fast) => {
+                    assert_eq!(command, op::Command::Request(correlation));
+                    let requester = request.pull::<NodeType>().unwrap();
+                    let mut reply = SizedBuffer::new(32);
+                    reply.push(&op::Route::One(requester)).and_then(|_| reply.push(&op::Command::Reply(correlation))).and_then(|_| reply.push(&op::Command::Inventory(1))).unwrap();
+                    write_buf(&mut fast_write, &reply).await.unwrap();
+                    assert_eq!(next_command(&mut gate).await, Some(op::Command::Reply(correlation)));
+                }
+            }
+        }
+        // the slow Archive sits on the first request, everything after it goes to the one that answers
+        assert_eq!(slow_requests, 1);
+    }
+
+    #[tokio::test]
+    async fn test_status() {
+        let interface = free_interface();
+        let (server_tx, _server_rx) = bounded(QueueConfig::default());
+        let (_external_tx, external_rx) = bounded(QueueConfig::default());
+        tokio::spawn(async_server((), server_tx, external_rx, interface.clone(), echo, |_, _, _| {}));
+
+        let (mut hall, mut hall_write) = register(&interface, op::Flavor::Hall).await;
+        assert_eq!(next_command(&mut hall).await, Some(op::Command::Hello));
+        let message = SizedBuffer::from(&op::Command::Message(1)).unwrap();
+        write_buf(&mut hall_write, &message).await.unwrap();
+        assert_eq!(next_command(&mut hall).await, Some(op::Command::Message(1)));
+
+        let (mut steward, mut steward_write) = register(&interface, op::Flavor::Steward).await;
+        assert_eq!(next_command(&mut steward).await, Some(op::Command::Hello));
+        write_buf(&mut steward_write, &SizedBuffer::from(&op::Command::Status).unwrap()).await.unwrap();
+        let (command, mut status) = next_message(&mut steward).await.unwrap();
+        assert_eq!(command, op::Command::Status);
+        let statuses = status.pull::<Vec<op::ConnectionStatus>>().unwrap();
+        assert_eq!(statuses.iter().map(|status| (status.id, status.flavor)).collect::<Vec<_>>(), vec![(1, op::Flavor::Hall), (2, op::Flavor::Steward)]);
+        let traffic = op::Traffic {
+            messages: 1,
+            bytes: message.size() as u64,
+        };
+        assert_eq!((statuses[0].received, statuses[0].sent), (traffic, traffic));
+        assert!(statuses[0].connected <= statuses[1].connected);
+
+        // only a Steward gets an answer, anyone else asking is dropped
+        write_buf(&mut hall_write, &SizedBuffer::from(&op::Command::Status).unwrap()).await.unwrap();
+        assert_eq!(next_command(&mut hall).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let interface = free_interface();
+        let (violations_tx, mut violations_rx) = bounded(QueueConfig::default());
+        let rate_limits = RateLimits::default().with_limit(op::Command::Message(0), RateLimit::new(0, 2)).with_violations(violations_tx);
+        let (server_tx, _server_rx) = bounded(QueueConfig::default());
+        let (_external_tx, external_rx) = bounded(QueueConfig::default());
+        tokio::spawn(async_server((), server_tx, external_rx, VServerConfig::new(interface.clone()).with_rate_limits(rate_limits.clone()), echo, |_, _, _| {}));
+
+        let (mut frames, mut write) = register(&interface, op::Flavor::Vagabond).await;
+        assert_eq!(next_command(&mut frames).await, Some(op::Command::Hello));
+        for command in [op::Command::Message(0), op::Command::Message(1), op::Command::Message(2), op::Command::Message(3), op::Command::Inventory(1)] {
+            write_buf(&mut write, &SizedBuffer::from(&command).unwrap()).await.unwrap();
+        }
+        // the bucket never refills, everything past the burst is dropped and reported once
+        for command in [op::Command::Message(0), op::Command::Message(1), op::Command::Inventory(1)] {
+            assert_eq!(next_command(&mut frames).await, Some(command));
+        }
+        assert_eq!(violations_rx.recv().await, Some(RateViolation { id: 1, command: op::Command::Message(2) }));
+        assert!(violations_rx.try_recv().is_none());
+
+        let interface = free_interface();
+        let (server_tx, _server_rx) = bounded(QueueConfig::default());
+        let (_external_tx, external_rx) = bounded(QueueConfig::default());
+        tokio::spawn(async_server((), server_tx, external_rx, VServerConfig::new(interface.clone()).with_rate_limits(rate_limits.with_policy(RateLimitPolicy::Disconnect)), echo, |_, _, _| {}));
+
+        let (mut frames, mut write) = register(&interface, op::Flavor::Vagabond).await;
+        assert_eq!(next_command(&mut frames).await, Some(op::Command::Hello));
+        for idx in 0..3 {
+            write_buf(&mut write, &SizedBuffer::from(&op::Command::Message(idx)).unwrap()).await.unwrap();
+        }
+        // the disconnect may beat the echoes out, but the frame over the limit never comes back
+        while let Some(command) = next_command(&mut frames).await {
+            assert!(matches!(command, op::Command::Message(0) | op::Command::Message(1)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_multicast() {
+        let interface = free_interface();
+        let (server_tx, _server_rx) = bounded(QueueConfig::default());
+        let (_external_tx, external_rx) = bounded(QueueConfig::default());
+        tokio::spawn(async_server((), server_tx, external_rx, interface.clone(), forward, |_, _, _| {}));
+
+        let mut gates = Vec::new();
+        for _ in 0..2 {
+            let (mut frames, write) = register(&interface, op::Flavor::Gate).await;
+            assert_eq!(next_command(&mut frames).await, Some(op::Command::Hello));
+            gates.push((frames, write));
+        }
+        let (mut hall, mut hall_write) = register(&interface, op::Flavor::Hall).await;
+        assert_eq!(next_command(&mut hall).await, Some(op::Command::Hello));
+
+        let targets = [(1, 10), (2, 20), (1, 11), (9, 90)].map(|(gate, vagabond)| op::Target { gate, vagabond });
+        let mut out = SizedBuffer::new(64);
+        out.push(&op::Route::Multi(targets.to_vec())).and_then(|_| out.push(&op::Command::Game(4))).and_then(|_| out.push(&7_u64)).unwrap();
+        write_buf(&mut hall_write, &out).await.unwrap();
+
+        // one copy per Gate, naming its own Vagabonds
+        for ((frames, _), vagabonds) in gates.iter_mut().zip([vec![10, 11], vec![20]]) {
+            let (command, mut buf) = next_message(frames).await.unwrap();
+            assert_eq!(command, op::Command::Multicast);
+            assert_eq!(buf.pull::<Vec<NodeType>>().unwrap(), vagabonds);
+            assert_eq!(buf.pull::<op::Command>().unwrap(), op::Command::Game(4));
+            assert_eq!(buf.pull::<NodeType>().unwrap(), 3);
+            assert_eq!(buf.pull::<u64>().unwrap(), 7);
+        }
+
+        let (command, mut notice) = next_message(&mut hall).await.unwrap();
+        assert_eq!(command, op::Command::Undeliverable);
+        assert_eq!(notice.pull::<op::Undeliverable>().unwrap(), op::Undeliverable { route: op::Route::Multi(vec![targets[3]]), command: op::Command::Game(4), reason: op::UndeliverableReason::Disconnected });
+    }
+}
@@ -0,0 +1,17 @@
+use tokio::signal;
+use tokio::time::Duration;
+pub use tokio_util::sync::CancellationToken;
+
+// How long connections get to flush what is already queued once shutdown starts
+pub(crate) const DRAIN_LIMIT: Duration = Duration::from_secs(5);
+
+pub fn shutdown_on_ctrl_c() -> CancellationToken {
+    let shutdown = CancellationToken::new();
+    let trigger = shutdown.clone();
+    tokio::spawn(async move {
+        if signal::ctrl_c().await.is_ok() {
+            trigger.cancel();
+        }
+    });
+    shutdown
+}
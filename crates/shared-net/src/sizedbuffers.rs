@@ -1,6 +1,7 @@
 use std::cmp::min;
 use std::mem::size_of;
 use std::string::FromUtf8Error;
+use std::sync::Arc;
 
 #[derive(Debug)]
 pub enum SizedBufferError {
@@ -8,29 +9,46 @@ pub enum SizedBufferError {
     Write(usize, usize),
     Utf8(FromUtf8Error),
     UnexpectedEnum(u8),
+    TooLarge(usize, usize),
+    Length(usize, usize),
 }
 
 pub trait Bufferable: Sized {
+    // The fewest bytes any value takes on the wire, lets a decoder reject a length prefix the buffer can't hold
+    const MIN_SIZE: usize = 0;
+
     fn push_into(&self, buf: &mut SizedBuffer) -> Result<usize, SizedBufferError>;
     fn pull_from(buf: &mut SizedBuffer) -> Result<Self, SizedBufferError>;
     fn size_in_buffer(&self) -> usize;
 }
 
+// Clones share the bytes until one of them writes, and the frame can start past `start` headroom so headers can be
+// prepended in place
 #[derive(Clone)]
 pub struct SizedBuffer {
-    pub(crate) raw: Vec<u8>,
+    raw: Arc<Vec<u8>>,
+    start: usize,
     rpos: usize,
     wpos: usize,
 }
 
-type SizeMarkerType = u16;
+type SizeMarkerType = u32;
+type LengthMarkerType = u16;
 
 impl SizedBuffer {
+    pub const MAX_SIZE: usize = 16 * 1024 * 1024;
+    pub const HEADROOM: usize = 64;
+
     pub fn new(size: usize) -> Self {
+        Self::with_headroom(0, size)
+    }
+
+    pub fn with_headroom(headroom: usize, size: usize) -> Self {
         SizedBuffer {
-            raw: vec![0; size + Self::sizesize()],
-            rpos: Self::sizesize(),
-            wpos: Self::sizesize(),
+            raw: Arc::new(vec![0; headroom + Self::sizesize() + size]),
+            start: headroom,
+            rpos: headroom + Self::sizesize(),
+            wpos: headroom + Self::sizesize(),
         }
     }
 
@@ -51,29 +69,40 @@ impl SizedBuffer {
     }
 
     pub fn rewind(&mut self) {
-        self.rpos = Self::sizesize();
+        self.rpos = self.start + Self::sizesize();
     }
 
     pub fn reset(&mut self) {
-        self.raw[..Self::sizesize()].copy_from_slice(&[0_u8; Self::sizesize()]);
-        self.wpos = Self::sizesize();
-        self.rpos = Self::sizesize();
+        self.set_size(0);
+        self.wpos = self.start + Self::sizesize();
+        self.rpos = self.start + Self::sizesize();
     }
 
     pub fn write_remain(&self) -> usize {
-        self.raw.capacity() - self.wpos
+        min(self.raw.len() - self.wpos, Self::MAX_SIZE - self.size())
     }
 
     pub fn read_remain(&self) -> usize {
-        self.size() - (self.rpos - Self::sizesize())
+        self.size() - (self.rpos - self.start - Self::sizesize())
     }
 
     pub fn size(&self) -> usize {
-        SizedBuffer::extract_size(&self.raw)
+        SizedBuffer::extract_size(&self.raw[self.start..])
+    }
+
+    // The size marker and contents, as they go on the wire
+    pub(crate) fn frame(&self) -> &[u8] {
+        &self.raw[self.start..self.start + Self::sizesize() + self.size()]
     }
 
-    pub fn set_size(&mut self, new_size: usize) {
-        self.raw[..size_of::<SizeMarkerType>()].copy_from_slice(&SizeMarkerType::to_le_bytes(new_size as SizeMarkerType));
+    // Only copies when a clone still shares the bytes
+    fn writable(&mut self) -> &mut [u8] {
+        Arc::make_mut(&mut self.raw).as_mut_slice()
+    }
+
+    fn set_size(&mut self, new_size: usize) {
+        let start = self.start;
+        self.writable()[start..start + size_of::<SizeMarkerType>()].copy_from_slice(&SizeMarkerType::to_le_bytes(new_size as SizeMarkerType));
     }
 
     fn visited(&mut self, bytes: usize) {
@@ -91,7 +120,8 @@ impl SizedBuffer {
         if len > self.write_remain() {
             return Err(SizedBufferError::Write(len, self.write_remain()));
         }
-        self.raw[self.wpos..len + self.wpos].copy_from_slice(&push[..len]);
+        let wpos = self.wpos;
+        self.writable()[wpos..len + wpos].copy_from_slice(&push[..len]);
         self.stored(len);
         Ok(len)
     }
@@ -111,6 +141,40 @@ impl SizedBuffer {
         self.push_bytes(&push.pull_remaining()?)
     }
 
+    // Makes the unread bytes the whole frame, by moving the size marker instead of the bytes
+    pub fn discard_read(&mut self) {
+        let remain = self.read_remain();
+        self.start = self.rpos - Self::sizesize();
+        self.set_size(remain);
+    }
+
+    // Discards what was read and writes `ob` in front of what remains, so a router can swap headers without copying
+    // the payload. Falls back to a copy when the read bytes and headroom can't fit `ob`.
+    pub fn prepend<T: Bufferable>(&mut self, ob: &T) -> Result<usize, SizedBufferError> {
+        let len = ob.size_in_buffer();
+        let remain = self.read_remain();
+        if len + remain > Self::MAX_SIZE {
+            return Err(SizedBufferError::Write(len, Self::MAX_SIZE - remain));
+        }
+        if len + Self::sizesize() > self.rpos {
+            let mut moved = Self::with_headroom(Self::HEADROOM, len + remain);
+            let pushed = moved.push(ob)?;
+            moved.xfer_bytes(self)?;
+            *self = moved;
+            return Ok(pushed);
+        }
+
+        let wpos = self.wpos;
+        self.rpos -= len;
+        self.start = self.rpos - Self::sizesize();
+        self.wpos = self.rpos;
+        self.set_size(0);
+        let pushed = ob.push_into(self);
+        self.wpos = wpos;
+        self.set_size(len + remain);
+        pushed
+    }
+
     pub fn push<T: Bufferable>(&mut self, ob: &T) -> Result<usize, SizedBufferError> {
         ob.push_into(self)
     }
@@ -126,11 +190,14 @@ macro_rules! bufferable_ints {
     (for $($t:ty),+) => {
         $(
         impl Bufferable for $t {
+            const MIN_SIZE: usize = size_of::<Self>();
+
             fn push_into(&self, buf: &mut SizedBuffer) -> Result<usize, SizedBufferError> {
                 if size_of::<Self>() > buf.write_remain() {
                     return Err(SizedBufferError::Write(size_of::<Self>(), buf.write_remain()));
                 }
-                buf.raw[buf.wpos..buf.wpos + size_of::<Self>()].copy_from_slice(&Self::to_le_bytes(*self));
+                let wpos = buf.wpos;
+                buf.writable()[wpos..wpos + size_of::<Self>()].copy_from_slice(&Self::to_le_bytes(*self));
                 buf.stored(size_of::<Self>());
                 Ok(size_of::<Self>())
             }
@@ -157,13 +224,22 @@ macro_rules! bufferable_ints {
 bufferable_ints!(for u16, u32, u64, u128);
 
 impl Bufferable for u8 {
+    const MIN_SIZE: usize = size_of::<Self>();
+
     fn push_into(&self, buf: &mut SizedBuffer) -> Result<usize, SizedBufferError> {
-        buf.raw[buf.wpos] = *self;
+        if size_of::<Self>() > buf.write_remain() {
+            return Err(SizedBufferError::Write(size_of::<Self>(), buf.write_remain()));
+        }
+        let wpos = buf.wpos;
+        buf.writable()[wpos] = *self;
         buf.stored(size_of::<Self>());
         Ok(size_of::<Self>())
     }
 
     fn pull_from(buf: &mut SizedBuffer) -> Result<Self, SizedBufferError> {
+        if size_of::<Self>() > buf.read_remain() {
+            return Err(SizedBufferError::Read(size_of::<Self>(), buf.read_remain()));
+        }
         let result = buf.raw[buf.rpos];
         buf.visited(size_of::<Self>());
         Ok(result)
@@ -175,6 +251,8 @@ impl Bufferable for u8 {
 }
 
 impl Bufferable for bool {
+    const MIN_SIZE: usize = size_of::<u8>();
+
     fn push_into(&self, buf: &mut SizedBuffer) -> Result<usize, SizedBufferError> {
         let as_byte = if *self {
             1u8
@@ -195,33 +273,38 @@ impl Bufferable for bool {
 }
 
 impl Bufferable for String {
+    const MIN_SIZE: usize = size_of::<LengthMarkerType>();
+
     fn push_into(&self, buf: &mut SizedBuffer) -> Result<usize, SizedBufferError> {
         let bytes = self.as_bytes();
-        let byte_len = bytes.len() as u8;
-        byte_len.push_into(buf)?;
-        let smaller = min(buf.write_remain(), byte_len as usize);
-        buf.raw[buf.wpos..smaller + buf.wpos].copy_from_slice(&bytes[..smaller]);
-        buf.stored(smaller);
-        Ok(smaller)
+        let byte_len = LengthMarkerType::try_from(bytes.len()).map_err(|_| SizedBufferError::TooLarge(bytes.len(), LengthMarkerType::MAX as usize))?;
+        if self.size_in_buffer() > buf.write_remain() {
+            return Err(SizedBufferError::Write(self.size_in_buffer(), buf.write_remain()));
+        }
+        let pushed = byte_len.push_into(buf)?;
+        Ok(pushed + buf.push_bytes(bytes)?)
     }
 
     fn pull_from(buf: &mut SizedBuffer) -> Result<Self, SizedBufferError> {
-        let len = u8::pull_from(buf)? as usize;
-        let mut slice = vec![0; len];
-        slice.copy_from_slice(&buf.raw[buf.rpos..buf.rpos + len]);
-        buf.visited(len);
-        String::from_utf8(slice).map_err(SizedBufferError::Utf8)
+        let len = LengthMarkerType::pull_from(buf)? as usize;
+        if len > buf.read_remain() {
+            return Err(SizedBufferError::Length(len, buf.read_remain()));
+        }
+        String::from_utf8(buf.pull_bytes_n(len)?).map_err(SizedBufferError::Utf8)
     }
 
     fn size_in_buffer(&self) -> usize {
-        size_of::<u8>() + self.len()
+        size_of::<LengthMarkerType>() + self.len()
     }
 }
 
 impl<T: Bufferable> Bufferable for Vec<T> {
+    const MIN_SIZE: usize = size_of::<LengthMarkerType>();
+
     fn push_into(&self, buf: &mut SizedBuffer) -> Result<usize, SizedBufferError> {
+        let len = LengthMarkerType::try_from(self.len()).map_err(|_| SizedBufferError::TooLarge(self.len(), LengthMarkerType::MAX as usize))?;
         let mut pushed = 0;
-        pushed += (self.len() as u8).push_into(buf)?;
+        pushed += len.push_into(buf)?;
         for item in self {
             pushed += item.push_into(buf)?;
         }
@@ -229,8 +312,12 @@ impl<T: Bufferable> Bufferable for Vec<T> {
     }
 
     fn pull_from(buf: &mut SizedBuffer) -> Result<Self, SizedBufferError> {
-        let len = u8::pull_from(buf)? as usize;
-        let mut vec = Vec::with_capacity(len);
+        let len = LengthMarkerType::pull_from(buf)? as usize;
+        if len.saturating_mul(T::MIN_SIZE) > buf.read_remain() {
+            return Err(SizedBufferError::Length(len, buf.read_remain()));
+        }
+        // Elements without a known minimum size may still claim more than is there, so never reserve past the remaining bytes
+        let mut vec = Vec::with_capacity(min(len, buf.read_remain()));
         for _ in 0..len {
             let item = T::pull_from(buf)?;
             vec.push(item);
@@ -239,11 +326,13 @@ impl<T: Bufferable> Bufferable for Vec<T> {
     }
 
     fn size_in_buffer(&self) -> usize {
-        size_of::<u8>() + self.iter().map(|item| item.size_in_buffer()).sum::<usize>()
+        size_of::<LengthMarkerType>() + self.iter().map(|item| item.size_in_buffer()).sum::<usize>()
     }
 }
 
 impl<T: Bufferable + Default + Copy, const N: usize> Bufferable for [T; N] {
+    const MIN_SIZE: usize = N * T::MIN_SIZE;
+
     fn push_into(&self, buf: &mut SizedBuffer) -> Result<usize, SizedBufferError> {
         let mut pushed = 0;
         for item in self {
@@ -266,6 +355,8 @@ impl<T: Bufferable + Default + Copy, const N: usize> Bufferable for [T; N] {
 }
 
 impl<T: Bufferable, U: Bufferable> Bufferable for (T, U) {
+    const MIN_SIZE: usize = T::MIN_SIZE + U::MIN_SIZE;
+
     fn push_into(&self, buf: &mut SizedBuffer) -> Result<usize, SizedBufferError> {
         let mut pushed = 0;
         pushed += self.0.push_into(buf)?;
@@ -286,7 +377,37 @@ impl<T: Bufferable, U: Bufferable> Bufferable for (T, U) {
 
 #[cfg(test)]
 mod test {
-    use super::{SizedBuffer, Bufferable, SizedBufferError};
+    use std::sync::Arc;
+
+    use super::{SizedBuffer, SizedBufferError};
+    use crate::Bufferable;
+
+    #[derive(Bufferable, Debug, PartialEq)]
+    struct TestTuple(u8, u32);
+
+    #[derive(Bufferable, Debug, PartialEq)]
+    struct TestUnit;
+
+    #[derive(Bufferable, Debug, PartialEq)]
+    enum TestEnum {
+        Unit,
+        Tuple(u8, String),
+        Named {
+            first: u16,
+            second: Vec<u8>,
+        },
+        #[bufferable(tag = 10)]
+        Tagged(TestTuple),
+        Next,
+    }
+
+    #[derive(Bufferable, Debug, PartialEq)]
+    enum TestFallback {
+        First = 2,
+        Second = 7,
+        #[bufferable(default)]
+        Unknown = 255,
+    }
 
     #[test]
     fn test_u8() -> Result<(), SizedBufferError> {
@@ -385,17 +506,53 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_prepend() -> Result<(), SizedBufferError> {
+        let mut buf = SizedBuffer::with_headroom(SizedBuffer::HEADROOM, 16);
+        buf.push(&(1_u8, 2_u16))?;
+        buf.push_bytes(&[7, 8, 9])?;
+        assert_eq!(buf.pull::<u8>()?, 1);
+
+        buf.prepend(&3_u32)?;
+        buf.prepend(&4_u8)?;
+        assert_eq!(buf.frame(), &[10, 0, 0, 0, 4, 3, 0, 0, 0, 2, 0, 7, 8, 9]);
+        assert_eq!(buf.pull::<(u8, u32)>()?, (4, 3));
+
+        let shared = buf.clone();
+        buf.discard_read();
+        assert_eq!(buf.frame(), &[5, 0, 0, 0, 2, 0, 7, 8, 9]);
+        assert_eq!(shared.size(), 10);
+
+        let mut cramped = SizedBuffer::from(&5_u8)?;
+        cramped.prepend(&6_u128)?;
+        assert_eq!((cramped.size(), cramped.pull::<u128>()?, cramped.pull::<u8>()?), (17, 6, 5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_shared() -> Result<(), SizedBufferError> {
+        let mut buf = SizedBuffer::new(8);
+        buf.push(&1_u16)?;
+        let mut other = buf.clone();
+        assert!(Arc::ptr_eq(&buf.raw, &other.raw));
+
+        other.push(&2_u16)?;
+        assert!(!Arc::ptr_eq(&buf.raw, &other.raw));
+        assert_eq!((buf.size(), other.size()), (2, 4));
+        Ok(())
+    }
+
     #[test]
     fn test_string() -> Result<(), SizedBufferError> {
         let mut source = SizedBuffer::new(64);
         let mut target = SizedBuffer::new(64);
 
         source.push(&"This is a test".to_string())?;
-        let mut total_len = "This is a test".len() + 1;
+        let mut total_len = "This is a test".len() + 2;
         assert_eq!(source.size(), total_len);
 
         source.push(&String::from("So is this"))?;
-        total_len += "So is this".len() + 1;
+        total_len += "So is this".len() + 2;
         assert_eq!(source.size(), total_len);
 
         let test1 = source.pull::<String>()?;
@@ -408,6 +565,15 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_string_too_large() {
+        let orig = "x".repeat(u16::MAX as usize + 1);
+        let mut buf = SizedBuffer::new(orig.len() + 2);
+
+        assert!(matches!(buf.push(&orig), Err(SizedBufferError::TooLarge(_, _))));
+        assert_eq!(buf.size(), 0);
+    }
+
     #[test]
     fn test_vec() -> Result<(), SizedBufferError> {
         let orig = vec![0u32, 1, 2, 3, 4, 5, 6, 7, 8, 9];
@@ -420,6 +586,50 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_hostile_lengths() -> Result<(), SizedBufferError> {
+        let mut buf = SizedBuffer::from(&(u16::MAX, 7_u32))?;
+        assert!(matches!(buf.pull::<Vec<u32>>(), Err(SizedBufferError::Length(65535, 4))));
+
+        let mut buf = SizedBuffer::from(&(3_u16, [1_u8; 5]))?;
+        assert!(matches!(buf.pull::<Vec<(u8, u8)>>(), Err(SizedBufferError::Length(3, 5))));
+
+        let mut buf = SizedBuffer::from(&(u16::MAX, 1_u8))?;
+        assert!(matches!(buf.pull::<Vec<TestUnit>>(), Ok(units) if units.len() == 65535));
+
+        let mut buf = SizedBuffer::from(&(40_u16, *b"short"))?;
+        assert!(matches!(buf.pull::<String>(), Err(SizedBufferError::Length(40, 5))));
+
+        let mut buf = SizedBuffer::new(0);
+        assert!(matches!(buf.pull::<u8>(), Err(SizedBufferError::Read(1, 0))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_vec_large() -> Result<(), SizedBufferError> {
+        let orig = (0..1000u32).collect::<Vec<_>>();
+
+        let mut buf = SizedBuffer::from(&orig)?;
+        let result = Vec::<u32>::pull_from(&mut buf)?;
+
+        assert_eq!(orig, result);
+
+        let too_large = vec![0u8; u16::MAX as usize + 1];
+        assert!(matches!(SizedBuffer::from(&too_large), Err(SizedBufferError::TooLarge(_, _))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_size() -> Result<(), SizedBufferError> {
+        let mut buf = SizedBuffer::new(SizedBuffer::MAX_SIZE + 1);
+        buf.push_bytes(&vec![0; SizedBuffer::MAX_SIZE])?;
+
+        assert_eq!(buf.size(), SizedBuffer::MAX_SIZE);
+        assert!(matches!(buf.push(&0u8), Err(SizedBufferError::Write(1, 0))));
+        assert_eq!(buf.size(), SizedBuffer::MAX_SIZE);
+        Ok(())
+    }
+
     #[test]
     fn test_array() -> Result<(), SizedBufferError> {
         type TestArray = [u32; 10];
@@ -474,6 +684,57 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_derive_struct() -> Result<(), SizedBufferError> {
+        let orig = (TestTuple(8, 32), TestUnit);
+
+        let mut buf = SizedBuffer::from(&orig)?;
+        let result = buf.pull::<(TestTuple, TestUnit)>()?;
+
+        assert_eq!(buf.size(), 5);
+        assert_eq!(buf.size(), orig.size_in_buffer());
+        assert_eq!(orig, result);
+        Ok(())
+    }
+
+    #[test]
+    fn test_derive_enum() -> Result<(), SizedBufferError> {
+        let orig = vec![
+            //
+            TestEnum::Unit,
+            TestEnum::Tuple(1, "two".to_string()),
+            TestEnum::Named {
+                first: 3,
+                second: vec![4, 5],
+            },
+            TestEnum::Tagged(TestTuple(6, 7)),
+            TestEnum::Next,
+        ];
+
+        let mut buf = SizedBuffer::from(&orig)?;
+        let result = buf.pull::<Vec<TestEnum>>()?;
+
+        assert_eq!(buf.size(), orig.size_in_buffer());
+        assert_eq!(orig, result);
+
+        let mut buf = SizedBuffer::from(&TestEnum::Next)?;
+        assert_eq!(buf.pull::<u8>()?, 11);
+        Ok(())
+    }
+
+    #[test]
+    fn test_derive_enum_tags() -> Result<(), SizedBufferError> {
+        let mut buf = SizedBuffer::from(&TestFallback::Second)?;
+        assert_eq!(buf.pull::<u8>()?, 7);
+
+        let mut buf = SizedBuffer::from(&3u8)?;
+        assert_eq!(buf.pull::<TestFallback>()?, TestFallback::Unknown);
+
+        let mut buf = SizedBuffer::from(&3u8)?;
+        assert!(matches!(buf.pull::<TestEnum>(), Err(SizedBufferError::UnexpectedEnum(3))));
+        Ok(())
+    }
+
     #[test]
     fn test_tuple_arrays() -> Result<(), SizedBufferError> {
         type TestTuple = ([u128; 4], Vec<bool>);
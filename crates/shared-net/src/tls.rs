@@ -0,0 +1,129 @@
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::pem::{self, PemObject};
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::{self, RootCertStore};
+
+pub use tokio_rustls::rustls::{ClientConfig, ServerConfig};
+
+#[derive(Debug)]
+pub enum TlsError {
+    Io(io::Error),
+    Pem(pem::Error),
+    Rustls(rustls::Error),
+    NoCertificates,
+}
+
+pub fn load_server_config(cert_path: &Path, key_path: &Path) -> Result<Arc<ServerConfig>, TlsError> {
+    let certs = load_certs(cert_path)?;
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(TlsError::Pem)?;
+
+    let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(TlsError::Rustls)?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(TlsError::Rustls)?;
+
+    Ok(Arc::new(config))
+}
+
+pub fn load_client_config(ca_path: &Path) -> Result<Arc<ClientConfig>, TlsError> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(ca_path)? {
+        roots.add(cert).map_err(TlsError::Rustls)?;
+    }
+
+    let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(TlsError::Rustls)?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    Ok(Arc::new(config))
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let certs = CertificateDer::pem_file_iter(path).map_err(TlsError::Pem)?.collect::<Result<Vec<_>, _>>().map_err(TlsError::Pem)?;
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificates);
+    }
+    Ok(certs)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::path::PathBuf;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, duplex};
+    use tokio_rustls::rustls::pki_types::ServerName;
+    use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+    use super::{TlsError, load_client_config, load_server_config};
+
+    pub(crate) struct TestCerts {
+        pub(crate) cert: PathBuf,
+        pub(crate) key: PathBuf,
+    }
+
+    impl TestCerts {
+        pub(crate) fn generate(name: &str) -> Self {
+            let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string(), "127.0.0.1".to_string(), "::1".to_string()]).unwrap();
+            let dir = std::env::temp_dir().join(format!("shared-net-{}-{}", name, std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+
+            let cert = dir.join("cert.pem");
+            let key = dir.join("key.pem");
+            std::fs::write(&cert, certified.cert.pem()).unwrap();
+            std::fs::write(&key, certified.signing_key.serialize_pem()).unwrap();
+            Self {
+                cert,
+                key,
+            }
+        }
+    }
+
+    impl Drop for TestCerts {
+        fn drop(&mut self) {
+            if let Some(dir) = self.cert.parent() {
+                let _ = std::fs::remove_dir_all(dir);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tls_handshake() -> Result<(), TlsError> {
+        let certs = TestCerts::generate("handshake");
+        let acceptor = TlsAcceptor::from(load_server_config(&certs.cert, &certs.key)?);
+        let connector = TlsConnector::from(load_client_config(&certs.cert)?);
+
+        let (client, server) = duplex(4096);
+        let server = tokio::spawn(async move {
+            let mut stream = acceptor.accept(server).await.unwrap();
+            let mut buf = [0_u8; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            buf
+        });
+
+        let name = ServerName::try_from("localhost").unwrap();
+        let mut stream = connector.connect(name, client).await.map_err(TlsError::Io)?;
+        stream.write_all(&[1, 2, 3, 4]).await.map_err(TlsError::Io)?;
+        stream.flush().await.map_err(TlsError::Io)?;
+
+        assert_eq!(server.await.unwrap(), [1, 2, 3, 4]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let certs = TestCerts::generate("errors");
+        let missing = certs.cert.with_file_name("missing.pem");
+
+        assert!(load_client_config(&missing).is_err());
+        assert!(load_server_config(&certs.cert, &missing).is_err());
+        assert!(matches!(load_server_config(&certs.key, &certs.key), Err(TlsError::NoCertificates)));
+    }
+}
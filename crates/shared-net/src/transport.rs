@@ -0,0 +1,135 @@
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+use crate::memory::{self, MemoryListener};
+use crate::tls;
+#[cfg(unix)]
+use crate::unix::{self, UnixSocketListener};
+
+pub(crate) trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S> AsyncStream for S where S: AsyncRead + AsyncWrite + Unpin + Send {}
+
+pub(crate) type BoxedStream = Box<dyn AsyncStream>;
+
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    Memory(MemoryListener),
+    #[cfg(unix)]
+    Unix(UnixSocketListener),
+}
+
+impl Listener {
+    pub(crate) async fn bind(interface: &str) -> Result<Self, Error> {
+        #[cfg(unix)]
+        if let Some(path) = interface.strip_prefix(unix::PREFIX) {
+            return Ok(Listener::Unix(UnixSocketListener::bind(path).await?));
+        }
+        match interface.strip_prefix(memory::PREFIX) {
+            Some(name) => Ok(Listener::Memory(MemoryListener::bind(name)?)),
+            None => Ok(Listener::Tcp(TcpListener::bind(interface).await?)),
+        }
+    }
+
+    pub(crate) async fn accept(&mut self) -> Result<(BoxedStream, String), Error> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, peer_addr) = listener.accept().await?;
+                Ok((Box::new(stream), peer_addr.to_string()))
+            }
+            Listener::Memory(listener) => Ok((Box::new(listener.accept().await?), memory::PREFIX.to_string())),
+            #[cfg(unix)]
+            Listener::Unix(listener) => Ok((Box::new(listener.accept().await?), unix::PREFIX.to_string())),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) enum Endpoint {
+    Tcp(SocketAddr),
+    Memory(String),
+    #[cfg(unix)]
+    Unix(String),
+}
+
+impl Endpoint {
+    pub(crate) fn resolve(interface: &str) -> Result<Self, Error> {
+        #[cfg(unix)]
+        if let Some(path) = interface.strip_prefix(unix::PREFIX) {
+            return Ok(Endpoint::Unix(path.to_string()));
+        }
+        match interface.strip_prefix(memory::PREFIX) {
+            Some(name) => Ok(Endpoint::Memory(name.to_string())),
+            None => interface.to_socket_addrs()?.next().map(Endpoint::Tcp).ok_or_else(|| Error::from(ErrorKind::AddrNotAvailable)),
+        }
+    }
+
+    pub(crate) async fn open(&self) -> Result<BoxedStream, Error> {
+        match self {
+            Endpoint::Tcp(addr) => Ok(Box::new(TcpStream::connect(addr).await?)),
+            Endpoint::Memory(name) => Ok(Box::new(memory::connect(name)?)),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => Ok(Box::new(unix::connect(path).await?)),
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Tcp(addr) => write!(f, "{addr}"),
+            Endpoint::Memory(name) => write!(f, "{}{name}", memory::PREFIX),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => write!(f, "{}{path}", unix::PREFIX),
+        }
+    }
+}
+
+pub(crate) async fn accept(stream: BoxedStream, tls: Option<Arc<tls::ServerConfig>>) -> Result<BoxedStream, Error> {
+    match tls {
+        Some(tls) => Ok(Box::new(TlsAcceptor::from(tls).accept(stream).await?)),
+        None => Ok(Box::new(stream)),
+    }
+}
+
+pub(crate) async fn connect(stream: BoxedStream, tls: Option<Arc<tls::ClientConfig>>, server_name: &str) -> Result<BoxedStream, Error> {
+    match tls {
+        Some(tls) => {
+            let server_name = ServerName::try_from(server_name.to_string()).map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+            Ok(Box::new(TlsConnector::from(tls).connect(server_name, stream).await?))
+        }
+        None => Ok(Box::new(stream)),
+    }
+}
+
+pub(crate) fn host(interface: &str) -> &str {
+    #[cfg(unix)]
+    if interface.starts_with(unix::PREFIX) {
+        return "localhost";
+    }
+    let interface = interface.strip_prefix(memory::PREFIX).unwrap_or(interface);
+    let host = interface.rsplit_once(':').map_or(interface, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+#[cfg(test)]
+mod test {
+    use super::host;
+
+    #[test]
+    fn test_host() {
+        assert_eq!(host("localhost:23450"), "localhost");
+        assert_eq!(host("[::1]:23450"), "::1");
+        assert_eq!(host("127.0.0.1:23450"), "127.0.0.1");
+        assert_eq!(host("localhost"), "localhost");
+        assert_eq!(host("mem:courtyard"), "courtyard");
+        assert_eq!(host("unix:/run/courtyard.sock"), "localhost");
+    }
+}
@@ -5,4 +5,5 @@ pub type GameIdType = u64;
 pub type PartType = u64;
 pub type SeedType = u64;
 pub type TimestampType = u64;
-pub type NodeType = u8;
+pub type NodeType = u16;
+pub type CorrelationType = u32;
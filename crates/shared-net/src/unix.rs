@@ -0,0 +1,108 @@
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use tokio::net::{UnixListener, UnixStream};
+
+// Interfaces starting with this are a socket path, for services sharing a host with the server
+pub(crate) const PREFIX: &str = "unix:";
+
+pub(crate) struct UnixSocketListener {
+    path: PathBuf,
+    listener: UnixListener,
+}
+
+impl UnixSocketListener {
+    pub(crate) async fn bind(path: &str) -> Result<Self, Error> {
+        let path = PathBuf::from(path);
+        let listener = match UnixListener::bind(&path) {
+            Err(err) if err.kind() == ErrorKind::AddrInUse && is_stale(&path).await => {
+                std::fs::remove_file(&path)?;
+                UnixListener::bind(&path)?
+            }
+            result => result?,
+        };
+        Ok(Self {
+            path,
+            listener,
+        })
+    }
+
+    pub(crate) async fn accept(&self) -> Result<UnixStream, Error> {
+        let (stream, _) = self.listener.accept().await?;
+        Ok(stream)
+    }
+}
+
+impl Drop for UnixSocketListener {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// A socket file left behind by a server that didn't shut down cleanly refuses connections
+async fn is_stale(path: &Path) -> bool {
+    matches!(UnixStream::connect(path).await, Err(err) if err.kind() == ErrorKind::ConnectionRefused)
+}
+
+pub(crate) async fn connect(path: &str) -> Result<UnixStream, Error> {
+    UnixStream::connect(path).await
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use tokio::sync::mpsc::{self, UnboundedSender};
+    use tokio::time::{Duration, timeout};
+
+    use super::{UnixSocketListener, connect};
+    use crate::channel::{QueueConfig, VSender, bounded};
+    use crate::{IdMessage, RoutedMessage, SizedBuffer, VClientEvent, VClientMode, async_client, async_server, op};
+
+    fn socket(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("shared-net-{}-{}.sock", name, std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn test_bind() {
+        let path = socket("bind");
+        let name = path.to_str().unwrap();
+
+        // what a crashed server leaves behind
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(path.exists() && connect(name).await.is_err());
+
+        let listener = UnixSocketListener::bind(name).await.unwrap();
+        assert!(UnixSocketListener::bind(name).await.is_err());
+        assert!(connect(name).await.is_ok());
+
+        drop(listener);
+        assert!(!path.exists());
+    }
+
+    fn received(context: UnboundedSender<op::Command>, _tx: VSender<RoutedMessage>, mut msg: IdMessage) -> bool {
+        msg.buf.pull::<op::Command>().is_ok_and(|command| context.send(command).is_ok())
+    }
+
+    fn connected(context: UnboundedSender<VClientEvent>, _tx: VSender<RoutedMessage>, event: VClientEvent) {
+        let _ = context.send(event);
+    }
+
+    #[tokio::test]
+    async fn test_register_over_socket() {
+        let interface = format!("unix:{}", socket("register").display());
+        let (commands_tx, mut commands_rx) = mpsc::unbounded_channel();
+        let (server_tx, _server_rx) = bounded(QueueConfig::default());
+        let (_external_tx, external_rx) = bounded(QueueConfig::default());
+        tokio::spawn(async_server(commands_tx, server_tx, external_rx, interface.clone(), received, |_, _, _| {}));
+
+        let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+        let (client_tx, client_rx) = bounded(QueueConfig::default());
+        let sender = client_tx.clone();
+        tokio::spawn(async_client(events_tx, op::Flavor::Hall, client_tx, client_rx, interface, |_: UnboundedSender<VClientEvent>, _, _| VClientMode::Continue, connected));
+        assert_eq!(timeout(Duration::from_secs(10), events_rx.recv()).await.unwrap(), Some(VClientEvent::Connected));
+
+        sender.send(RoutedMessage::local(SizedBuffer::from(&op::Command::Game(3)).unwrap())).unwrap();
+        assert_eq!(timeout(Duration::from_secs(10), commands_rx.recv()).await.unwrap(), Some(op::Command::Game(3)));
+    }
+}
@@ -1,16 +1,22 @@
 use std::io::Error;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use tokio::io::{AsyncWrite, AsyncWriteExt};
 
-use crate::SizedBuffer;
+use crate::{SizedBuffer, TimestampType};
 
 pub(crate) async fn write_buf<T>(stream: &mut T, buf: &SizedBuffer) -> Result<usize, Error>
 where
     T: Unpin + AsyncWrite,
 {
-    let len = buf.size() + SizedBuffer::sizesize();
-    let amt = stream.write(&buf.raw[..len]).await?;
-    Ok(amt)
+    let frame = buf.frame();
+    stream.write_all(frame).await?;
+    Ok(frame.len())
+}
+
+// Microseconds since the epoch
+pub(crate) fn now() -> TimestampType {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_micros() as TimestampType).unwrap_or_default()
 }
 
 #[cfg(test)]
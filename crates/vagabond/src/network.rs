@@ -1,2 +1,25 @@
+use std::path::Path;
+
+use bevy::prelude::error;
+
+use shared_net::VClientConfig;
+
 pub(crate) mod client_drawbridge;
 pub(crate) mod client_gate;
+
+pub(crate) fn client_config(iface: String) -> Option<VClientConfig> {
+    let mut config = VClientConfig::new(iface);
+    if let Ok(ca) = std::env::var("VAGABOND_TLS_CA") {
+        match shared_net::tls::load_client_config(Path::new(&ca)) {
+            Ok(tls) => config = config.with_tls(tls),
+            Err(err) => {
+                error!("[Network] Unable to load TLS CA {ca}: {err:?}");
+                return None;
+            }
+        }
+    }
+    if let Ok(server_name) = std::env::var("VAGABOND_TLS_NAME") {
+        config = config.with_server_name(server_name);
+    }
+    Some(config)
+}
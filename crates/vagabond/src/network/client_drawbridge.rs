@@ -3,12 +3,14 @@ use std::net::IpAddr;
 use bevy::prelude::Resource;
 use fasthash::farm::fingerprint128;
 use tokio::runtime::Runtime;
-use tokio::sync::mpsc;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio::task::JoinHandle;
 
+use shared_net::channel::{QueueConfig, VReceiver, VSender, bounded};
 use shared_net::{AuthType, RoutedMessage, SizedBuffer, SizedBufferError, VClientMode, op};
 
+use crate::network;
+
 pub(crate) struct AuthInfo {
     pub(crate) ip: IpAddr,
     pub(crate) port: u16,
@@ -19,18 +21,19 @@ pub(crate) struct AuthInfo {
 pub(crate) struct DrawbridgeIFace {
     pub(crate) username: String,
     pub(crate) password: String,
-    pub(crate) dtx: UnboundedSender<RoutedMessage>,
-    pub(crate) drx: UnboundedReceiver<AuthInfo>,
+    pub(crate) dtx: VSender<RoutedMessage>,
+    pub(crate) drx: UnboundedReceiver<Result<AuthInfo, op::UndeliverableReason>>,
 }
 
 #[derive(Clone)]
 pub(crate) struct DrawbridgeClient {
-    pub(crate) auth_tx: UnboundedSender<AuthInfo>,
+    pub(crate) auth_tx: UnboundedSender<Result<AuthInfo, op::UndeliverableReason>>,
 }
 
 impl DrawbridgeClient {
-    pub(crate) fn start(iface: String, auth_tx: UnboundedSender<AuthInfo>, rx: UnboundedReceiver<RoutedMessage>, runtime: &Runtime) -> Option<JoinHandle<Result<(), ()>>> {
-        let (dummy_tx, _) = mpsc::unbounded_channel();
+    pub(crate) fn start(iface: String, auth_tx: UnboundedSender<Result<AuthInfo, op::UndeliverableReason>>, rx: VReceiver<RoutedMessage>, runtime: &Runtime) -> Option<JoinHandle<Result<(), ()>>> {
+        let config = network::client_config(iface)?;
+        let (dummy_tx, _) = bounded(QueueConfig::default());
         Some(runtime.spawn(shared_net::async_client(
             DrawbridgeClient {
                 auth_tx,
@@ -38,15 +41,17 @@ impl DrawbridgeClient {
             op::Flavor::Vagabond,
             dummy_tx,
             rx,
-            iface,
+            config,
             process_drawbridge,
+            |_, _, _| {},
         )))
     }
 }
 
-fn process_drawbridge(context: DrawbridgeClient, _tx: UnboundedSender<RoutedMessage>, mut buf: SizedBuffer) -> VClientMode {
+fn process_drawbridge(context: DrawbridgeClient, _tx: VSender<RoutedMessage>, mut buf: SizedBuffer) -> VClientMode {
     match buf.pull::<op::Command>() {
         Ok(op::Command::Authorize) => recv_authorize(context, buf).unwrap_or(VClientMode::Shutdown),
+        Ok(op::Command::Undeliverable) => recv_undeliverable(context, buf).unwrap_or(VClientMode::Continue),
         _ => VClientMode::Continue,
     }
 }
@@ -64,21 +69,26 @@ fn recv_authorize(context: DrawbridgeClient, mut buf: SizedBuffer) -> Result<VCl
         port,
         auth,
     };
-    let _ = context.auth_tx.send(auth_info);
+    let _ = context.auth_tx.send(Ok(auth_info));
 
     Ok(VClientMode::Shutdown)
 }
 
-pub(crate) fn send_authorize(tx: &UnboundedSender<RoutedMessage>, user: String, pass: String) {
+// Login could not reach Lookout, stay connected so it can be tried again
+fn recv_undeliverable(context: DrawbridgeClient, mut buf: SizedBuffer) -> Result<VClientMode, SizedBufferError> {
+    let notice = buf.pull::<op::Undeliverable>()?;
+    let _ = context.auth_tx.send(Err(notice.reason));
+
+    Ok(VClientMode::Continue)
+}
+
+pub(crate) fn send_authorize(tx: &VSender<RoutedMessage>, user: String, pass: String) {
     let mut out = SizedBuffer::new(64);
     let _ = out.push(&op::Command::Authorize);
     let _ = out.push(&fingerprint128(user.as_bytes()));
     let _ = out.push(&fingerprint128(pass.as_bytes()));
 
-    let msg = RoutedMessage {
-        route: op::Route::Local,
-        buf: out,
-    };
+    let msg = RoutedMessage::new(op::Route::Local, out);
 
     let _ = tx.send(msg);
 }
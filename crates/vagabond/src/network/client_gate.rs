@@ -2,7 +2,6 @@ use std::collections::HashMap;
 
 use bevy::prelude::Resource;
 use tokio::runtime::Runtime;
-use tokio::sync::mpsc;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio::task::JoinHandle;
 
@@ -10,10 +9,13 @@ use archive_lib::core::ArchiveSubCommand;
 use forum_lib::core::ForumSubCommand;
 use hall_lib::core::{AttributeKind, GameSubCommand, MissionNodeIntent, PickedCardTarget};
 use hall_lib::message::*;
+use shared_net::channel::{QueueConfig, VReceiver, VSender, bounded};
 use shared_net::op::SubCommandType;
 use shared_net::{AuthType, Bufferable, GameIdType, PartType};
 use shared_net::{RoutedMessage, SizedBuffer, SizedBufferError, VClientMode, op};
 
+use crate::network;
+
 pub(crate) enum GateCommand {
     Hello,
     GameActivate(Box<GameActivateResponse>),
@@ -37,7 +39,7 @@ pub(crate) enum GateCommand {
 pub(crate) struct GateIFace {
     pub(crate) auth: AuthType,
     pub(crate) game_id: GameIdType,
-    pub(crate) gtx: UnboundedSender<RoutedMessage>,
+    pub(crate) gtx: VSender<RoutedMessage>,
     pub(crate) grx: UnboundedReceiver<GateCommand>,
 }
 
@@ -47,12 +49,13 @@ pub(crate) struct GateClient {
 }
 
 impl GateClient {
-    pub(crate) fn start(iface: String, tx: UnboundedSender<GateCommand>, rx: UnboundedReceiver<RoutedMessage>, runtime: &Runtime) -> Option<JoinHandle<Result<(), ()>>> {
-        let (dummy_tx, _) = mpsc::unbounded_channel();
+    pub(crate) fn start(iface: String, tx: UnboundedSender<GateCommand>, rx: VReceiver<RoutedMessage>, runtime: &Runtime) -> Option<JoinHandle<Result<(), ()>>> {
+        let config = network::client_config(iface)?;
+        let (dummy_tx, _) = bounded(QueueConfig::default());
         let gate_client = GateClient {
             tx,
         };
-        Some(runtime.spawn(shared_net::async_client(gate_client, op::Flavor::Vagabond, dummy_tx, rx, iface, process_gate)))
+        Some(runtime.spawn(shared_net::async_client(gate_client, op::Flavor::Vagabond, dummy_tx, rx, config, process_gate, |_, _, _| {})))
     }
 }
 
@@ -90,14 +93,15 @@ fn subprocess_game(subcommand: SubCommandType, context: GateClient, mut buf: Siz
     }
 }
 
-fn process_gate(context: GateClient, _tx: UnboundedSender<RoutedMessage>, mut buf: SizedBuffer) -> VClientMode {
+fn process_gate(context: GateClient, _tx: VSender<RoutedMessage>, mut buf: SizedBuffer) -> VClientMode {
     if let Ok(command) = buf.pull::<op::Command>() {
         match command {
             op::Command::Hello => recv_hello(context),
             op::Command::Message(sub) => subprocess_message(sub, buf),
             op::Command::Inventory(sub) => subprocess_inventory(sub, buf),
             op::Command::Game(sub) => subprocess_game(sub, context, buf),
-            op::Command::NoOp | op::Command::Register | op::Command::Authorize | op::Command::UserAttr => Ok(VClientMode::Continue),
+            op::Command::Undeliverable => recv_undeliverable(&mut buf),
+            op::Command::NoOp | op::Command::Register | op::Command::Authorize | op::Command::UserAttr | op::Command::Reject | op::Command::Ping | op::Command::Pong | op::Command::Request(_) | op::Command::Reply(_) | op::Command::Challenge | op::Command::Advertise | op::Command::Forward | op::Command::Status | op::Command::Multicast => Ok(VClientMode::Continue),
         }
         .unwrap_or(VClientMode::Continue)
     } else {
@@ -110,6 +114,12 @@ fn recv_hello(context: GateClient) -> Result<VClientMode, SizedBufferError> {
     Ok(VClientMode::Continue)
 }
 
+fn recv_undeliverable(buf: &mut SizedBuffer) -> Result<VClientMode, SizedBufferError> {
+    let notice = buf.pull::<op::Undeliverable>()?;
+    println!("[Undeliverable] {:?} to {:?}: {}", notice.command, notice.route, notice.reason);
+    Ok(VClientMode::Continue)
+}
+
 fn recv_chat(buf: &mut SizedBuffer) -> Result<VClientMode, SizedBufferError> {
     let name = buf.pull::<String>()?;
     if let Ok(msg) = String::from_utf8(buf.pull_remaining()?) {
@@ -151,10 +161,7 @@ impl GateIFace {
         let _ = out.push(&self.auth);
         let _ = out.push(&request);
 
-        let result = self.gtx.send(RoutedMessage {
-            route: op::Route::Local,
-            buf: out,
-        });
+        let result = self.gtx.send(RoutedMessage::new(op::Route::Local, out));
 
         result.is_ok()
     }
@@ -230,10 +237,7 @@ impl GateIFace {
         let _ = out.push(&self.auth);
         let _ = out.push(&123_u8);
 
-        let _ = self.gtx.send(RoutedMessage {
-            route: op::Route::Local,
-            buf: out,
-        });
+        let _ = self.gtx.send(RoutedMessage::new(op::Route::Local, out));
     }
 
     #[allow(dead_code)]
@@ -243,10 +247,7 @@ impl GateIFace {
         let _ = out.push(&self.auth);
         let _ = out.push(&123_u8);
 
-        let _ = self.gtx.send(RoutedMessage {
-            route: op::Route::Local,
-            buf: out,
-        });
+        let _ = self.gtx.send(RoutedMessage::new(op::Route::Local, out));
     }
 
     #[allow(dead_code)]
@@ -256,10 +257,7 @@ impl GateIFace {
         let _ = out.push(&self.auth);
         let _ = out.push(&msg.to_string());
 
-        let _ = self.gtx.send(RoutedMessage {
-            route: op::Route::Local,
-            buf: out,
-        });
+        let _ = self.gtx.send(RoutedMessage::new(op::Route::Local, out));
     }
 
     #[allow(dead_code)]
@@ -270,9 +268,6 @@ impl GateIFace {
         let _ = out.push(&who.to_string());
         let _ = out.push(&msg.to_string());
 
-        let _ = self.gtx.send(RoutedMessage {
-            route: op::Route::Local,
-            buf: out,
-        });
+        let _ = self.gtx.send(RoutedMessage::new(op::Route::Local, out));
     }
 }
@@ -3,6 +3,7 @@ use bevy_simple_text_input::{TextInputCursorPos, TextInputInactive, TextInputSub
 use tokio::sync::mpsc;
 
 use shared_net::AuthType;
+use shared_net::channel::{QueueConfig, bounded};
 
 use crate::manager::{AtlasManager, NetworkManager, ScreenLayoutManager, ScreenLayoutManagerParams};
 use crate::network::client_drawbridge;
@@ -55,7 +56,7 @@ fn drawbridge_enter(
     mut commands: Commands,
     mut net: ResMut<NetworkManager>,
 ) {
-    let (to_drawbridge_tx, to_drawbridge_rx) = mpsc::unbounded_channel();
+    let (to_drawbridge_tx, to_drawbridge_rx) = bounded(QueueConfig::default());
     let (from_drawbridge_tx, from_drawbridge_rx) = mpsc::unbounded_channel();
 
     let username = std::env::var("VAGABOND_USERNAME").unwrap_or("".to_string());
@@ -139,12 +140,21 @@ fn drawbridge_update(
     mut drawbridge: ResMut<DrawbridgeIFace>,
     connected_q: Query<Entity, With<ConnectedIcon>>,
 ) {
-    if let Ok(auth_info) = drawbridge.drx.try_recv() {
-        if let Ok(connected) = connected_q.single() {
-            commands.entity(connected).trigger(|e| SetColorEvent::new(e, bevy::color::palettes::css::YELLOW));
+    match drawbridge.drx.try_recv() {
+        Ok(Ok(auth_info)) => {
+            if let Ok(connected) = connected_q.single() {
+                commands.entity(connected).trigger(|e| SetColorEvent::new(e, bevy::color::palettes::css::YELLOW));
+            }
+            commands.insert_resource(DrawbridgeHandoff::new(auth_info));
+            app_state.set(AppState::LoginGate);
+        }
+        Ok(Err(reason)) => {
+            warn!("[Login] Unable to authorize: {reason}");
+            if let Ok(connected) = connected_q.single() {
+                commands.entity(connected).trigger(|e| SetColorEvent::new(e, bevy::color::palettes::css::RED));
+            }
         }
-        commands.insert_resource(DrawbridgeHandoff::new(auth_info));
-        app_state.set(AppState::LoginGate);
+        Err(_) => {}
     }
 }
 
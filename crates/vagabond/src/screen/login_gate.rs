@@ -3,6 +3,8 @@ use std::mem::discriminant;
 use bevy::prelude::*;
 use tokio::sync::mpsc;
 
+use shared_net::channel::{QueueConfig, bounded};
+
 use crate::manager::{NetworkManager, ScreenLayoutManager};
 use crate::network::client_gate::{GateClient, GateCommand, GateIFace};
 use crate::screen::login_drawbridge::DrawbridgeHandoff;
@@ -34,7 +36,7 @@ fn gate_enter(
     handoff: Res<DrawbridgeHandoff>,
     mut net: ResMut<NetworkManager>,
 ) {
-    let (gtx, to_gate_rx) = mpsc::unbounded_channel();
+    let (gtx, to_gate_rx) = bounded(QueueConfig::default());
     let (from_gate_tx, grx) = mpsc::unbounded_channel();
     let gate = GateIFace {
         game_id: 0,